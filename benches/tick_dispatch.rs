@@ -0,0 +1,130 @@
+//! Throughput of tick frames from the socket to `Ticker` watchers, over the frames recorded in
+//! `data/ticks.log` for request ids 1 to 50.
+//!
+//! Baseline, with frames split into a `Vec<String>` before parsing, against frames parsed from the
+//! received bytes (same machine, `--warm-up-time 2 --measurement-time 8`):
+//!
+//! | input       | `Vec<String>`              | bytes                      |
+//! |-------------|----------------------------|----------------------------|
+//! | all_ticks   | 494 µs (2.02 Melem/s)      | 423 µs (2.36 Melem/s)      |
+//! | price_ticks | 248 µs (1.82 Melem/s)      | 207 µs (2.18 Melem/s)      |
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rs_ib_api::ib_client::{IBClient, ConnectOptions};
+use rs_ib_api::ib_contract::Contract;
+use rs_ib_api::ib_enums::*;
+use rs_ib_api::recorder::{self, Direction};
+use rs_ib_api::ticker::Ticker;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, WriteHalf};
+use tokio::runtime::Runtime;
+
+const N_TICKERS: i32 = 50;
+
+fn frame(fields: &[&str]) -> Vec<u8> {
+    let mut msg = fields.join("\0");
+    msg.push('\0');
+    length_prefixed(msg.as_bytes())
+}
+
+fn length_prefixed(payload: &[u8]) -> Vec<u8> {
+    let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(payload);
+    frame
+}
+
+async fn read_msg(stream: &mut DuplexStream) -> Vec<String> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).await.unwrap();
+    let mut msg = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut msg).await.unwrap();
+    String::from_utf8(msg).unwrap().split('\0').map(str::to_string).collect()
+}
+
+struct Session {
+    _client: IBClient,
+    tickers: Vec<Ticker>,
+    server: WriteHalf<DuplexStream>
+}
+
+//connects over a duplex stream and subscribes to market data with ids 1 to N_TICKERS
+async fn subscribe() -> Session {
+    let (client_end, mut server) = tokio::io::duplex(1 << 20);
+    let gateway = tokio::spawn(async move {
+        let mut prefix = [0u8; 4];
+        server.read_exact(&mut prefix).await.unwrap();
+        read_msg(&mut server).await;
+        server.write_all(&frame(&[&constants::MIN_SERVER_VER_SIZE_RULES.to_string(), "20210301 14:30:00 UTC"])).await.unwrap();
+        read_msg(&mut server).await;
+        server.write_all(&frame(&["9", "1", "1"])).await.unwrap();
+        server
+    });
+    let options = ConnectOptions::new(4002, 1)
+        .subscribe_account_updates(false)
+        .request_next_order_id(false)
+        .rate_limit(None);
+    let client = IBClient::connect_with_stream(client_end, options).await.unwrap();
+    let mut server = gateway.await.unwrap();
+    let contract = Contract {
+        symbol: Some("AAPL".to_string()),
+        exchange: Some("SMART".to_string()),
+        sec_type: Some(SecType::Stock),
+        currency: Some("USD".to_string()),
+        ..Default::default()
+    };
+    let mut tickers = Vec::new();
+    for _ in 0..N_TICKERS {
+        let (ticker, _) = tokio::join!(client.req_market_data(&contract, false, false, None), async {
+            loop {
+                let msg = read_msg(&mut server).await;
+                if msg[0] == "1" {
+                    server.write_all(&frame(&["1", "6", &msg[2], "1", "1.0", "100", "0"])).await.unwrap();
+                    break;
+                }
+            }
+        });
+        tickers.push(ticker.unwrap());
+    }
+    let (mut server_rx, server) = tokio::io::split(server);
+    tokio::spawn(async move {
+        let _ = tokio::io::copy(&mut server_rx, &mut tokio::io::sink()).await;
+    });
+    Session{_client: client, tickers, server}
+}
+
+fn recorded_frames(filter: impl Fn(&[u8]) -> bool) -> (usize, Vec<u8>) {
+    let recording = recorder::read_recording(concat!(env!("CARGO_MANIFEST_DIR"), "/benches/data/ticks.log")).unwrap();
+    let frames: Vec<Vec<u8>> = recording.into_iter()
+        .filter(|frame| frame.direction == Direction::In && filter(&frame.payload))
+        .map(|frame| length_prefixed(&frame.payload))
+        .collect();
+    (frames.len(), frames.concat())
+}
+
+fn tick_dispatch(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut session = rt.block_on(subscribe());
+    let inputs = [
+        ("all_ticks", recorded_frames(|_| true)),
+        ("price_ticks", recorded_frames(|payload| payload.starts_with(b"1\0")))
+    ];
+    let mut group = c.benchmark_group("tick_dispatch");
+    let mut sentinel = 1e6;
+    for (name, (n_frames, batch)) in inputs.iter() {
+        group.throughput(Throughput::Elements(*n_frames as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), batch, |b, batch| {
+            b.iter(|| rt.block_on(async {
+                //the batch is done once a bid that appears nowhere else reached the first ticker
+                sentinel += 1.0;
+                session.server.write_all(batch).await.unwrap();
+                session.server.write_all(&frame(&["1", "6", "1", "1", &sentinel.to_string(), "100", "0"])).await.unwrap();
+                //polling without a pause would hold off the dispatcher updating the watch
+                while session.tickers[0].bid() != Some(sentinel) {
+                    std::thread::yield_now();
+                }
+            }));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, tick_dispatch);
+criterion_main!(benches);
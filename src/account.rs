@@ -4,7 +4,8 @@ use rust_decimal::prelude::*;
 
 type Updating<T> = watch::Receiver<Option<T>>;
 type Sender<T> = watch::Sender<Option<T>>;
-#[derive(Debug)]
+#[derive(Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Position {
     pub contract: ib_contract::Contract,
     pub position: Option<Decimal>,
@@ -15,6 +16,7 @@ pub struct Position {
     pub realized_pnl: Option<Decimal>
 }
 
+#[derive(Clone)]
 pub struct AccountReceiver {
     pub update_time: Updating<String>,
     pub account_code: Updating<String>,
@@ -1,5 +1,6 @@
-
+use rust_decimal::Decimal;
 #[derive(Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Bar {
     pub t_stamp: String,
     pub open: f64,
@@ -7,10 +8,11 @@ pub struct Bar {
     pub low: f64,
     pub close: f64,
     pub wap: f64,
-    pub volume: i64,
+    pub volume: Decimal,
     pub count: isize
 }
 #[derive(Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BarSeries {
     pub start_dt: String,
     pub end_dt: String,
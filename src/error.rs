@@ -0,0 +1,191 @@
+use std::{error::Error, fmt, io};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+use crate::ib_enums::error_codes;
+
+pub type AsyncResult<T> = Result<T, IBError>;
+
+/// Error returned by all fallible operations of the client.
+#[derive(Debug,Clone)]
+pub enum IBError {
+    /// Socket level failure.
+    Io(Arc<io::Error>),
+    /// The connection to TWS is gone or the client has been shut down.
+    ConnectionClosed,
+    /// TWS answered the handshake with something unexpected.
+    Handshake(String),
+    /// TWS runs a server version outside of the range supported by this client.
+    VersionMismatch{server_version: i32},
+    /// Error message sent by TWS for a request or order.
+    Tws(TwsError),
+    /// An incoming message could not be decoded.
+    Decode(DecodeError),
+    Timeout,
+    /// The request was abandoned before a response arrived, e.g. by `IBClient::disconnect`.
+    Cancelled,
+    /// TWS rejected the request because of a pacing violation.
+    Pacing(TwsError),
+    /// TWS answered with a message that does not match the request.
+    InvalidResponse
+}
+
+impl Error for IBError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            IBError::Io(err) => Some(err.as_ref()),
+            IBError::Tws(err) | IBError::Pacing(err) => Some(err),
+            IBError::Decode(err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for IBError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IBError::Io(err) => write!(f, "IO error: {}", err),
+            IBError::ConnectionClosed => write!(f, "Connection to TWS closed"),
+            IBError::Handshake(msg) => write!(f, "Handshake failed: {}", msg),
+            IBError::VersionMismatch{server_version} => write!(f, "Unsupported server version {}", server_version),
+            IBError::Tws(err) => write!(f, "{}", err),
+            IBError::Decode(err) => write!(f, "{}", err),
+            IBError::Timeout => write!(f, "Request timed out"),
+            IBError::Cancelled => write!(f, "Request cancelled"),
+            IBError::Pacing(err) => write!(f, "Pacing violation: {}", err),
+            IBError::InvalidResponse => write!(f, "Invalid response type!")
+        }
+    }
+}
+
+impl From<io::Error> for IBError {
+    fn from(err: io::Error) -> Self {
+        IBError::Io(Arc::new(err))
+    }
+}
+
+impl From<TwsError> for IBError {
+    fn from(err: TwsError) -> Self {
+        match err.kind() {
+            TwsErrorKind::Pacing => IBError::Pacing(err),
+            _ => IBError::Tws(err)
+        }
+    }
+}
+
+impl From<DecodeError> for IBError {
+    fn from(err: DecodeError) -> Self {
+        IBError::Decode(err)
+    }
+}
+
+impl From<tokio::time::error::Elapsed> for IBError {
+    fn from(_: tokio::time::error::Elapsed) -> Self {
+        IBError::Timeout
+    }
+}
+
+//the client's tasks hold the other end of every channel, so a closed channel means a closed connection
+impl<T> From<mpsc::error::SendError<T>> for IBError {
+    fn from(_: mpsc::error::SendError<T>) -> Self {
+        IBError::ConnectionClosed
+    }
+}
+
+impl From<oneshot::error::RecvError> for IBError {
+    fn from(_: oneshot::error::RecvError) -> Self {
+        IBError::ConnectionClosed
+    }
+}
+
+/// Rough classification of TWS error codes.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum TwsErrorKind {
+    /// Connectivity between TWS and IB's servers.
+    Connectivity,
+    ClientIdInUse,
+    NotConnected,
+    Pacing,
+    /// The contract could not be resolved.
+    NoSecurityDefinition,
+    OrderRejected,
+    MarketData,
+    HistoricalData,
+    /// Informational message, the request it refers to is still alive.
+    Warning,
+    Other
+}
+
+impl TwsErrorKind {
+    pub fn from_code(code: i32, msg: &str) -> Self {
+        match code {
+            error_codes::CONNECTIVITY_LOST | error_codes::CONNECTIVITY_RESTORED_DATA_LOST |
+            error_codes::CONNECTIVITY_RESTORED_DATA_KEPT | error_codes::CONNECTIVITY_BROKEN => TwsErrorKind::Connectivity,
+            error_codes::CLIENT_ID_IN_USE => TwsErrorKind::ClientIdInUse,
+            error_codes::NOT_CONNECTED => TwsErrorKind::NotConnected,
+            error_codes::MAX_RATE_EXCEEDED => TwsErrorKind::Pacing,
+            error_codes::HISTORICAL_DATA_ERROR if msg.to_lowercase().contains("pacing violation") => TwsErrorKind::Pacing,
+            error_codes::NO_SECURITY_DEFINITION => TwsErrorKind::NoSecurityDefinition,
+            code if error_codes::is_order_rejection(code) => TwsErrorKind::OrderRejected,
+            code if error_codes::is_historical_data_error(code) => TwsErrorKind::HistoricalData,
+            code if error_codes::is_market_data_error(code) => TwsErrorKind::MarketData,
+            code if error_codes::is_warning(code) => TwsErrorKind::Warning,
+            _ => TwsErrorKind::Other
+        }
+    }
+}
+
+/// Error reported by TWS for a specific request or order.
+#[derive(Debug,Clone)]
+pub struct TwsError {
+    pub code: i32,
+    pub msg: String
+}
+
+impl TwsError {
+    pub fn kind(&self) -> TwsErrorKind {
+        TwsErrorKind::from_code(self.code, &self.msg)
+    }
+}
+
+impl Error for TwsError {}
+
+impl fmt::Display for TwsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TWS error {}: {}", self.code, self.msg)
+    }
+}
+
+/// A field of a message that could not be decoded.
+#[derive(Debug,Clone,Default)]
+pub struct DecodeError {
+    /// Id of the message, if known.
+    pub msg_id: Option<i32>,
+    /// Position of the field within the message, the message id being field 1.
+    pub position: Option<usize>,
+    /// Raw value of the field, `None` if the message ended before it.
+    pub value: Option<String>
+}
+
+impl DecodeError {
+    pub fn new(value: &str) -> Self {
+        DecodeError{value: Some(value.to_string()), ..Default::default()}
+    }
+}
+
+impl Error for DecodeError {}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "Decoding value {:?} failed", value)?,
+            None => write!(f, "Truncated message")?
+        }
+        if let Some(position) = self.position {
+            write!(f, " at field {}", position)?;
+        }
+        if let Some(msg_id) = self.msg_id {
+            write!(f, " in message {}", msg_id)?;
+        }
+        Ok(())
+    }
+}
@@ -1,16 +1,43 @@
+//! Messages received from TWS.
+//!
+//! Every message is decoded into an [`IBFrame`]. Besides feeding the typed APIs of the client,
+//! all frames are available through [`IBClient::subscribe_frames`](crate::ib_client::IBClient::subscribe_frames),
+//! together with their raw bytes for messages this crate does not decode yet or does not know at all.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use rs_ib_api::frame::IBFrame;
+//! use rs_ib_api::ib_client::{IBClient, ConnectOptions};
+//!
+//! let client = IBClient::connect(ConnectOptions::new(4002, 1)).await?;
+//! let mut frames = client.subscribe_frames();
+//! while let Ok(received) = frames.recv().await {
+//!     match &received.frame {
+//!         Ok(IBFrame::NotImplemented) | Err(_) => println!("{} {:?}", received.received_at, received.raw),
+//!         _ => ()
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
 use rust_decimal::prelude::*;
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use crate::account::Position;
 use crate::ib_contract;
-use crate::utils::ib_message::decode;
+use crate::utils::ib_message::{Encodable, FieldCursor, MessageBuilder};
+use crate::error::DecodeError;
 use crate::order;
 use crate::bars;
 use crate::ib_enums::*;
 
+use bytes::Bytes;
 use enumset::EnumSetType;
 use enumset::EnumSet;
 use bitvec::prelude::*;
 
+//counts are read from the wire, so preallocation is capped in case of a malformed message
+const MAX_PREALLOC: usize = 1024;
+
 #[derive(EnumSetType, Debug)]
 pub enum TickAttribute {
    CanAutoExecute,
@@ -18,6 +45,7 @@ pub enum TickAttribute {
    PreOpen
 }
 
+#[derive(Debug,Clone)]
 pub enum IBFrame {
     AccountType(Option<String>),
     AccountCode(Option<String>),
@@ -30,472 +58,648 @@ pub enum IBFrame {
     TotalCashBalance(Option<Decimal>),
     AccountUpdateEnd(Option<String>),
     AccountUpdateTime(Option<String>),
-    PortfolioValue(Position),
+    PortfolioValue(Box<Position>),
     CurrentTime(NaiveDateTime),
     ContractDetails{
-        req_id: i32, contract_details: ib_contract::ContractDetails
+        req_id: i32, contract_details: Box<ib_contract::ContractDetails>
     },
     ContractDetailsEnd(i32),
     OrderID(i32),
     OpenOrder{
-        order: order::Order, order_state: order::OrderState
+        order: Box<order::Order>, order_state: Box<order::OrderState>
     },
-    Execution(order::Execution),
+    Execution(Box<order::Execution>),
     CommissionReport(order::CommissionReport),
     OrderStatus(order::OrderStatus),
-    PriceTick{id: i32, kind: TickType, price: f64, size: Option<i32>, attributes: EnumSet<TickAttribute>},
-    SizeTick{id: i32, kind: TickType, size: i32},
+    PriceTick{id: i32, kind: TickType, price: f64, size: Option<Decimal>, attributes: EnumSet<TickAttribute>},
+    SizeTick{id: i32, kind: TickType, size: Decimal},
     StringTick{id: i32, kind: TickType, val: Option<String>},
     GenericTick{id: i32, kind: TickType, val: f64},
     Bars{id: i32, data: bars::BarSeries},
+    /// Sent after the bars from server version `MIN_SERVER_VER_HISTORICAL_DATA_END` on.
+    BarsEnd{id: i32, start_dt: String, end_dt: String},
     Error{id: i32, code: i32, msg: String},
     NotImplemented
 }
 
+/// A frame as received from TWS, see `IBClient::subscribe_frames`.
+#[derive(Debug,Clone)]
+pub struct ReceivedFrame {
+    /// When the message was read from the socket.
+    pub received_at: DateTime<Utc>,
+    /// The decoded message, an error for unknown message ids and malformed messages.
+    pub frame: Result<IBFrame, DecodeError>,
+    /// The message without its length prefix, to handle frames that are `NotImplemented` or not decodable.
+    pub raw: Bytes
+}
+
 impl IBFrame {
-    pub fn parse (msg: &[u8]) -> Self {
-        let utf8msg = String::from_utf8_lossy(msg);
-        let mut it = utf8msg.split("\0");
-        let msg_type: Incoming = it.next().unwrap().parse().expect("Could not parse message type.");
-        match msg_type {
+    /// Decodes a message received from TWS running `server_version`. Messages this client
+    /// does not handle yet are returned as `NotImplemented`, unknown message ids are an error.
+    /// Price, size and generic ticks are decoded without allocating.
+    pub fn parse (msg: &[u8], server_version: i32) -> Result<Self, DecodeError> {
+        if server_version >= constants::MIN_SERVER_VER_PROTOBUF {
+            if msg.len() < 4 {
+                return Err(DecodeError::default());
+            }
+            let msg_id = i32::from_be_bytes([msg[0], msg[1], msg[2], msg[3]]);
+            if msg_id > constants::PROTOBUF_MSG_ID {
+                return IBFrame::parse_protobuf(msg_id - constants::PROTOBUF_MSG_ID, &msg[4..]);
+            }
+            return IBFrame::parse_fields(msg_id, FieldCursor::after_msg_id(&msg[4..], msg_id), server_version);
+        }
+        let mut it = FieldCursor::new(msg);
+        let msg_id: i32 = it.req()?;
+        it.set_msg_id(msg_id);
+        IBFrame::parse_fields(msg_id, it, server_version)
+    }
+
+    #[cfg(feature = "protobuf")]
+    fn parse_protobuf(msg_id: i32, msg: &[u8]) -> Result<Self, DecodeError> {
+        crate::proto::parse(msg_id, msg)
+    }
+
+    //protobuf messages are only sent to clients negotiating MIN_SERVER_VER_PROTOBUF, which needs the feature
+    #[cfg(not(feature = "protobuf"))]
+    fn parse_protobuf(msg_id: i32, _msg: &[u8]) -> Result<Self, DecodeError> {
+        Err(DecodeError{msg_id: Some(msg_id), position: Some(1), value: Some(msg_id.to_string())})
+    }
+
+    fn parse_fields(msg_id: i32, mut it: FieldCursor, server_version: i32) -> Result<Self, DecodeError> {
+        let msg_type: Incoming = match FromPrimitive::from_i32(msg_id) {
+            Some(msg_type) => msg_type,
+            None => return Err(DecodeError{msg_id: Some(msg_id), position: Some(1), value: Some(msg_id.to_string())})
+        };
+        Ok(match msg_type {
             Incoming::AcctValue => {
-                it.next(); //skip version
-                match it.next().unwrap() {
-                    "AccountCode" => IBFrame::AccountCode(decode(&mut it)),
-                    "AccountType" => IBFrame::AccountType(decode(&mut it)),
-                    "CashBalance" => IBFrame::CashBalance(decode(&mut it)),
-                    "EquityWithLoanValue" => IBFrame::EquityWithLoanValue(decode(&mut it)),
-                    "ExcessLiquidity" => IBFrame::ExcessLiquidity(decode(&mut it)),
-                    "NetLiquidation" => IBFrame::NetLiquidation(decode(&mut it)),
-                    "RealizedPnL" => IBFrame::RealizedPnL(decode(&mut it)),
-                    "UnrealizedPnL" => IBFrame::UnrealizedPnL(decode(&mut it)),
-                    "TotalCashBalance" => IBFrame::TotalCashBalance(decode(&mut it)),
+                it.skip()?; //skip version
+                match it.req_str()? {
+                    "AccountCode" => IBFrame::AccountCode(it.opt()?),
+                    "AccountType" => IBFrame::AccountType(it.opt()?),
+                    "CashBalance" => IBFrame::CashBalance(it.opt()?),
+                    "EquityWithLoanValue" => IBFrame::EquityWithLoanValue(it.opt()?),
+                    "ExcessLiquidity" => IBFrame::ExcessLiquidity(it.opt()?),
+                    "NetLiquidation" => IBFrame::NetLiquidation(it.opt()?),
+                    "RealizedPnL" => IBFrame::RealizedPnL(it.opt()?),
+                    "UnrealizedPnL" => IBFrame::UnrealizedPnL(it.opt()?),
+                    "TotalCashBalance" => IBFrame::TotalCashBalance(it.opt()?),
                     &_ => IBFrame::NotImplemented
                 }
             },
             Incoming::AcctDownloadEnd => {
-                it.next(); //skip version
-                IBFrame::AccountUpdateEnd(decode(&mut it))
+                it.skip()?; //skip version
+                IBFrame::AccountUpdateEnd(it.opt()?)
             },
             Incoming::AcctUpdateTime => {
-                it.next(); //skip version
-                IBFrame::AccountUpdateTime(decode(&mut it))
+                it.skip()?; //skip version
+                IBFrame::AccountUpdateTime(it.opt()?)
             },
             Incoming::PortfolioValue => {
-                let version: i32 = decode(&mut it).unwrap();
-                let con_id: i32 = decode(&mut it).unwrap();
-                let mut contract = ib_contract::Contract::default();
-                contract.con_id = Some(con_id);
-                contract.symbol = decode(&mut it);
-                contract.sec_type = decode(&mut it);
-                contract.last_trade_date_or_contract_month = decode(&mut it);
-                contract.strike = decode(&mut it);
-                contract.right = decode(&mut it);
+                let version: i32 = it.req()?;
+                let mut contract = ib_contract::Contract {
+                    con_id: Some(it.req()?),
+                    ..Default::default()
+                };
+                contract.symbol = it.opt()?;
+                contract.sec_type = it.opt()?;
+                contract.last_trade_date_or_contract_month = it.opt()?;
+                contract.strike = it.opt()?;
+                contract.right = it.opt()?;
                 if version >= 7 {
-                    contract.multiplier = decode(&mut it);
-                    contract.primary_exchange = decode(&mut it);
+                    contract.multiplier = it.opt()?;
+                    contract.primary_exchange = it.opt()?;
                 }
-                contract.currency = decode(&mut it);
-                contract.local_symbol = decode(&mut it);
+                contract.currency = it.opt()?;
+                contract.local_symbol = it.opt()?;
                 if version >= 8 {
-                    contract.trading_class = decode(&mut it);
+                    contract.trading_class = it.opt()?;
                 }
-                IBFrame::PortfolioValue(Position {
+                IBFrame::PortfolioValue(Box::new(Position {
                     contract,
-                    position: decode(&mut it),
-                    market_price: decode(&mut it),
-                    market_value: decode(&mut it),
-                    average_cost: decode(&mut it),
-                    unrealized_pnl: decode(&mut it),
-                    realized_pnl: decode(&mut it)
-                })
+                    position: it.opt()?,
+                    market_price: it.opt()?,
+                    market_value: it.opt()?,
+                    average_cost: it.opt()?,
+                    unrealized_pnl: it.opt()?,
+                    realized_pnl: it.opt()?
+                }))
 
             },
             Incoming::CurrentTime => {
-                it.next(); //skip version
-                let unix_time: i64 = decode(&mut it).unwrap();
-                IBFrame::CurrentTime(NaiveDateTime::from_timestamp(unix_time, 0))
+                it.skip()?; //skip version
+                let unix_time: i64 = it.req()?;
+                match NaiveDateTime::from_timestamp_opt(unix_time, 0) {
+                    Some(time) => IBFrame::CurrentTime(time),
+                    None => return Err(DecodeError{msg_id: Some(msg_id), position: Some(3), value: Some(unix_time.to_string())})
+                }
             },
             Incoming::ContractData => {
-                it.next(); //skip version
-                let req_id = decode(&mut it).unwrap();
+                if server_version < constants::MIN_SERVER_VER_SIZE_RULES {
+                    it.skip()?; //skip version
+                }
+                let req_id = it.req()?;
                 let mut contract = ib_contract::Contract {
-                    symbol : decode(&mut it),
-                    sec_type: decode(&mut it),
-                    last_trade_date_or_contract_month: decode(&mut it),
-                    strike: decode(&mut it),
-                    right: decode(&mut it),
-                    exchange: decode(&mut it),
-                    currency: decode(&mut it),
-                    local_symbol: decode(&mut it),
+                    symbol : it.opt()?,
+                    sec_type: it.opt()?,
+                    last_trade_date_or_contract_month: it.opt()?,
                     ..Default::default()
                 };
+                let last_trade_date = if server_version >= constants::MIN_SERVER_VER_LAST_TRADE_DATE {it.opt()?} else {None};
+                contract = ib_contract::Contract {
+                    strike: it.opt()?,
+                    right: it.opt()?,
+                    exchange: it.opt()?,
+                    currency: it.opt()?,
+                    local_symbol: it.opt()?,
+                    ..contract
+                };
                 let mut details = ib_contract::ContractDetails {
-                    market_name: decode(&mut it),
+                    market_name: it.opt()?,
+                    last_trade_date,
                     ..Default::default()
                 };
-                contract.trading_class = decode(&mut it);
-                contract.con_id = decode(&mut it);
-                details.min_tick = decode(&mut it);
-                details.md_size_multiplier = decode(&mut it);
-                contract.multiplier = decode(&mut it);
-                details.order_types = decode(&mut it);
-                details.valid_exchanges = decode(&mut it);
-                details.price_magnifier = decode(&mut it);
-                details.under_con_id = decode(&mut it);
-                details.long_name = decode(&mut it);
-                contract.primary_exchange = decode(&mut it);
-                details.contract_month = decode(&mut it);
-                details.industry = decode(&mut it);
-                details.category = decode(&mut it);
-                details.subcategory = decode(&mut it);
-                details.timezone_id = decode(&mut it);
-                details.trading_hours = decode(&mut it);
-                details.liquid_hours = decode(&mut it);
-                details.ev_rule = decode(&mut it);
-                details.ev_multiplier = decode(&mut it);
-                let sec_id_list_count: Option<usize> = decode(&mut it);
+                contract.trading_class = it.opt()?;
+                contract.con_id = it.opt()?;
+                details.min_tick = it.opt()?;
+                if (constants::MIN_SERVER_VER_MD_SIZE_MULTIPLIER..constants::MIN_SERVER_VER_SIZE_RULES).contains(&server_version) {
+                    details.md_size_multiplier = it.opt()?;
+                }
+                contract.multiplier = it.opt()?;
+                details.order_types = it.opt()?;
+                details.valid_exchanges = it.opt()?;
+                details.price_magnifier = it.opt()?;
+                details.under_con_id = it.opt()?;
+                details.long_name = it.opt()?;
+                contract.primary_exchange = it.opt()?;
+                details.contract_month = it.opt()?;
+                details.industry = it.opt()?;
+                details.category = it.opt()?;
+                details.subcategory = it.opt()?;
+                details.timezone_id = it.opt()?;
+                details.trading_hours = it.opt()?;
+                details.liquid_hours = it.opt()?;
+                details.ev_rule = it.opt()?;
+                details.ev_multiplier = it.opt()?;
+                let sec_id_list_count: Option<usize> = it.opt()?;
                 details.sec_id_list = match sec_id_list_count {
                     Some(count) => {
-                        let mut sec_ids: Vec<(String,String)> = Vec::with_capacity(count);
+                        let mut sec_ids: Vec<(String,String)> = Vec::with_capacity(count.min(MAX_PREALLOC));
                         for i in 0..count {
-                            sec_ids.push((decode(&mut it).unwrap(), decode(&mut it).unwrap()));
+                            sec_ids.push((it.req()?, it.req()?));
                         }
                         Some(sec_ids)
                     },
                     None => None
                 };
-                details.agg_group = decode(&mut it);
-                details.under_symbol = decode(&mut it);
-                details.under_sec_type = decode(&mut it);
-                details.market_rule_ids = decode(&mut it);
-                details.real_expiration_date = decode(&mut it);
+                if server_version >= constants::MIN_SERVER_VER_AGG_GROUP {
+                    details.agg_group = it.opt()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_UNDERLYING_INFO {
+                    details.under_symbol = it.opt()?;
+                    details.under_sec_type = it.opt()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_MARKET_RULES {
+                    details.market_rule_ids = it.opt()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_REAL_EXPIRATION_DATE {
+                    details.real_expiration_date = it.opt()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_STOCK_TYPE {
+                    details.stock_type = it.opt()?;
+                }
+                if server_version == constants::MIN_SERVER_VER_FRACTIONAL_SIZE_SUPPORT {
+                    it.skip()?; //size min tick, replaced by the size rules
+                }
+                if server_version >= constants::MIN_SERVER_VER_SIZE_RULES {
+                    details.min_size = it.opt()?;
+                    details.size_increment = it.opt()?;
+                    details.suggested_size_increment = it.opt()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_FUND_DATA_FIELDS && contract.sec_type == Some(SecType::MutualFund) {
+                    for _ in 0..17 {
+                        it.skip()?; //fund data fields
+                    }
+                }
+                if server_version >= constants::MIN_SERVER_VER_INELIGIBILITY_REASONS {
+                    let count: usize = it.req()?;
+                    let mut reasons = Vec::with_capacity(count.min(MAX_PREALLOC));
+                    for _ in 0..count {
+                        reasons.push((it.req()?, it.req()?));
+                    }
+                    details.ineligibility_reasons = Some(reasons);
+                }
                 details.contract = Some(contract);
                 IBFrame::ContractDetails{
                     req_id,
-                    contract_details: details
+                    contract_details: Box::new(details)
                 }
             }
             Incoming::ContractDataEnd => {
-                it.next(); //skip version
-                IBFrame::ContractDetailsEnd(decode(&mut it).unwrap())
+                it.skip()?; //skip version
+                IBFrame::ContractDetailsEnd(it.req()?)
             }
             Incoming::NextValidId => {
-                it.next(); //skip version
-                IBFrame::OrderID(decode(&mut it).unwrap())
+                it.skip()?; //skip version
+                IBFrame::OrderID(it.req()?)
             }
             Incoming::OpenOrder => {
-                let order_id: i32 = decode(&mut it).unwrap();
+                if server_version < constants::MIN_SERVER_VER_ORDER_CONTAINER {
+                    it.skip()?; //skip version
+                }
+                let order_id: i32 = it.req()?;
                 //decode contract
                 let contract = ib_contract::Contract {
-                    con_id: decode(&mut it),
-                    symbol: decode(&mut it),
-                    sec_type: decode(&mut it),
-                    last_trade_date_or_contract_month: decode(&mut it),
-                    strike: decode(&mut it),
-                    right: decode(&mut it),
-                    multiplier: decode(&mut it),
-                    exchange: decode(&mut it),
-                    currency: decode(&mut it),
-                    local_symbol: decode(&mut it),
-                    trading_class: decode(&mut it),
+                    con_id: it.opt()?,
+                    symbol: it.opt()?,
+                    sec_type: it.opt()?,
+                    last_trade_date_or_contract_month: it.opt()?,
+                    strike: it.opt()?,
+                    right: it.opt()?,
+                    multiplier: it.opt()?,
+                    exchange: it.opt()?,
+                    currency: it.opt()?,
+                    local_symbol: it.opt()?,
+                    trading_class: it.opt()?,
                     ..Default::default()
                 };
                 let mut order = order::Order {
                     contract,
                     order_id,
-                    action: decode(&mut it).unwrap(),
-                    total_qty: decode(&mut it).unwrap(),
-                    order_type: decode(&mut it).unwrap(),
-                    lmt_price: decode(&mut it),
-                    aux_price: decode(&mut it),
-                    tif: decode(&mut it),
-                    oca_group: decode(&mut it),
-                    account: decode(&mut it),
-                    open_close:  decode(&mut it),
-                    origin: decode(&mut it),
-                    order_ref: decode(&mut it),
-                    client_id: decode(&mut it).unwrap(),
-                    perm_id: decode(&mut it).unwrap(),
-                    outside_rth: decode(&mut it).unwrap(),
-                    hidden: decode(&mut it).unwrap(),
-                    discretionary_amt: decode(&mut it).unwrap(),
-                    good_after_time: decode(&mut it),
-                    fa_group: {it.next(); decode(&mut it)},
-                    fa_method: decode(&mut it),
-                    fa_percentage: decode(&mut it),
-                    fa_profile: decode(&mut it),
-                    model_code: decode(&mut it),
-                    good_till_date: decode(&mut it),
-                    rule_80A: decode(&mut it),
-                    percent_offset: decode(&mut it),
-                    settling_firm: decode(&mut it),
-                    short_sale_slot: decode(&mut it),
-                    designated_location: decode(&mut it),
-                    exempt_code: decode(&mut it).unwrap(),
-                    auction_strategy: decode(&mut it),
-                    starting_price: decode(&mut it),
-                    stock_ref_price: decode(&mut it),
-                    delta: decode(&mut it),
-                    stock_range_lower: decode(&mut it),
-                    stock_range_upper: decode(&mut it),
-                    display_size: decode(&mut it),
-                    block_order: decode(&mut it).unwrap(),
-                    sweep_to_fill: decode(&mut it).unwrap(),
-                    all_or_none: decode(&mut it).unwrap(),
-                    min_qty: decode(&mut it),
-                    oca_type: decode(&mut it),
-                    e_trade_only: decode(&mut it).unwrap(),
-                    firm_quote_only: decode(&mut it).unwrap(),
-                    nbbo_price_cap: decode(&mut it),
-                    parent_id: decode(&mut it).unwrap(),
-                    trigger_method: decode(&mut it),
-                    volatility: decode(&mut it),
-                    volatility_type: decode(&mut it),
-                    delta_neutral_order_type: decode(&mut it),
-                    delta_neutral_aux_price: decode(&mut it),
+                    action: it.req()?,
+                    total_qty: it.req()?,
+                    order_type: it.req()?,
+                    lmt_price: it.opt()?,
+                    aux_price: it.opt()?,
+                    tif: it.opt()?,
+                    oca_group: it.opt()?,
+                    account: it.opt()?,
+                    open_close:  it.opt()?,
+                    origin: it.opt()?,
+                    order_ref: it.opt()?,
+                    client_id: it.req()?,
+                    perm_id: it.req()?,
+                    outside_rth: it.req()?,
+                    hidden: it.req()?,
+                    discretionary_amt: it.req()?,
+                    good_after_time: it.opt()?,
+                    fa_group: {it.skip()?; it.opt()?},
+                    fa_method: it.opt()?,
+                    fa_percentage: it.opt()?,
+                    fa_profile: if server_version < constants::MIN_SERVER_VER_FA_PROFILE_DESUPPORT {it.opt()?} else {None},
+                    model_code: if server_version >= constants::MIN_SERVER_VER_MODELS_SUPPORT {it.opt()?} else {None},
+                    good_till_date: it.opt()?,
+                    rule_80A: it.opt()?,
+                    percent_offset: it.opt()?,
+                    settling_firm: it.opt()?,
+                    short_sale_slot: it.opt()?,
+                    designated_location: it.opt()?,
+                    exempt_code: it.req()?,
+                    auction_strategy: it.opt()?,
+                    starting_price: it.opt()?,
+                    stock_ref_price: it.opt()?,
+                    delta: it.opt()?,
+                    stock_range_lower: it.opt()?,
+                    stock_range_upper: it.opt()?,
+                    display_size: it.opt()?,
+                    block_order: it.req()?,
+                    sweep_to_fill: it.req()?,
+                    all_or_none: it.req()?,
+                    min_qty: it.opt()?,
+                    oca_type: it.opt()?,
+                    e_trade_only: it.req()?,
+                    firm_quote_only: it.req()?,
+                    nbbo_price_cap: it.opt()?,
+                    parent_id: it.req()?,
+                    trigger_method: it.opt()?,
+                    volatility: it.opt()?,
+                    volatility_type: it.opt()?,
+                    delta_neutral_order_type: it.opt()?,
+                    delta_neutral_aux_price: it.opt()?,
                     ..Default::default()
                 };
                 if order.delta_neutral_order_type.is_some() {
-                    order.delta_neutral_con_id = decode(&mut it).unwrap();
-                    order.delta_neutral_settling_firm = decode(&mut it);
-                    order.delta_neutral_clearing_account = decode(&mut it);
-                    order.delta_neutral_clearing_intent = decode(&mut it);
-                    order.delta_neutral_open_close = decode(&mut it);
-                    order.delta_neutral_short_sale = decode(&mut it).unwrap();
-                    order.delta_neutral_short_sale_slot = decode(&mut it).unwrap();
-                    order.delta_neutral_designated_location = decode(&mut it);
-                }
-                order.continuous_update = decode(&mut it).unwrap();
-                order.reference_price_type = decode(&mut it);
-                order.trail_stop_price = decode(&mut it);
-                order.trailing_percent = decode(&mut it);
-                order.basis_points = decode(&mut it);
-                order.basis_points_type = decode(&mut it);
-                order.contract.combo_legs_description = decode(&mut it);
-                let combo_legs_count: Option<usize> = decode(&mut it);
+                    order.delta_neutral_con_id = it.req()?;
+                    order.delta_neutral_settling_firm = it.opt()?;
+                    order.delta_neutral_clearing_account = it.opt()?;
+                    order.delta_neutral_clearing_intent = it.opt()?;
+                    order.delta_neutral_open_close = it.opt()?;
+                    order.delta_neutral_short_sale = it.req()?;
+                    order.delta_neutral_short_sale_slot = it.req()?;
+                    order.delta_neutral_designated_location = it.opt()?;
+                }
+                order.continuous_update = it.req()?;
+                order.reference_price_type = it.opt()?;
+                order.trail_stop_price = it.opt()?;
+                order.trailing_percent = it.opt()?;
+                order.basis_points = it.opt()?;
+                order.basis_points_type = it.opt()?;
+                order.contract.combo_legs_description = it.opt()?;
+                let combo_legs_count: Option<usize> = it.opt()?;
                 if let Some(n) = combo_legs_count {
-                    let mut legs = Vec::with_capacity(n);
+                    let mut legs = Vec::with_capacity(n.min(MAX_PREALLOC));
                     for i in 0..n {
                         legs.push(ib_contract::ComboLeg {
-                            con_id: decode(&mut it).unwrap(),
-                            ratio: decode(&mut it).unwrap(),
-                            action: decode(&mut it).unwrap(),
-                            exchange: decode(&mut it).unwrap(),
-                            open_close: decode(&mut it),
-                            shortsale_slot: decode(&mut it),
-                            designated_location: decode(&mut it),
-                            exempt_code: decode(&mut it),
+                            con_id: it.req()?,
+                            ratio: it.req()?,
+                            action: it.req()?,
+                            exchange: it.req()?,
+                            open_close: it.opt()?,
+                            shortsale_slot: it.opt()?,
+                            designated_location: it.opt()?,
+                            exempt_code: it.opt()?,
                         })
                     }
                     order.contract.combo_legs = Some(legs);
                 }
-                let order_combo_legs_count: Option<usize> = decode(&mut it);
+                let order_combo_legs_count: Option<usize> = it.opt()?;
                 if let Some(n) = order_combo_legs_count {
-                    let mut order_legs: Vec<Option<Decimal>> = Vec::with_capacity(n);
+                    let mut order_legs: Vec<Option<Decimal>> = Vec::with_capacity(n.min(MAX_PREALLOC));
                     for i in 0..n {
-                        order_legs.push(decode(&mut it));
+                        order_legs.push(it.opt()?);
                     }
                     order.order_combo_legs = Some(order_legs);
                 }
-                let smart_combo_routing_params_count: Option<usize> = decode(&mut it);
+                let smart_combo_routing_params_count: Option<usize> = it.opt()?;
                 if let Some(n) = smart_combo_routing_params_count {
-                    let mut combo_params: Vec<(String,String)> = Vec::with_capacity(n);
+                    let mut combo_params: Vec<(String,String)> = Vec::with_capacity(n.min(MAX_PREALLOC));
                     for i in 0..n {
-                        combo_params.push((decode(&mut it).unwrap(), decode(&mut it).unwrap()));
+                        combo_params.push((it.req()?, it.req()?));
                     }
+                    order.smart_combo_routing_params = Some(combo_params);
                 }
-                order.scale_init_level_size = decode(&mut it);
-                order.scale_subs_level_size = decode(&mut it);
-                order.scale_price_increment = decode(&mut it);
+                order.scale_init_level_size = it.opt()?;
+                order.scale_subs_level_size = it.opt()?;
+                order.scale_price_increment = it.opt()?;
                 if let Some(incr) = order.scale_price_increment {
                     if incr > 0.0 {
-                        order.scale_price_adjust_value = decode(&mut it);
-                        order.scale_price_adjust_interval = decode(&mut it);
-                        order.scale_profit_offset = decode(&mut it);
-                        order.scale_auto_reset = decode(&mut it).unwrap();
-                        order.scale_init_position = decode(&mut it);
-                        order.scale_init_fill_qty = decode(&mut it);
-                        order.scale_random_percent = decode(&mut it).unwrap();
+                        order.scale_price_adjust_value = it.opt()?;
+                        order.scale_price_adjust_interval = it.opt()?;
+                        order.scale_profit_offset = it.opt()?;
+                        order.scale_auto_reset = it.req()?;
+                        order.scale_init_position = it.opt()?;
+                        order.scale_init_fill_qty = it.opt()?;
+                        order.scale_random_percent = it.req()?;
                     }
                 }
-                order.hedge_type = decode(&mut it);
+                order.hedge_type = it.opt()?;
                 if let Some(ht) = &order.hedge_type {
                     if *ht != HedgeType::Undefined {
-                        order.hedge_param = decode(&mut it);
+                        order.hedge_param = it.opt()?;
                     }
                 }
-                order.opt_out_smart_routing = decode(&mut it).unwrap();
-                order.clearing_account = decode(&mut it);
-                order.clearing_intent = decode(&mut it);
-                order.not_held = decode(&mut it).unwrap();
-                let has_delta_neutral_contract: Option<bool> = decode(&mut it);
+                order.opt_out_smart_routing = it.req()?;
+                order.clearing_account = it.opt()?;
+                order.clearing_intent = it.opt()?;
+                order.not_held = it.req()?;
+                let has_delta_neutral_contract: Option<bool> = it.opt()?;
                 if let Some(has_dnc) = has_delta_neutral_contract {
                     if has_dnc {
                         order.contract.delta_neutral_contract = Some(ib_contract::DeltaNeutralContract{
-                            con_id: decode(&mut it).unwrap(),
-                            delta: decode(&mut it).unwrap(),
-                            price: decode(&mut it).unwrap()
+                            con_id: it.req()?,
+                            delta: it.req()?,
+                            price: it.req()?
                         });
                     }
                 }
-                order.algo_strategy = decode(&mut it);
+                order.algo_strategy = it.opt()?;
                 if order.algo_strategy.is_some() {
-                    let params_count: Option<usize> = decode(&mut it);
+                    let params_count: Option<usize> = it.opt()?;
                     if let Some(n) = params_count {
-                        let mut params: Vec<(String,String)> = Vec::with_capacity(n);
+                        let mut params: Vec<(String,String)> = Vec::with_capacity(n.min(MAX_PREALLOC));
                         for i in 0..n {
-                            params.push((decode(&mut it).unwrap(),decode(&mut it).unwrap()));
+                            params.push((it.req()?,it.req()?));
                         }
                         order.algo_params = Some(params);
                     }
                 }
-                order.solicited = decode(&mut it).unwrap();
-                order.what_if = decode(&mut it).unwrap();
-                let order_state = order::OrderState{
-                    status: decode(&mut it),
-                    init_margin_before: decode(&mut it),
-                    maint_margin_before: decode(&mut it),
-                    equity_with_loan_value_before: decode(&mut it),
-                    init_margin_change: decode(&mut it),
-                    maint_margin_change: decode(&mut it),
-                    equity_with_loan_change: decode(&mut it),
-                    init_margin_after: decode(&mut it),
-                    maint_margin_after: decode(&mut it),
-                    equity_with_loan_after: decode(&mut it),
-                    commission: decode(&mut it),
-                    min_commission: decode(&mut it),
-                    max_commission: decode(&mut it),
-                    commission_currency: decode(&mut it),
-                    warning_text: decode(&mut it),
+                order.solicited = it.req()?;
+                order.what_if = it.req()?;
+                let mut order_state = order::OrderState{
+                    status: it.opt()?,
                     ..Default::default()
                 };
-                order.randomize_size = decode(&mut it).unwrap();
-                order.randomize_price = decode(&mut it).unwrap();
-                if order.order_type == OrderType::PeggedToBenchmark {
-                    order.reference_contract_id = decode(&mut it).unwrap();
-                    order.is_pegged_change_amount_decrease = decode(&mut it).unwrap();
-                    order.pegged_change_amount = decode(&mut it).unwrap();
-                    order.reference_change_amount = decode(&mut it).unwrap();
-                    order.reference_exchange_id = decode(&mut it);
-                }
-                let conditions_count: Option<usize> = decode(&mut it);
-                if let Some(n) = conditions_count 
-                {
-                    if n > 0 {
-                        let mut conditions = Vec::with_capacity(n);
-                        for i in 0..n {
-                            conditions.push(decode(&mut it).unwrap());
+                if server_version >= constants::MIN_SERVER_VER_WHAT_IF_EXT_FIELDS {
+                    order_state.init_margin_before = it.opt()?;
+                    order_state.maint_margin_before = it.opt()?;
+                    order_state.equity_with_loan_value_before = it.opt()?;
+                    order_state.init_margin_change = it.opt()?;
+                    order_state.maint_margin_change = it.opt()?;
+                    order_state.equity_with_loan_change = it.opt()?;
+                }
+                order_state.init_margin_after = it.opt()?;
+                order_state.maint_margin_after = it.opt()?;
+                order_state.equity_with_loan_after = it.opt()?;
+                order_state.commission = it.opt()?;
+                order_state.min_commission = it.opt()?;
+                order_state.max_commission = it.opt()?;
+                order_state.commission_currency = it.opt()?;
+                if server_version >= constants::MIN_SERVER_VER_FULL_ORDER_PREVIEW_FIELDS {
+                    order_state.margin_currency = it.opt()?;
+                    for _ in 0..9 {
+                        it.skip()?; //margin values outside of regular trading hours
+                    }
+                    order_state.suggested_size = it.opt()?;
+                    order_state.reject_reason = it.opt()?;
+                    let allocations: usize = it.req()?;
+                    let fields = allocations.checked_mul(7).ok_or_else(|| DecodeError::new(&allocations.to_string()))?;
+                    for _ in 0..fields {
+                        it.skip()?; //order allocations
+                    }
+                }
+                order_state.warning_text = it.opt()?;
+                order.randomize_size = it.req()?;
+                order.randomize_price = it.req()?;
+                if server_version >= constants::MIN_SERVER_VER_PEGGED_TO_BENCHMARK {
+                    if order.order_type == OrderType::PeggedToBenchmark {
+                        order.reference_contract_id = it.req()?;
+                        order.is_pegged_change_amount_decrease = it.req()?;
+                        order.pegged_change_amount = it.req()?;
+                        order.reference_change_amount = it.req()?;
+                        order.reference_exchange_id = it.opt()?;
+                    }
+                    let conditions_count: Option<usize> = it.opt()?;
+                    if let Some(n) = conditions_count
+                    {
+                        if n > 0 {
+                            let mut conditions = Vec::with_capacity(n.min(MAX_PREALLOC));
+                            for i in 0..n {
+                                conditions.push(it.req()?);
+                            }
+                            order.conditions = Some(conditions);
+                            order.conditions_ignore_rth = it.req()?;
+                            order.conditions_cancel_order = it.req()?;
                         }
-                        order.conditions = Some(conditions);
-                        order.conditions_ignore_rth = decode(&mut it).unwrap();
-                        order.conditions_cancel_order = decode(&mut it).unwrap();
-                    }  
-                }
-                order.adjusted_order_type = decode(&mut it);
-                order.trigger_price = decode(&mut it);
-                order.trail_stop_price = decode(&mut it);
-                order.lmt_price_offset = decode(&mut it);
-                order.adjusted_stop_price = decode(&mut it);
-                order.adjusted_stop_limit_price = decode(&mut it);
-                order.adjusted_trailing_amount = decode(&mut it);
-                order.adjustable_trailing_unit = decode(&mut it).unwrap();
-                let name: Option<String> = decode(&mut it);
-                let val: Option<String> = decode(&mut it);
-                let display_name: Option<String> = decode(&mut it);
-                if name.is_some() || val.is_some() || display_name.is_some() {
-                    order.soft_dollar_tier = Some(order::SoftDollarTier{
-                        name,val,display_name
-                    })
-                }
-                order.cash_qty = decode(&mut it);
-                order.dont_use_auto_price_for_hedge = decode(&mut it).unwrap();
-                order.is_oms_container = decode(&mut it).unwrap();
-                order.discretionary_up_to_limit_price = decode(&mut it).unwrap();
-                order.use_price_mgmt_algo = decode(&mut it);                
+                    }
+                    order.adjusted_order_type = it.opt()?;
+                    order.trigger_price = it.opt()?;
+                    order.trail_stop_price = it.opt()?;
+                    order.lmt_price_offset = it.opt()?;
+                    order.adjusted_stop_price = it.opt()?;
+                    order.adjusted_stop_limit_price = it.opt()?;
+                    order.adjusted_trailing_amount = it.opt()?;
+                    order.adjustable_trailing_unit = it.req()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_SOFT_DOLLAR_TIER {
+                    let name: Option<String> = it.opt()?;
+                    let val: Option<String> = it.opt()?;
+                    let display_name: Option<String> = it.opt()?;
+                    if name.is_some() || val.is_some() || display_name.is_some() {
+                        order.soft_dollar_tier = Some(order::SoftDollarTier{
+                            name,val,display_name
+                        })
+                    }
+                }
+                if server_version >= constants::MIN_SERVER_VER_CASH_QTY {
+                    order.cash_qty = it.opt()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_AUTO_PRICE_FOR_HEDGE {
+                    order.dont_use_auto_price_for_hedge = it.req()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_ORDER_CONTAINER {
+                    order.is_oms_container = it.req()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_D_PEG_ORDERS {
+                    order.discretionary_up_to_limit_price = it.req()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_PRICE_MGMT_ALGO {
+                    order.use_price_mgmt_algo = it.opt()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_DURATION {
+                    order.duration = it.opt()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_POST_TO_ATS {
+                    order.post_to_ats = it.opt()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_AUTO_CANCEL_PARENT {
+                    order.auto_cancel_parent = it.req()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_PEGBEST_PEGMID_OFFSETS {
+                    order.min_trade_qty = it.opt()?;
+                    order.min_compete_size = it.opt()?;
+                    order.compete_against_best_offset = it.opt()?;
+                    order.mid_offset_at_whole = it.opt()?;
+                    order.mid_offset_at_half = it.opt()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_CUSTOMER_ACCOUNT {
+                    order.customer_account = it.opt()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_PROFESSIONAL_CUSTOMER {
+                    order.professional_customer = it.req()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_BOND_ACCRUED_INTEREST {
+                    order.bond_accrued_interest = it.opt()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_INCLUDE_OVERNIGHT {
+                    order.include_overnight = it.req()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_CME_TAGGING_FIELDS_IN_OPEN_ORDER {
+                    order.ext_operator = it.opt()?;
+                    order.manual_order_indicator = it.opt()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_SUBMITTER {
+                    order.submitter = it.opt()?;
+                }
+                if server_version >= constants::MIN_SERVER_VER_IMBALANCE_ONLY {
+                    order.imbalance_only = it.req()?;
+                }
                 IBFrame::OpenOrder{
-                    order, order_state
+                    order: Box::new(order),
+                    order_state: Box::new(order_state)
                 }
             },
             Incoming::CommissionReport => {
-                it.next(); //skip version
+                it.skip()?; //skip version
                 IBFrame::CommissionReport(
                     order::CommissionReport {
-                        exec_id: decode(&mut it).unwrap(),
-                        commission: decode(&mut it).unwrap(),
-                        currency: decode(&mut it).unwrap(),
-                        realized_pnl: decode(&mut it),
-                        yield_amount: decode(&mut it),
-                        yield_redemption_date: decode(&mut it)
+                        exec_id: it.req()?,
+                        commission: it.req()?,
+                        currency: it.req()?,
+                        realized_pnl: it.opt()?,
+                        yield_amount: it.opt()?,
+                        yield_redemption_date: it.opt()?
                     }
                 )
             },
             Incoming::ExecutionData => {
-                it.next(); //skip version
-                let order_id: i32 = decode(&mut it).unwrap();
+                if server_version < constants::MIN_SERVER_VER_LAST_LIQUIDITY {
+                    it.skip()?; //skip version
+                }
+                it.skip()?; //skip request id
+                let order_id: i32 = it.req()?;
                 let contract = ib_contract::Contract {
-                    con_id: decode(&mut it),
-                    symbol : decode(&mut it),
-                    sec_type: decode(&mut it),
-                    last_trade_date_or_contract_month: decode(&mut it),
-                    strike: decode(&mut it),
-                    right: decode(&mut it),
-                    multiplier: decode(&mut it),
-                    exchange: decode(&mut it),
-                    currency: decode(&mut it),
-                    local_symbol: decode(&mut it),
-                    trading_class: decode(&mut it),
+                    con_id: it.opt()?,
+                    symbol : it.opt()?,
+                    sec_type: it.opt()?,
+                    last_trade_date_or_contract_month: it.opt()?,
+                    strike: it.opt()?,
+                    right: it.opt()?,
+                    multiplier: it.opt()?,
+                    exchange: it.opt()?,
+                    currency: it.opt()?,
+                    local_symbol: it.opt()?,
+                    trading_class: it.opt()?,
                     ..Default::default()
                 };
-                IBFrame::Execution(order::Execution {
+                IBFrame::Execution(Box::new(order::Execution {
                     order_id,
                     contract,
-                    exec_id: decode(&mut it).unwrap(),
-                    time: decode(&mut it).unwrap(),
-                    acct_number: decode(&mut it).unwrap(),
-                    exchange: decode(&mut it).unwrap(),
-                    side: decode(&mut it).unwrap(),
-                    shares: decode(&mut it).unwrap(),
-                    price: decode(&mut it).unwrap(),
-                    perm_id: decode(&mut it).unwrap(),
-                    client_id: decode(&mut it).unwrap(),
-                    liquidation: decode(&mut it).unwrap(),
-                    cum_qty: decode(&mut it).unwrap(),
-                    avg_price: decode(&mut it).unwrap(),
-                    order_ref: decode(&mut it),
-                    ev_rule: decode(&mut it),
-                    ev_multiplier: decode(&mut it),
-                    model_code: decode(&mut it),
-                    last_liquidity: decode(&mut it)
-                })
+                    exec_id: it.req()?,
+                    time: it.req()?,
+                    acct_number: it.req()?,
+                    exchange: it.req()?,
+                    side: it.req()?,
+                    shares: it.req()?,
+                    price: it.req()?,
+                    perm_id: it.req()?,
+                    client_id: it.req()?,
+                    liquidation: it.req()?,
+                    cum_qty: it.req()?,
+                    avg_price: it.req()?,
+                    order_ref: it.opt()?,
+                    ev_rule: it.opt()?,
+                    ev_multiplier: it.opt()?,
+                    model_code: if server_version >= constants::MIN_SERVER_VER_MODELS_SUPPORT {it.opt()?} else {None},
+                    last_liquidity: if server_version >= constants::MIN_SERVER_VER_LAST_LIQUIDITY {it.opt()?} else {None},
+                    pending_price_revision: if server_version >= constants::MIN_SERVER_VER_PENDING_PRICE_REVISION {it.opt()?} else {None},
+                    submitter: if server_version >= constants::MIN_SERVER_VER_SUBMITTER {it.opt()?} else {None}
+                }))
             },
             Incoming::OrderStatus => {
+                if server_version < constants::MIN_SERVER_VER_MARKET_CAP_PRICE {
+                    it.skip()?; //skip version
+                }
                 IBFrame::OrderStatus(order::OrderStatus {
-                    order_id: decode(&mut it).unwrap(),
-                    status: decode(&mut it).unwrap(),
-                    filled: decode(&mut it).unwrap(),
-                    remaining: decode(&mut it).unwrap(),
-                    avg_fill_price: decode(&mut it).unwrap(),
-                    perm_id: decode(&mut it).unwrap(),
-                    parent_id: decode(&mut it).unwrap(),
-                    last_fill_price: decode(&mut it).unwrap(),
-                    client_id: decode(&mut it).unwrap(),
-                    why_held: decode(&mut it)
+                    order_id: it.req()?,
+                    status: it.req()?,
+                    filled: it.req()?,
+                    remaining: it.req()?,
+                    avg_fill_price: it.req()?,
+                    perm_id: it.req()?,
+                    parent_id: it.req()?,
+                    last_fill_price: it.req()?,
+                    client_id: it.req()?,
+                    why_held: it.opt()?,
+                    mkt_cap_price: if server_version >= constants::MIN_SERVER_VER_MARKET_CAP_PRICE {it.opt()?} else {None}
                 })
             },
             Incoming::TickPrice => {
-                it.next(); //skip version
-                let id = decode(&mut it).unwrap();
-                let kind = decode(&mut it).unwrap();
-                let price = decode(&mut it).unwrap();
+                it.skip()?; //skip version
+                let id = it.req()?;
+                let kind = it.req()?;
+                let price = it.req()?;
         
-                let size = decode(&mut it);
-                let mask: u32 = decode(&mut it).unwrap();
+                let size = it.opt()?;
+                let mask: u32 = it.req()?;
                 let bits = BitSlice::<Lsb0, _>::from_element(&mask);
                 let mut attributes = EnumSet::new();
-                if bits[0] == true {attributes.insert(TickAttribute::CanAutoExecute);}
-                if bits[1] == true {attributes.insert(TickAttribute::PastLimit);}
-                if bits[2] == true {attributes.insert(TickAttribute::PreOpen);}
+                if server_version >= constants::MIN_SERVER_VER_PAST_LIMIT {
+                    if bits[0] == true {attributes.insert(TickAttribute::CanAutoExecute);}
+                    if bits[1] == true {attributes.insert(TickAttribute::PastLimit);}
+                    if server_version >= constants::MIN_SERVER_VER_PRE_OPEN_BID_ASK && bits[2] == true {attributes.insert(TickAttribute::PreOpen);}
+                }
+                else if mask != 0 {
+                    attributes.insert(TickAttribute::CanAutoExecute);
+                }
                 IBFrame::PriceTick {
                     id,
                     kind,
@@ -505,62 +709,656 @@ impl IBFrame {
                 }
             }
             Incoming::TickSize => {
-                it.next(); //skip version
+                it.skip()?; //skip version
                 IBFrame::SizeTick {
-                    id: decode(&mut it).unwrap(),
-                    kind: decode(&mut it).unwrap(),
-                    size: decode(&mut it).unwrap(),
+                    id: it.req()?,
+                    kind: it.req()?,
+                    size: it.req()?,
                 }
             },
             Incoming::TickString => {
-                it.next(); //skip version
+                it.skip()?; //skip version
                 IBFrame::StringTick {
-                    id: decode(&mut it).unwrap(),
-                    kind: decode(&mut it).unwrap(),
-                    val: decode(&mut it)
+                    id: it.req()?,
+                    kind: it.req()?,
+                    val: it.opt()?
                 }
             },
             Incoming::TickGeneric => {
-                it.next(); //skip version
+                it.skip()?; //skip version
                 IBFrame::GenericTick {
-                    id: decode(&mut it).unwrap(),
-                    kind: decode(&mut it).unwrap(),
-                    val: decode(&mut it).unwrap()
+                    id: it.req()?,
+                    kind: it.req()?,
+                    val: it.req()?
                 }
             },
             Incoming::HistoricalData => {
-                let id = decode(&mut it).unwrap();
-                let start_dt: String = decode(&mut it).unwrap();
-                let end_dt: String = decode(&mut it).unwrap();
-                let n_bars = decode(&mut it).unwrap();
+                if server_version < constants::MIN_SERVER_VER_SYNT_REALTIME_BARS {
+                    it.skip()?; //skip version
+                }
+                let id = it.req()?;
+                let (start_dt, end_dt) = if server_version < constants::MIN_SERVER_VER_HISTORICAL_DATA_END {
+                    (it.req()?, it.req()?)
+                } else {
+                    (String::new(), String::new())
+                };
+                let n_bars: usize = it.req()?;
                 let data = if n_bars > 0 {
-                    let mut bar_data = Vec::with_capacity(n_bars);
+                    let mut bar_data = Vec::with_capacity(n_bars.min(MAX_PREALLOC));
                     for i in 0..n_bars {
                         bar_data.push(bars::Bar {
-                            t_stamp: decode(&mut it).unwrap(),
-                            open: decode(&mut it).unwrap(),
-                            high: decode(&mut it).unwrap(),
-                            low: decode(&mut it).unwrap(),
-                            close: decode(&mut it).unwrap(),
-                            volume: decode(&mut it).unwrap(),
-                            wap: decode(&mut it).unwrap(),
-                            count: decode(&mut it).unwrap()
+                            t_stamp: it.req()?,
+                            open: it.req()?,
+                            high: it.req()?,
+                            low: it.req()?,
+                            close: it.req()?,
+                            volume: it.req()?,
+                            wap: it.req()?,
+                            count: {
+                                if server_version < constants::MIN_SERVER_VER_SYNT_REALTIME_BARS {
+                                    it.skip()?; //skip has gaps
+                                }
+                                it.req()?
+                            }
                         });
                     }
                     Some(bar_data)
                 } else {None};
                 IBFrame::Bars{id, data: bars::BarSeries{start_dt, end_dt, n_bars, data}}
             }
+            Incoming::HistoricalDataEnd => {
+                IBFrame::BarsEnd {
+                    id: it.req()?,
+                    start_dt: it.req()?,
+                    end_dt: it.req()?
+                }
+            }
             Incoming::ErrMsg => {
-                it.next(); //skip version
+                if server_version < constants::MIN_SERVER_VER_ERROR_TIME {
+                    it.skip()?; //skip version
+                }
+                //advanced order reject json and error time follow from newer server versions
                 IBFrame::Error {
-                    id: decode(&mut it).unwrap(),
-                    code: decode(&mut it).unwrap(),
-                    msg: decode(&mut it).unwrap()
+                    id: it.req()?,
+                    code: it.req()?,
+                    msg: it.req()?
                 }
             }
             _ => IBFrame::NotImplemented
-        }
+        })
         
     }
-}
\ No newline at end of file
+
+    /// The length-prefixed message TWS running `server_version` sends for this frame, `None` for
+    /// `NotImplemented`. Fields `parse` skips are written empty or as their default, so a decoded
+    /// frame encodes to the same message again.
+    pub fn encode(&self, server_version: i32) -> Option<Bytes> {
+        let msg_id = match self {
+            IBFrame::AccountType(_) | IBFrame::AccountCode(_) | IBFrame::CashBalance(_) | IBFrame::EquityWithLoanValue(_)
+            | IBFrame::ExcessLiquidity(_) | IBFrame::NetLiquidation(_) | IBFrame::RealizedPnL(_) | IBFrame::UnrealizedPnL(_)
+            | IBFrame::TotalCashBalance(_) => Incoming::AcctValue,
+            IBFrame::AccountUpdateEnd(_) => Incoming::AcctDownloadEnd,
+            IBFrame::AccountUpdateTime(_) => Incoming::AcctUpdateTime,
+            IBFrame::PortfolioValue(_) => Incoming::PortfolioValue,
+            IBFrame::CurrentTime(_) => Incoming::CurrentTime,
+            IBFrame::ContractDetails{..} => Incoming::ContractData,
+            IBFrame::ContractDetailsEnd(_) => Incoming::ContractDataEnd,
+            IBFrame::OrderID(_) => Incoming::NextValidId,
+            IBFrame::OpenOrder{..} => Incoming::OpenOrder,
+            IBFrame::Execution(_) => Incoming::ExecutionData,
+            IBFrame::CommissionReport(_) => Incoming::CommissionReport,
+            IBFrame::OrderStatus(_) => Incoming::OrderStatus,
+            IBFrame::PriceTick{..} => Incoming::TickPrice,
+            IBFrame::SizeTick{..} => Incoming::TickSize,
+            IBFrame::StringTick{..} => Incoming::TickString,
+            IBFrame::GenericTick{..} => Incoming::TickGeneric,
+            IBFrame::Bars{..} => Incoming::HistoricalData,
+            IBFrame::BarsEnd{..} => Incoming::HistoricalDataEnd,
+            IBFrame::Error{..} => Incoming::ErrMsg,
+            IBFrame::NotImplemented => return None
+        };
+        let mut msg = MessageBuilder::new(msg_id as i32, server_version);
+        match self {
+            IBFrame::AccountType(val) => IBFrame::encode_account_value(&mut msg, "AccountType", val),
+            IBFrame::AccountCode(val) => IBFrame::encode_account_value(&mut msg, "AccountCode", val),
+            IBFrame::CashBalance(val) => IBFrame::encode_account_value(&mut msg, "CashBalance", val),
+            IBFrame::EquityWithLoanValue(val) => IBFrame::encode_account_value(&mut msg, "EquityWithLoanValue", val),
+            IBFrame::ExcessLiquidity(val) => IBFrame::encode_account_value(&mut msg, "ExcessLiquidity", val),
+            IBFrame::NetLiquidation(val) => IBFrame::encode_account_value(&mut msg, "NetLiquidation", val),
+            IBFrame::RealizedPnL(val) => IBFrame::encode_account_value(&mut msg, "RealizedPnL", val),
+            IBFrame::UnrealizedPnL(val) => IBFrame::encode_account_value(&mut msg, "UnrealizedPnL", val),
+            IBFrame::TotalCashBalance(val) => IBFrame::encode_account_value(&mut msg, "TotalCashBalance", val),
+            IBFrame::AccountUpdateEnd(account) => {
+                msg.push_raw(b"1\0").push(account);
+            },
+            IBFrame::AccountUpdateTime(time) => {
+                msg.push_raw(b"1\0").push(time);
+            },
+            IBFrame::PortfolioValue(position) => {
+                let contract = &position.contract;
+                msg.push_raw(b"8\0")
+                    .push(&contract.con_id.unwrap_or_default())
+                    .push(&contract.symbol)
+                    .push(&contract.sec_type)
+                    .push(&contract.last_trade_date_or_contract_month)
+                    .push(&contract.strike)
+                    .push(&contract.right)
+                    .push(&contract.multiplier)
+                    .push(&contract.primary_exchange)
+                    .push(&contract.currency)
+                    .push(&contract.local_symbol)
+                    .push(&contract.trading_class)
+                    .push(&position.position)
+                    .push(&position.market_price)
+                    .push(&position.market_value)
+                    .push(&position.average_cost)
+                    .push(&position.unrealized_pnl)
+                    .push(&position.realized_pnl)
+                    .push_raw(b"\0"); //account name
+            },
+            IBFrame::CurrentTime(time) => {
+                msg.push_raw(b"1\0").push(&time.timestamp());
+            },
+            IBFrame::ContractDetails{req_id, contract_details: details} => {
+                let default = ib_contract::Contract::default();
+                let contract = details.contract.as_ref().unwrap_or(&default);
+                if server_version < constants::MIN_SERVER_VER_SIZE_RULES {
+                    msg.push_raw(b"8\0"); //version
+                }
+                msg.push(req_id)
+                    .push(&contract.symbol)
+                    .push(&contract.sec_type)
+                    .push(&contract.last_trade_date_or_contract_month);
+                if server_version >= constants::MIN_SERVER_VER_LAST_TRADE_DATE {
+                    msg.push(&details.last_trade_date);
+                }
+                msg.push(&contract.strike)
+                    .push(&contract.right)
+                    .push(&contract.exchange)
+                    .push(&contract.currency)
+                    .push(&contract.local_symbol)
+                    .push(&details.market_name)
+                    .push(&contract.trading_class)
+                    .push(&contract.con_id)
+                    .push(&details.min_tick);
+                if (constants::MIN_SERVER_VER_MD_SIZE_MULTIPLIER..constants::MIN_SERVER_VER_SIZE_RULES).contains(&server_version) {
+                    msg.push(&details.md_size_multiplier);
+                }
+                msg.push(&contract.multiplier)
+                    .push(&details.order_types)
+                    .push(&details.valid_exchanges)
+                    .push(&details.price_magnifier)
+                    .push(&details.under_con_id)
+                    .push(&details.long_name)
+                    .push(&contract.primary_exchange)
+                    .push(&details.contract_month)
+                    .push(&details.industry)
+                    .push(&details.category)
+                    .push(&details.subcategory)
+                    .push(&details.timezone_id)
+                    .push(&details.trading_hours)
+                    .push(&details.liquid_hours)
+                    .push(&details.ev_rule)
+                    .push(&details.ev_multiplier);
+                match &details.sec_id_list {
+                    Some(sec_ids) => IBFrame::encode_pairs(&mut msg, sec_ids),
+                    None => {
+                        msg.push_raw(b"\0");
+                    }
+                }
+                if server_version >= constants::MIN_SERVER_VER_AGG_GROUP {
+                    msg.push(&details.agg_group);
+                }
+                if server_version >= constants::MIN_SERVER_VER_UNDERLYING_INFO {
+                    msg.push(&details.under_symbol).push(&details.under_sec_type);
+                }
+                if server_version >= constants::MIN_SERVER_VER_MARKET_RULES {
+                    msg.push(&details.market_rule_ids);
+                }
+                if server_version >= constants::MIN_SERVER_VER_REAL_EXPIRATION_DATE {
+                    msg.push(&details.real_expiration_date);
+                }
+                if server_version >= constants::MIN_SERVER_VER_STOCK_TYPE {
+                    msg.push(&details.stock_type);
+                }
+                if server_version == constants::MIN_SERVER_VER_FRACTIONAL_SIZE_SUPPORT {
+                    msg.push_raw(b"\0"); //size min tick
+                }
+                if server_version >= constants::MIN_SERVER_VER_SIZE_RULES {
+                    msg.push(&details.min_size).push(&details.size_increment).push(&details.suggested_size_increment);
+                }
+                if server_version >= constants::MIN_SERVER_VER_FUND_DATA_FIELDS && contract.sec_type == Some(SecType::MutualFund) {
+                    msg.push_raw(&[0; 17]); //fund data fields
+                }
+                if server_version >= constants::MIN_SERVER_VER_INELIGIBILITY_REASONS {
+                    IBFrame::encode_pairs(&mut msg, details.ineligibility_reasons.as_deref().unwrap_or_default());
+                }
+            },
+            IBFrame::ContractDetailsEnd(req_id) => {
+                msg.push_raw(b"1\0").push(req_id);
+            },
+            IBFrame::OrderID(id) => {
+                msg.push_raw(b"1\0").push(id);
+            },
+            IBFrame::OpenOrder{order, order_state} => IBFrame::encode_open_order(&mut msg, order, order_state, server_version),
+            IBFrame::Execution(execution) => {
+                let contract = &execution.contract;
+                if server_version < constants::MIN_SERVER_VER_LAST_LIQUIDITY {
+                    msg.push_raw(b"10\0"); //version
+                }
+                msg.push_raw(b"-1\0") //request id
+                    .push(&execution.order_id)
+                    .push(&contract.con_id)
+                    .push(&contract.symbol)
+                    .push(&contract.sec_type)
+                    .push(&contract.last_trade_date_or_contract_month)
+                    .push(&contract.strike)
+                    .push(&contract.right)
+                    .push(&contract.multiplier)
+                    .push(&contract.exchange)
+                    .push(&contract.currency)
+                    .push(&contract.local_symbol)
+                    .push(&contract.trading_class)
+                    .push(&execution.exec_id)
+                    .push(&execution.time)
+                    .push(&execution.acct_number)
+                    .push(&execution.exchange)
+                    .push(&execution.side)
+                    .push(&execution.shares)
+                    .push(&execution.price)
+                    .push(&execution.perm_id)
+                    .push(&execution.client_id)
+                    .push(&execution.liquidation)
+                    .push(&execution.cum_qty)
+                    .push(&execution.avg_price)
+                    .push(&execution.order_ref)
+                    .push(&execution.ev_rule)
+                    .push(&execution.ev_multiplier);
+                if server_version >= constants::MIN_SERVER_VER_MODELS_SUPPORT {
+                    msg.push(&execution.model_code);
+                }
+                if server_version >= constants::MIN_SERVER_VER_LAST_LIQUIDITY {
+                    msg.push(&execution.last_liquidity);
+                }
+                if server_version >= constants::MIN_SERVER_VER_PENDING_PRICE_REVISION {
+                    msg.push(&execution.pending_price_revision);
+                }
+                if server_version >= constants::MIN_SERVER_VER_SUBMITTER {
+                    msg.push(&execution.submitter);
+                }
+            },
+            IBFrame::CommissionReport(report) => {
+                msg.push_raw(b"1\0")
+                    .push(&report.exec_id)
+                    .push(&report.commission)
+                    .push(&report.currency)
+                    .push(&report.realized_pnl)
+                    .push(&report.yield_amount)
+                    .push(&report.yield_redemption_date);
+            },
+            IBFrame::OrderStatus(status) => {
+                if server_version < constants::MIN_SERVER_VER_MARKET_CAP_PRICE {
+                    msg.push_raw(b"6\0"); //version
+                }
+                msg.push(&status.order_id)
+                    .push(&status.status)
+                    .push(&status.filled)
+                    .push(&status.remaining)
+                    .push(&status.avg_fill_price)
+                    .push(&status.perm_id)
+                    .push(&status.parent_id)
+                    .push(&status.last_fill_price)
+                    .push(&status.client_id)
+                    .push(&status.why_held);
+                if server_version >= constants::MIN_SERVER_VER_MARKET_CAP_PRICE {
+                    msg.push(&status.mkt_cap_price);
+                }
+            },
+            IBFrame::PriceTick{id, kind, price, size, attributes} => {
+                let mask = if server_version >= constants::MIN_SERVER_VER_PAST_LIMIT {
+                    attributes.contains(TickAttribute::CanAutoExecute) as i32
+                        | (attributes.contains(TickAttribute::PastLimit) as i32) << 1
+                        | (attributes.contains(TickAttribute::PreOpen) as i32) << 2
+                } else {
+                    attributes.contains(TickAttribute::CanAutoExecute) as i32
+                };
+                msg.push_raw(b"6\0").push(id).push(kind).push(price).push(size).push(&mask);
+            },
+            IBFrame::SizeTick{id, kind, size} => {
+                msg.push_raw(b"6\0").push(id).push(kind).push(size);
+            },
+            IBFrame::StringTick{id, kind, val} => {
+                msg.push_raw(b"6\0").push(id).push(kind).push(val);
+            },
+            IBFrame::GenericTick{id, kind, val} => {
+                msg.push_raw(b"6\0").push(id).push(kind).push(val);
+            },
+            IBFrame::Bars{id, data} => {
+                let synthetic_bars = server_version >= constants::MIN_SERVER_VER_SYNT_REALTIME_BARS;
+                if !synthetic_bars {
+                    msg.push_raw(b"3\0"); //version
+                }
+                msg.push(id);
+                if server_version < constants::MIN_SERVER_VER_HISTORICAL_DATA_END {
+                    msg.push(&data.start_dt).push(&data.end_dt);
+                }
+                let bars = data.data.as_deref().unwrap_or_default();
+                msg.push(&bars.len());
+                for bar in bars {
+                    msg.push(&bar.t_stamp)
+                        .push(&bar.open)
+                        .push(&bar.high)
+                        .push(&bar.low)
+                        .push(&bar.close)
+                        .push(&bar.volume)
+                        .push(&bar.wap);
+                    if !synthetic_bars {
+                        msg.push_raw(b"false\0"); //has gaps
+                    }
+                    msg.push(&bar.count);
+                }
+            },
+            IBFrame::BarsEnd{id, start_dt, end_dt} => {
+                msg.push(id).push(start_dt).push(end_dt);
+            },
+            IBFrame::Error{id, code, msg: text} => {
+                if server_version < constants::MIN_SERVER_VER_ERROR_TIME {
+                    msg.push_raw(b"2\0"); //version
+                }
+                msg.push(id).push(code).push(text);
+                if server_version >= constants::MIN_SERVER_VER_ADVANCED_ORDER_REJECT {
+                    msg.push_raw(b"\0"); //advanced order reject json
+                }
+                if server_version >= constants::MIN_SERVER_VER_ERROR_TIME {
+                    msg.push_raw(b"\0"); //error time
+                }
+            },
+            IBFrame::NotImplemented => ()
+        }
+        Some(msg.finish())
+    }
+
+    fn encode_account_value<T: Encodable>(msg: &mut MessageBuilder, key: &str, val: &Option<T>) {
+        msg.push_raw(b"2\0").push(&key).push(val).push_raw(b"\0\0"); //currency and account
+    }
+
+    //a count followed by the pairs
+    fn encode_pairs(msg: &mut MessageBuilder, pairs: &[(String, String)]) {
+        msg.push(&pairs.len());
+        for (tag, val) in pairs {
+            msg.push(tag).push(val);
+        }
+    }
+
+    fn encode_open_order(msg: &mut MessageBuilder, order: &order::Order, order_state: &order::OrderState, server_version: i32) {
+        let contract = &order.contract;
+        if server_version < constants::MIN_SERVER_VER_ORDER_CONTAINER {
+            msg.push_raw(b"33\0"); //version
+        }
+        msg.push(&order.order_id)
+            .push(&contract.con_id)
+            .push(&contract.symbol)
+            .push(&contract.sec_type)
+            .push(&contract.last_trade_date_or_contract_month)
+            .push(&contract.strike)
+            .push(&contract.right)
+            .push(&contract.multiplier)
+            .push(&contract.exchange)
+            .push(&contract.currency)
+            .push(&contract.local_symbol)
+            .push(&contract.trading_class)
+            .push(&order.action)
+            .push(&order.total_qty)
+            .push(&order.order_type)
+            .push(&order.lmt_price)
+            .push(&order.aux_price)
+            .push(&order.tif)
+            .push(&order.oca_group)
+            .push(&order.account)
+            .push(&order.open_close)
+            .push(&order.origin)
+            .push(&order.order_ref)
+            .push(&order.client_id)
+            .push(&order.perm_id)
+            .push(&order.outside_rth)
+            .push(&order.hidden)
+            .push(&order.discretionary_amt)
+            .push(&order.good_after_time)
+            .push_raw(b"\0") //deprecated shares allocation
+            .push(&order.fa_group)
+            .push(&order.fa_method)
+            .push(&order.fa_percentage);
+        if server_version < constants::MIN_SERVER_VER_FA_PROFILE_DESUPPORT {
+            msg.push(&order.fa_profile);
+        }
+        if server_version >= constants::MIN_SERVER_VER_MODELS_SUPPORT {
+            msg.push(&order.model_code);
+        }
+        msg.push(&order.good_till_date)
+            .push(&order.rule_80A)
+            .push(&order.percent_offset)
+            .push(&order.settling_firm)
+            .push(&order.short_sale_slot)
+            .push(&order.designated_location)
+            .push(&order.exempt_code)
+            .push(&order.auction_strategy)
+            .push(&order.starting_price)
+            .push(&order.stock_ref_price)
+            .push(&order.delta)
+            .push(&order.stock_range_lower)
+            .push(&order.stock_range_upper)
+            .push(&order.display_size)
+            .push(&order.block_order)
+            .push(&order.sweep_to_fill)
+            .push(&order.all_or_none)
+            .push(&order.min_qty)
+            .push(&order.oca_type)
+            .push(&order.e_trade_only)
+            .push(&order.firm_quote_only)
+            .push(&order.nbbo_price_cap)
+            .push(&order.parent_id)
+            .push(&order.trigger_method)
+            .push(&order.volatility)
+            .push(&order.volatility_type)
+            .push(&order.delta_neutral_order_type)
+            .push(&order.delta_neutral_aux_price);
+        if order.delta_neutral_order_type.is_some() {
+            msg.push(&order.delta_neutral_con_id)
+                .push(&order.delta_neutral_settling_firm)
+                .push(&order.delta_neutral_clearing_account)
+                .push(&order.delta_neutral_clearing_intent)
+                .push(&order.delta_neutral_open_close)
+                .push(&order.delta_neutral_short_sale)
+                .push(&order.delta_neutral_short_sale_slot)
+                .push(&order.delta_neutral_designated_location);
+        }
+        msg.push(&order.continuous_update)
+            .push(&order.reference_price_type)
+            .push(&order.trail_stop_price)
+            .push(&order.trailing_percent)
+            .push(&order.basis_points)
+            .push(&order.basis_points_type)
+            .push(&contract.combo_legs_description);
+        match &contract.combo_legs {
+            Some(legs) => {
+                msg.push(&legs.len());
+                for leg in legs {
+                    msg.push(&leg.con_id)
+                        .push(&leg.ratio)
+                        .push(&leg.action)
+                        .push(&leg.exchange)
+                        .push(&leg.open_close)
+                        .push(&leg.shortsale_slot)
+                        .push(&leg.designated_location)
+                        .push(&leg.exempt_code);
+                }
+            },
+            None => {
+                msg.push_raw(b"0\0");
+            }
+        }
+        match &order.order_combo_legs {
+            Some(legs) => {
+                msg.push(&legs.len());
+                for leg in legs {
+                    msg.push(leg);
+                }
+            },
+            None => {
+                msg.push_raw(b"0\0");
+            }
+        }
+        IBFrame::encode_pairs(msg, order.smart_combo_routing_params.as_deref().unwrap_or_default());
+        msg.push(&order.scale_init_level_size)
+            .push(&order.scale_subs_level_size)
+            .push(&order.scale_price_increment);
+        if order.scale_price_increment.is_some_and(|incr| incr > 0.0) {
+            msg.push(&order.scale_price_adjust_value)
+                .push(&order.scale_price_adjust_interval)
+                .push(&order.scale_profit_offset)
+                .push(&order.scale_auto_reset)
+                .push(&order.scale_init_position)
+                .push(&order.scale_init_fill_qty)
+                .push(&order.scale_random_percent);
+        }
+        msg.push(&order.hedge_type);
+        if order.hedge_type.as_ref().is_some_and(|hedge_type| *hedge_type != HedgeType::Undefined) {
+            msg.push(&order.hedge_param);
+        }
+        msg.push(&order.opt_out_smart_routing)
+            .push(&order.clearing_account)
+            .push(&order.clearing_intent)
+            .push(&order.not_held);
+        match &contract.delta_neutral_contract {
+            Some(dnc) => {
+                msg.push_raw(b"1\0").push(&dnc.con_id).push(&dnc.delta).push(&dnc.price);
+            },
+            None => {
+                msg.push_raw(b"0\0");
+            }
+        }
+        msg.push(&order.algo_strategy);
+        if order.algo_strategy.is_some() {
+            IBFrame::encode_pairs(msg, order.algo_params.as_deref().unwrap_or_default());
+        }
+        msg.push(&order.solicited)
+            .push(&order.what_if)
+            .push(&order_state.status);
+        if server_version >= constants::MIN_SERVER_VER_WHAT_IF_EXT_FIELDS {
+            msg.push(&order_state.init_margin_before)
+                .push(&order_state.maint_margin_before)
+                .push(&order_state.equity_with_loan_value_before)
+                .push(&order_state.init_margin_change)
+                .push(&order_state.maint_margin_change)
+                .push(&order_state.equity_with_loan_change);
+        }
+        msg.push(&order_state.init_margin_after)
+            .push(&order_state.maint_margin_after)
+            .push(&order_state.equity_with_loan_after)
+            .push(&order_state.commission)
+            .push(&order_state.min_commission)
+            .push(&order_state.max_commission)
+            .push(&order_state.commission_currency);
+        if server_version >= constants::MIN_SERVER_VER_FULL_ORDER_PREVIEW_FIELDS {
+            msg.push(&order_state.margin_currency)
+                .push_raw(&[0; 9]) //margin values outside of regular trading hours
+                .push(&order_state.suggested_size)
+                .push(&order_state.reject_reason)
+                .push_raw(b"0\0"); //order allocations
+        }
+        msg.push(&order_state.warning_text)
+            .push(&order.randomize_size)
+            .push(&order.randomize_price);
+        if server_version >= constants::MIN_SERVER_VER_PEGGED_TO_BENCHMARK {
+            if order.order_type == OrderType::PeggedToBenchmark {
+                msg.push(&order.reference_contract_id)
+                    .push(&order.is_pegged_change_amount_decrease)
+                    .push(&order.pegged_change_amount)
+                    .push(&order.reference_change_amount)
+                    .push(&order.reference_exchange_id);
+            }
+            match &order.conditions {
+                Some(conditions) if !conditions.is_empty() => {
+                    msg.push(&conditions.len());
+                    for condition in conditions {
+                        msg.push(condition);
+                    }
+                    msg.push(&order.conditions_ignore_rth).push(&order.conditions_cancel_order);
+                },
+                _ => {
+                    msg.push_raw(b"0\0");
+                }
+            }
+            msg.push(&order.adjusted_order_type)
+                .push(&order.trigger_price)
+                .push(&order.trail_stop_price)
+                .push(&order.lmt_price_offset)
+                .push(&order.adjusted_stop_price)
+                .push(&order.adjusted_stop_limit_price)
+                .push(&order.adjusted_trailing_amount)
+                .push(&order.adjustable_trailing_unit);
+        }
+        if server_version >= constants::MIN_SERVER_VER_SOFT_DOLLAR_TIER {
+            match &order.soft_dollar_tier {
+                Some(tier) => {
+                    msg.push(&tier.name).push(&tier.val).push(&tier.display_name);
+                },
+                None => {
+                    msg.push_raw(b"\0\0\0");
+                }
+            }
+        }
+        if server_version >= constants::MIN_SERVER_VER_CASH_QTY {
+            msg.push(&order.cash_qty);
+        }
+        if server_version >= constants::MIN_SERVER_VER_AUTO_PRICE_FOR_HEDGE {
+            msg.push(&order.dont_use_auto_price_for_hedge);
+        }
+        if server_version >= constants::MIN_SERVER_VER_ORDER_CONTAINER {
+            msg.push(&order.is_oms_container);
+        }
+        if server_version >= constants::MIN_SERVER_VER_D_PEG_ORDERS {
+            msg.push(&order.discretionary_up_to_limit_price);
+        }
+        if server_version >= constants::MIN_SERVER_VER_PRICE_MGMT_ALGO {
+            msg.push(&order.use_price_mgmt_algo);
+        }
+        if server_version >= constants::MIN_SERVER_VER_DURATION {
+            msg.push(&order.duration);
+        }
+        if server_version >= constants::MIN_SERVER_VER_POST_TO_ATS {
+            msg.push(&order.post_to_ats);
+        }
+        if server_version >= constants::MIN_SERVER_VER_AUTO_CANCEL_PARENT {
+            msg.push(&order.auto_cancel_parent);
+        }
+        if server_version >= constants::MIN_SERVER_VER_PEGBEST_PEGMID_OFFSETS {
+            msg.push(&order.min_trade_qty)
+                .push(&order.min_compete_size)
+                .push(&order.compete_against_best_offset)
+                .push(&order.mid_offset_at_whole)
+                .push(&order.mid_offset_at_half);
+        }
+        if server_version >= constants::MIN_SERVER_VER_CUSTOMER_ACCOUNT {
+            msg.push(&order.customer_account);
+        }
+        if server_version >= constants::MIN_SERVER_VER_PROFESSIONAL_CUSTOMER {
+            msg.push(&order.professional_customer);
+        }
+        if server_version >= constants::MIN_SERVER_VER_BOND_ACCRUED_INTEREST {
+            msg.push(&order.bond_accrued_interest);
+        }
+        if server_version >= constants::MIN_SERVER_VER_INCLUDE_OVERNIGHT {
+            msg.push(&order.include_overnight);
+        }
+        if server_version >= constants::MIN_SERVER_VER_CME_TAGGING_FIELDS_IN_OPEN_ORDER {
+            msg.push(&order.ext_operator).push(&order.manual_order_indicator);
+        }
+        if server_version >= constants::MIN_SERVER_VER_SUBMITTER {
+            msg.push(&order.submitter);
+        }
+        if server_version >= constants::MIN_SERVER_VER_IMBALANCE_ONLY {
+            msg.push(&order.imbalance_only);
+        }
+    }
+}
@@ -1,161 +1,678 @@
 
 use crate::ib_enums::*;
 use crate::ib_contract;
-use crate::utils::ib_message;
 use crate::utils::ib_stream;
-use crate::utils::ib_stream::AsyncResult;
+use crate::error::{AsyncResult, IBError, TwsError};
 use crate::utils::ib_message::Encodable;
 use crate::account;
 use crate::order;
 use crate::ticker;
 use crate::bars;
-use crate::frame::IBFrame;
+use crate::frame::{IBFrame, ReceivedFrame};
+use crate::request::IBRequest;
+use crate::recorder::Recorder;
+use crate::pacing::{HistoricalPacer, HistoricalPacing, Pacer, PacingStats, RateLimit};
 
 use std::collections::HashMap;
 use std::collections::VecDeque;
-use std::{error::Error, fmt};
 
 use rust_decimal::prelude::*;
 
 use std::str;
-use chrono::{TimeZone, DateTime};
+use chrono::{TimeZone, DateTime, Utc};
 //use chrono::format::ParseError;
 use tokio::task;
 use tokio::time;
+use std::time::Duration;
 use tokio::net::TcpStream;
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::broadcast;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
-use crossbeam::channel::{self, RecvError};
-use std::sync::atomic::{AtomicUsize,AtomicI32};
+use tokio::sync::watch;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI32,Ordering};
 use futures::future::{Abortable, AbortHandle, Aborted};
+use futures::FutureExt;
+use tracing::{debug, info, warn, Instrument};
+
+//frames a subscriber can fall behind by before it misses some
+const FRAME_SUBSCRIPTION_CAPACITY: usize = 1024;
 
 enum Request {
     OrderID(oneshot::Sender<i32>),
     ReqWithID{id: i32, sender: oneshot::Sender<Response>},
+    //order ids and request ids overlap, so orders waiting for their confirmation are kept apart
+    Order{id: i32, sender: oneshot::Sender<Response>},
+    //streaming market data, the request is kept to resubscribe
+    MarketData{request: IBRequest, sender: oneshot::Sender<Response>},
+    MarketDataType(MarketDataType),
+    //the caller gave up on the request, the cancel message is sent to TWS if there is one
+    Cancel{id: i32, cancel_msg: Option<Bytes>},
+    //the caller stopped waiting for the order confirmation, the order itself stays
+    AbandonOrder(i32),
+    //a heartbeat is about to be sent
+    Heartbeat,
+    CurrentTime(oneshot::Sender<Response>),
+    //cancel all subscriptions and stop dispatching, acknowledged once the cancel messages are queued
+    Disconnect(oneshot::Sender<()>),
+}
+
+enum WriterCommand {
+    //socket of a new session after a reconnect
+    Replace(ib_stream::IBWriter),
+    //write all queued messages, then close the socket
+    Close(oneshot::Sender<()>),
 }
 enum Response {
     ContractDetails(Vec<ib_contract::ContractDetails>),
     Order(order::OrderTracker),
     Ticker(ticker::Ticker),
     Bars(bars::BarSeries),
+    CurrentTime(DateTime<Utc>),
+    Error(TwsError),
+    //the request was abandoned before TWS answered it
+    Cancelled,
     Empty
 }
 
-#[derive(Debug)]
-struct ResponseError;
+/// State of the connection, as reported by TWS system messages and the socket itself.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum ConnectionState {
+    Connected,
+    /// TWS is up, but has lost its connection to IB's servers.
+    LostToIB,
+    /// Connection to IB restored, market data subscriptions had to be renewed.
+    RestoredDataLost,
+    /// Connection to IB restored without losing any data.
+    RestoredDataKept,
+    /// The socket to TWS is closed.
+    Disconnected
+}
 
-impl Error for ResponseError {}
+/// Answer to a `ReqCurrentTime`, sent as heartbeat or by `IBClient::req_current_time`.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Heartbeat {
+    /// Time reported by TWS, in whole seconds.
+    pub server_time: DateTime<Utc>,
+    /// Local time the answer arrived.
+    pub received_at: DateTime<Utc>,
+    /// Time from issuing the request to its answer, including time spent in the rate limiter.
+    pub round_trip: Duration
+}
 
-impl fmt::Display for ResponseError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Invalid response type!") // user-facing output
+impl Heartbeat {
+    /// Estimated offset of the TWS clock to the local clock, positive if TWS is ahead.
+    /// TWS reports whole seconds, so the estimate is only good to about a second.
+    pub fn clock_offset(&self) -> chrono::Duration {
+        let half_trip = chrono::Duration::from_std(self.round_trip / 2).unwrap_or_else(|_| chrono::Duration::zero());
+        self.server_time - (self.received_at - half_trip)
     }
 }
 
-pub struct IBClient
-{
+/// Reissues every live market data subscription under a new request id, keeping the ticker handles.
+fn resubscribe_market_data(tickers: &mut HashMap<i32, ticker::TickerSender>, mkt_data_requests: &mut HashMap<i32, IBRequest>,
+    next_req_id: &AtomicI32, server_version: i32) -> Vec<Bytes> {
+    let mut msgs = Vec::new();
+    let live_ids: Vec<i32> = tickers.keys().cloned().collect();
+    mkt_data_requests.retain(|id, _| tickers.contains_key(id));
+    for old_id in live_ids {
+        if let (Some(ticker), Some(mut request)) = (tickers.remove(&old_id), mkt_data_requests.remove(&old_id)) {
+            let new_id = next_req_id.fetch_add(1, Ordering::SeqCst) + 1;
+            request.set_id(new_id);
+            msgs.push(request.encode(server_version));
+            tickers.insert(new_id, ticker);
+            mkt_data_requests.insert(new_id, request);
+        }
+    }
+    msgs
+}
+
+enum Session {
+    Ready{writer: ib_stream::IBWriter, reader: ib_stream::IBReader, server_version: i32, next_order_id: i32},
+    ClientIdInUse
+}
+
+/// Settings for [`IBClient::connect`].
+///
+/// ```no_run
+/// # use rs_ib_api::ib_client::{IBClient, ConnectOptions};
+/// # use std::time::Duration;
+/// # async fn run() {
+/// let options = ConnectOptions::new(4002, 1)
+///     .host("10.0.0.5")
+///     .connect_timeout(Duration::from_secs(5))
+///     .subscribe_account_updates(false);
+/// let client = IBClient::connect(options).await;
+/// # }
+/// ```
+#[derive(Debug,Clone)]
+pub struct ConnectOptions {
+    host: String,
+    port: u16,
     client_id: i32,
+    connect_timeout: Duration,
+    handshake_timeout: Duration,
+    optional_capabilities: String,
+    subscribe_account_updates: bool,
+    request_next_order_id: bool,
+    client_id_retries: u32,
+    reconnect: bool,
+    reconnect_initial_delay: Duration,
+    reconnect_max_delay: Duration,
+    heartbeat_interval: Duration,
+    max_missed_heartbeats: u32,
+    request_timeout: Option<Duration>,
+    recorder: Option<Recorder>,
+    rate_limit: Option<RateLimit>,
+    historical_pacing: Option<HistoricalPacing>
+}
+
+impl ConnectOptions {
+    /// Options for a gateway listening on `127.0.0.1:port`, using `client_id` for the API session.
+    pub fn new(port: u16, client_id: i32) -> Self {
+        ConnectOptions {
+            host: "127.0.0.1".to_string(),
+            port,
+            client_id,
+            connect_timeout: Duration::from_secs(10),
+            handshake_timeout: Duration::from_secs(10),
+            optional_capabilities: String::new(),
+            subscribe_account_updates: true,
+            request_next_order_id: true,
+            client_id_retries: 5,
+            reconnect: false,
+            reconnect_initial_delay: Duration::from_secs(1),
+            reconnect_max_delay: Duration::from_secs(60),
+            heartbeat_interval: Duration::from_secs(60),
+            max_missed_heartbeats: 3,
+            request_timeout: None,
+            recorder: None,
+            rate_limit: Some(RateLimit::default()),
+            historical_pacing: Some(HistoricalPacing::default())
+        }
+    }
+
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = host.to_string();
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn client_id(mut self, client_id: i32) -> Self {
+        self.client_id = client_id;
+        self
+    }
+
+    /// Maximum time to wait for the TCP connection to be established.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Maximum time to wait for each step of the API handshake.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    pub fn optional_capabilities(mut self, capabilities: &str) -> Self {
+        self.optional_capabilities = capabilities.to_string();
+        self
+    }
+
+    /// Whether to subscribe to account updates right after connecting (default: true).
+    pub fn subscribe_account_updates(mut self, subscribe: bool) -> Self {
+        self.subscribe_account_updates = subscribe;
+        self
+    }
+
+    /// Whether to request a fresh order id after connecting (default: true).
+    /// Otherwise the order id handed out during the handshake is used.
+    pub fn request_next_order_id(mut self, request: bool) -> Self {
+        self.request_next_order_id = request;
+        self
+    }
+
+    /// How many times to retry with the next client id if TWS reports the id as already in use (default: 5).
+    /// Set to 0 to fail instead.
+    pub fn client_id_retries(mut self, retries: u32) -> Self {
+        self.client_id_retries = retries;
+        self
+    }
+
+    /// Whether to reconnect automatically when the connection to TWS drops (default: false).
+    /// After reconnecting, account updates, streaming market data and open order trackers are resubscribed.
+    pub fn reconnect(mut self, reconnect: bool) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// Delay before the first reconnect attempt, doubled after each failed attempt up to `max`.
+    pub fn reconnect_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.reconnect_initial_delay = initial;
+        self.reconnect_max_delay = max;
+        self
+    }
+
+    /// Interval of the heartbeat sent to TWS (default: 60 seconds), and how many heartbeats may go
+    /// unanswered before the connection is considered dead (default: 3). A dead connection is closed,
+    /// or reopened if `reconnect` is set. Set `max_missed` to 0 to never give up on the connection.
+    pub fn heartbeat(mut self, interval: Duration, max_missed: u32) -> Self {
+        self.heartbeat_interval = interval;
+        self.max_missed_heartbeats = max_missed;
+        self
+    }
+
+    /// Default deadline for request/response calls such as `req_contract_details` (default: none).
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Records all messages exchanged with TWS, including those of later reconnects.
+    pub fn record_to(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Pacing of outgoing messages (default: `RateLimit::default()`), `None` sends messages as soon as they are issued.
+    pub fn rate_limit(mut self, limit: Option<RateLimit>) -> Self {
+        self.rate_limit = limit;
+        self
+    }
+
+    /// Limits historical data requests are held back to (default: IB's limits), `None` sends them right away.
+    pub fn historical_pacing(mut self, pacing: Option<HistoricalPacing>) -> Self {
+        self.historical_pacing = pacing;
+        self
+    }
+}
+
+/// Removes a pending request from the dispatcher, which sends the protocol cancel message,
+/// unless the request completed before the guard is dropped.
+struct PendingRequest {
+    id: i32,
+    order: bool,
+    cancel_msg: Option<Bytes>,
+    req_tx: mpsc::UnboundedSender<Request>,
+    done: bool
+}
+
+impl Drop for PendingRequest {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let cancel = if self.order {
+            Request::AbandonOrder(self.id)
+        }
+        else {
+            Request::Cancel{id: self.id, cancel_msg: self.cancel_msg.take()}
+        };
+        //the unbounded channel never rejects the cancel for being full, only once the dispatcher is gone
+        if self.req_tx.send(cancel).is_err() {
+            debug!(id = self.id, "Connection closed, request not cancelled");
+        }
+    }
+}
+
+struct AbortGuard(AbortHandle);
+
+impl Drop for AbortGuard {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Background tasks of a connection, aborted once the last client handle is dropped.
+struct Tasks {
     writer_abort_handle: AbortHandle,
     reader_abort_handle: AbortHandle,
-    keep_alive_abort_handle: AbortHandle,
-    write_tx: mpsc::Sender<String>,
-    req_tx: crossbeam::channel::Sender<Request>,
-    server_version: i32,
+    keep_alive_abort_handle: AbortHandle
+}
+
+impl Drop for Tasks {
+    fn drop(&mut self) {
+        self.keep_alive_abort_handle.abort();
+        self.writer_abort_handle.abort();
+        self.reader_abort_handle.abort();
+    }
+}
+
+/// Handle to a TWS connection. Clones are cheap and share the same connection,
+/// so requests can be issued concurrently from several tasks.
+#[derive(Clone)]
+pub struct IBClient
+{
+    client_id: i32,
+    tasks: Arc<Tasks>,
+    writer_ctl_tx: mpsc::UnboundedSender<WriterCommand>,
+    write_tx: mpsc::Sender<Bytes>,
+    req_tx: mpsc::UnboundedSender<Request>,
+    //renegotiated on every reconnect
+    server_version: Arc<AtomicI32>,
     account: account::AccountReceiver,
-    next_req_id: i32,
-    next_order_id: i32,
-    mkt_data_setting: MarketDataType
+    connection_state: watch::Receiver<ConnectionState>,
+    pacing_stats: watch::Receiver<PacingStats>,
+    last_heartbeat: watch::Receiver<Option<Heartbeat>>,
+    frames: broadcast::Sender<Arc<ReceivedFrame>>,
+    historical_pacer: Arc<HistoricalPacer>,
+    next_req_id: Arc<AtomicI32>,
+    next_order_id: Arc<AtomicI32>,
+    request_timeout: Option<Duration>
 }
 
 impl IBClient
 {
 
-    pub async fn connect(port: i32, client_id: i32, optional_capabilities: &str) -> AsyncResult<Self> {
+    pub async fn connect(options: ConnectOptions) -> AsyncResult<Self> {
+        let mut client_id = options.client_id;
+        let mut retries = 0;
+        let (writer, reader, server_version, handshake_order_id) = loop {
+            match IBClient::open_session(&options, client_id).await? {
+                Session::Ready{writer, reader, server_version, next_order_id} => break (writer, reader, server_version, next_order_id),
+                Session::ClientIdInUse => {
+                    if retries >= options.client_id_retries {
+                        return Err(IBError::Tws(TwsError{code: error_codes::CLIENT_ID_IN_USE, msg: "No free client id found".to_string()}));
+                    }
+                    retries += 1;
+                    client_id += 1;
+                }
+            }
+        };
+        IBClient::start(options, client_id, writer, reader, server_version, handshake_order_id).await
+    }
 
-        let mut addr = "127.0.0.1:".to_string();
-        addr.push_str(&port.to_string());
-        let stream = TcpStream::connect(addr).await?;
-        let ( recv, trans) = stream.into_split();
-        let mut writer = ib_stream::IBWriter::new(trans);
-        let mut reader = ib_stream::IBReader::new(recv);
-        //initiate handshake
-        writer.write_raw(b"API\0").await?;
-        let mut valid_versions = constants::MIN_CLIENT_VER.to_string();
-        valid_versions.push_str("..");
-        valid_versions.push_str(&constants::MAX_CLIENT_VER.to_string());
-        writer.write(&valid_versions).await?;
-        let msg = reader.read().await?;
-        let msg = String::from_utf8_lossy(&msg);
-        let mut it = msg.split("\0");
-        let server_version = it.next().unwrap().parse().unwrap();
-
-        //println!("{:?}", server_version);
-        let mut msg = Outgoing::StartApi.encode();
-        let version : i32 = 2;
-        //start API
-        msg.push_str(&version.encode());
-        msg.push_str(&client_id.encode());
-        msg.push_str(&optional_capabilities.to_string().encode());
-        writer.write(&msg).await?;
-        let client_id = client_id;
+    /// Runs the client over an already established transport, e.g. a TLS stream or an in-memory duplex.
+    /// The stream cannot be reopened, so client id retries and reconnects are not available.
+    pub async fn connect_with_stream<S>(stream: S, options: ConnectOptions) -> AsyncResult<Self>
+    where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let (recv, trans) = tokio::io::split(stream);
+        let writer = ib_stream::IBWriter::new(trans);
+        let reader = ib_stream::IBReader::new(recv);
+        let client_id = options.client_id;
+        match IBClient::start_session(&options, client_id, writer, reader).await? {
+            Session::Ready{writer, reader, server_version, next_order_id} => {
+                let options = options.reconnect(false);
+                IBClient::start(options, client_id, writer, reader, server_version, next_order_id).await
+            },
+            Session::ClientIdInUse => Err(IBError::Tws(TwsError{code: error_codes::CLIENT_ID_IN_USE, msg: "Client id already in use".to_string()}))
+        }
+    }
+
+    /// Spawns the writer, keep alive and dispatcher tasks for an established session.
+    async fn start(options: ConnectOptions, client_id: i32, writer: ib_stream::IBWriter, reader: ib_stream::IBReader,
+        server_version: i32, handshake_order_id: i32) -> AsyncResult<Self> {
         let (tx, mut rx) = mpsc::channel(64);
-        let write_tx: mpsc::Sender<String> = tx.clone();
-        let (req_tx, req_rx) = channel::bounded(100);
+        let write_tx: mpsc::Sender<Bytes> = tx.clone();
+        let (req_tx, mut req_rx) = mpsc::unbounded_channel();
+        let (writer_ctl_tx, mut writer_ctl_rx) = mpsc::unbounded_channel();
+        let reader_writer_ctl_tx = writer_ctl_tx.clone();
+        let next_req_id = Arc::new(AtomicI32::new(0));
+        let server_version = Arc::new(AtomicI32::new(server_version));
 
 
+        //all tasks of the connection log within its span
+        let span = tracing::info_span!("connection", client_id, host = %options.host, port = options.port);
+
         //start the writer task managing the write half of the socket
+        let (mut pacer, pacing_stats) = Pacer::new(options.rate_limit, server_version.clone());
         let (writer_abort_handle, writer_abort_registration) = AbortHandle::new_pair();
         let writer_fut = Abortable::new(async move {
-            loop {
-                let msg = rx.recv().await.unwrap();
-                writer.write(&msg).await.expect("Could not write to socket.");
+            let mut writer = Some(writer);
+            let mut closing = None;
+            let mut open = true;
+            while open || !pacer.is_empty() {
+                let next_send = pacer.next_send();
+                tokio::select! {
+                    biased;
+                    Some(cmd) = writer_ctl_rx.recv(), if closing.is_none() => match cmd {
+                        WriterCommand::Replace(new_writer) => writer = Some(new_writer),
+                        WriterCommand::Close(ack) => {
+                            rx.close();
+                            closing = Some(ack);
+                        }
+                    },
+                    msg = rx.recv(), if open => match msg {
+                        Some(msg) => pacer.push(msg),
+                        None => open = false
+                    },
+                    _ = time::sleep_until(next_send.unwrap_or_else(time::Instant::now)), if next_send.is_some() => ()
+                }
+                //queue everything issued meanwhile, so higher lanes can overtake
+                while open {
+                    match rx.recv().now_or_never() {
+                        Some(Some(msg)) => pacer.push(msg),
+                        Some(None) => open = false,
+                        None => break
+                    }
+                }
+                while let Some(msg) = pacer.pop() {
+                    match writer.as_mut() {
+                        Some(w) => if let Err(err) = w.write(&msg).await {
+                            warn!(error = %err, "Could not write to socket");
+                            writer = None;
+                        },
+                        None => warn!(?msg, "Not connected, dropping message")
+                    }
+                }
+            }
+            if let Some(w) = writer.as_mut() {
+                let _ = w.shutdown().await;
+            }
+            if let Some(ack) = closing {
+                let _ = ack.send(());
             }
-        }, writer_abort_registration);
+        }.instrument(span.clone()), writer_abort_registration);
         let _writer_task = tokio::spawn(writer_fut);
 
-        //start the keep alive task to send a message across the socket every minute
+        //start the keep alive task to send a heartbeat across the socket, the dispatcher tracks the answers
         let (keep_alive_abort_handle, keep_alive_abort_registration) = AbortHandle::new_pair();
+        let heartbeat_req_tx = req_tx.clone();
+        let heartbeat_interval = options.heartbeat_interval;
+        let heartbeat_server_version = server_version.clone();
         let keep_alive_fut = Abortable::new(async move{
-            let mut msg = Outgoing::ReqCurrentTime.encode();
-            msg.push_str(&1i32.encode());
             loop{
-                tx.send(msg.clone()).await.expect("Could not send heartbeat");
-                time::sleep(time::Duration::from_secs(60)).await;
+                if heartbeat_req_tx.send(Request::Heartbeat).is_err() {
+                    break;
+                }
+                let msg = IBRequest::ReqCurrentTime.encode(heartbeat_server_version.load(Ordering::SeqCst));
+                //the writer is gone once the client shuts down
+                if tx.send(msg).await.is_err() {
+                    break;
+                }
+                time::sleep(heartbeat_interval).await;
             }
-        }, keep_alive_abort_registration);
+        }.instrument(span.clone()), keep_alive_abort_registration);
         let _keep_alive_task = tokio::spawn(keep_alive_fut);
         let (account_tx, account) = account::init_account_channel();
-        //start the reader task
+        let (state_tx, connection_state) = watch::channel(ConnectionState::Connected);
+        let (heartbeat_tx, last_heartbeat) = watch::channel(None);
+        let (frames, _) = broadcast::channel(FRAME_SUBSCRIPTION_CAPACITY);
+        let reader_frames_tx = frames.clone();
+        let reader_options = options.clone();
+        let reader_write_tx = write_tx.clone();
+        let reader_next_req_id = next_req_id.clone();
+        let reader_server_version = server_version.clone();
+        //start the dispatcher task, which owns all request state and handles registrations and socket messages in order
         let (reader_abort_handle, reader_abort_registration) = AbortHandle::new_pair();
         let reader_fut = Abortable::new(async move {
+            let (socket_tx, mut socket_rx) = mpsc::channel(64);
+            let mut socket_reader = Some(IBClient::spawn_socket_reader(reader, socket_tx.clone()));
             //caches
             let mut positions_cache= Vec::new();
             let mut contract_details_cache = HashMap::new();
             let mut executions_cache = HashMap::new();
+            let mut bars_cache: HashMap<i32, bars::BarSeries> = HashMap::new();
             //pending requests
             let mut order_id_reqs = VecDeque::new();
+            //time requests in the order sent, `None` for heartbeats
+            let mut time_reqs: VecDeque<(time::Instant, Option<oneshot::Sender<Response>>)> = VecDeque::new();
             let mut requests = HashMap::new();
+            let mut order_reqs = HashMap::new();
             //open order trackers
             let mut order_trackers = HashMap::new();
             //open tickers
             let mut tickers = HashMap::new();
+            //streaming market data requests, needed to resubscribe after a reconnect
+            let mut mkt_data_requests: HashMap<i32, IBRequest> = HashMap::new();
+            let mut mkt_data_type = MarketDataType::RealTime;
 
 
-            loop {
-                let msg = reader.read().await.unwrap();
-                loop {
-                    match req_rx.try_recv() {
-                        Ok(req) => match req {
+            'session: loop {
+                //registrations are handled first, so a response can never overtake its request
+                let msg = tokio::select! {
+                    biased;
+                    Some(req) = req_rx.recv() => {
+                        let mut heartbeats_missed = false;
+                        match req {
                             Request::OrderID(sender) => {
                                 order_id_reqs.push_back(sender)},
                             Request::ReqWithID{id,sender} => {
-                                requests.insert(id, sender);}
-                        },
-                        Err(_) => break
+                                requests.insert(id, sender);},
+                            Request::Order{id, sender} => {
+                                order_reqs.insert(id, sender);},
+                            Request::MarketData{request, sender} => {
+                                let id = request.id().unwrap_or_default();
+                                requests.insert(id, sender);
+                                mkt_data_requests.insert(id, request);},
+                            Request::MarketDataType(kind) => mkt_data_type = kind,
+                            Request::Cancel{id, cancel_msg} => {
+                                if let Some(sender) = requests.remove(&id) {
+                                    let _ = sender.send(Response::Cancelled);
+                                }
+                                mkt_data_requests.remove(&id);
+                                contract_details_cache.remove(&id);
+                                if let Some(msg) = cancel_msg {
+                                    if reader_write_tx.send(msg).await.is_err() {
+                                        warn!(id, "Could not send cancel message");
+                                    }
+                                }
+                            },
+                            Request::AbandonOrder(id) => {
+                                if let Some(sender) = order_reqs.remove(&id) {
+                                    let _ = sender.send(Response::Cancelled);
+                                }
+                                //the tracker may have been sent after the caller stopped waiting
+                                order_trackers.remove(&id);
+                            },
+                            Request::Heartbeat => {
+                                let missed = time_reqs.iter().filter(|(_, sender)| sender.is_none()).count();
+                                time_reqs.push_back((time::Instant::now(), None));
+                                if reader_options.max_missed_heartbeats > 0 && missed >= reader_options.max_missed_heartbeats as usize {
+                                    warn!(missed, "Heartbeats unanswered");
+                                    heartbeats_missed = true;
+                                }
+                            },
+                            Request::CurrentTime(sender) => time_reqs.push_back((time::Instant::now(), Some(sender))),
+                            Request::Disconnect(ack) => {
+                                let server_version = reader_server_version.load(Ordering::SeqCst);
+                                for id in tickers.keys() {
+                                    let _ = reader_write_tx.send(IBRequest::CancelMarketData{req_id: *id}.encode(server_version)).await;
+                                }
+                                if reader_options.subscribe_account_updates {
+                                    let msg = IBRequest::ReqAccountUpdates{subscribe: false, account: None}.encode(server_version);
+                                    let _ = reader_write_tx.send(msg).await;
+                                }
+                                let _ = state_tx.send(ConnectionState::Disconnected);
+                                //requests still in flight will not be answered anymore
+                                let pending = requests.drain().chain(order_reqs.drain()).map(|(_, sender)| sender)
+                                    .chain(time_reqs.drain(..).filter_map(|(_, sender)| sender));
+                                for sender in pending {
+                                    let _ = sender.send(Response::Cancelled);
+                                }
+                                let _ = ack.send(());
+                                break 'session;
+                            }
+                        };
+                        if !heartbeats_missed {
+                            continue 'session;
+                        }
+                        //a half-open connection looks healthy to the socket, treat it like a failed one
+                        Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Heartbeats unanswered").into())
+                    },
+                    Some(msg) = socket_rx.recv() => msg,
+                    else => break 'session
+                };
+                let (received_at, msg) = match msg {
+                    Ok(received) => received,
+                    Err(err) => {
+                        warn!(error = %err, "Connection to TWS lost");
+                        //stops a reader still attached to a half-open connection
+                        drop(socket_reader.take());
+                        time_reqs.clear();
+                        let _ = state_tx.send(ConnectionState::Disconnected);
+                        //requests in flight are lost with the old session, dropping their senders fails them right away
+                        requests.clear();
+                        order_reqs.clear();
+                        order_id_reqs.clear();
+                        contract_details_cache.clear();
+                        positions_cache = Vec::new();
+                        if !reader_options.reconnect {
+                            break 'session;
+                        }
+                        let reopen = IBClient::reopen_session(&reader_options, client_id);
+                        tokio::pin!(reopen);
+                        let (new_writer, new_reader, new_server_version) = loop {
+                            tokio::select! {
+                                session = &mut reopen => break session,
+                                Some(req) = req_rx.recv() => match req {
+                                    Request::Disconnect(ack) => {
+                                        let _ = ack.send(());
+                                        break 'session;
+                                    },
+                                    Request::MarketDataType(kind) => mkt_data_type = kind,
+                                    //requests sent while disconnected never reach TWS, dropping them fails them right away
+                                    _ => ()
+                                }
+                            }
+                        };
+                        reader_server_version.store(new_server_version, Ordering::SeqCst);
+                        socket_reader = Some(IBClient::spawn_socket_reader(new_reader, socket_tx.clone()));
+                        if reader_writer_ctl_tx.send(WriterCommand::Replace(new_writer)).is_err() {
+                            break 'session;
+                        }
+                        let _ = state_tx.send(ConnectionState::Connected);
+                        let mut resubscriptions = Vec::new();
+                        if mkt_data_type != MarketDataType::RealTime {
+                            resubscriptions.push(IBRequest::ReqMarketDataType(mkt_data_type.clone()).encode(new_server_version));
+                        }
+                        if reader_options.subscribe_account_updates {
+                            resubscriptions.push(IBRequest::ReqAccountUpdates{subscribe: true, account: None}.encode(new_server_version));
+                        }
+                        resubscriptions.append(&mut resubscribe_market_data(&mut tickers, &mut mkt_data_requests, &reader_next_req_id, new_server_version));
+                        //open orders are reported again and update the existing trackers
+                        if !order_trackers.is_empty() {
+                            resubscriptions.push(IBRequest::ReqOpenOrders.encode(new_server_version));
+                        }
+                        for msg in resubscriptions {
+                            if reader_write_tx.send(msg).await.is_err() {
+                                break 'session;
+                            }
+                        }
+                        continue 'session;
+                    }
+                };
+                let frame = IBFrame::parse(&msg, reader_server_version.load(Ordering::SeqCst));
+                //frames are only copied while someone subscribed, including those that could not be decoded
+                if reader_frames_tx.receiver_count() > 0 {
+                    let _ = reader_frames_tx.send(Arc::new(ReceivedFrame{received_at, frame: frame.clone(), raw: msg.clone()}));
+                }
+                let frame = match frame {
+                    Ok(frame) => frame,
+                    Err(err) => {
+                        warn!(error = %err, "Skipping message");
+                        continue 'session;
                     }
                 };
-                //println!("{:?}", String::from_utf8_lossy(&msg));
-                let frame = IBFrame::parse(&msg);
                 match frame {
                     IBFrame::AccountCode(code) => account_tx.account_code.send(code).unwrap(),
                     IBFrame::AccountType(typ) => account_tx.account_type.send(typ).unwrap(),
@@ -167,20 +684,30 @@ impl IBClient
                     IBFrame::UnrealizedPnL(u_pnl) => account_tx.unrealized_pnl.send(u_pnl).unwrap(),
                     IBFrame::RealizedPnL(pnl) => account_tx.realized_pnl.send(pnl).unwrap(),
                     IBFrame::TotalCashBalance(balance) => account_tx.total_cash_balance.send(balance).unwrap(),
-                    IBFrame::PortfolioValue(position) => positions_cache.push(position),
+                    IBFrame::PortfolioValue(position) => positions_cache.push(*position),
                     IBFrame::AccountUpdateEnd(_) => {
                         account_tx.portfolio.send(Some(positions_cache)).unwrap();
                         positions_cache = Vec::new();},
-                    IBFrame::CurrentTime(dtime) => println!("Heartbeat at {}", dtime),
+                    IBFrame::CurrentTime(dtime) => {
+                        let server_time = Utc.from_utc_datetime(&dtime);
+                        if let Some((sent, sender)) = time_reqs.pop_front() {
+                            let heartbeat = Heartbeat{server_time, received_at: Utc::now(), round_trip: sent.elapsed()};
+                            debug!(time = %server_time, round_trip = ?heartbeat.round_trip, "Heartbeat");
+                            let _ = heartbeat_tx.send(Some(heartbeat));
+                            if let Some(sender) = sender {
+                                let _ = sender.send(Response::CurrentTime(server_time));
+                            }
+                        }
+                    },
                     IBFrame::OrderID(id) => {
                         match order_id_reqs.pop_front() {
                             Some(sender) => sender.send(id).unwrap(),
-                            None => println!("No pending order id request.")
+                            None => debug!(id, "No pending order id request")
                         }
                     },
                     IBFrame::ContractDetails{req_id: id,contract_details: details} => {
                         contract_details_cache.entry(id).or_insert(Vec::new());
-                        contract_details_cache.get_mut(&id).unwrap().push(details);
+                        contract_details_cache.get_mut(&id).unwrap().push(*details);
                     },
                     IBFrame::ContractDetailsEnd(req_id) => {
                         match requests.remove_entry(&req_id) {
@@ -192,47 +719,51 @@ impl IBClient
                                 };
 
                             },
-                            None => println!("No pending contract details request for req_id {}", req_id)
+                            None => debug!(req_id, "No pending contract details request")
                         };
                     },
                     IBFrame::OpenOrder{order,order_state} => {
                         let order_id = order.order_id;
-                        match requests.remove_entry(&order_id) {
+                        match order_reqs.remove_entry(&order_id) {
                             Some((_, sender)) => {
-                                let (order_sender, order_receiver) = order::OrderTracker::new(order, order_state);
-                                sender.send(Response::Order(order_receiver));
-                                order_trackers.insert(order_id, order_sender);
+                                let (order_sender, order_receiver) = order::OrderTracker::new(*order, *order_state);
+                                //a caller that gave up waiting never gets the tracker, so nothing would drain it
+                                if sender.send(Response::Order(order_receiver)).is_ok() {
+                                    order_trackers.insert(order_id, order_sender);
+                                }
                             },
                             None => {
                                 if let Some(tracker) = order_trackers.get(&order_id) {
-                                    tracker.order_state_tx.send(order_state);
-                                    tracker.order_tx.send(order);
+                                    //a dropped tracker only means nobody follows the order anymore
+                                    let _ = tracker.order_state_tx.send(*order_state);
+                                    let _ = tracker.order_tx.send(*order);
                                 }
                             }
                         }
                         
                     },
                     IBFrame::Execution(execution) => {
-                        if let Some(tracker) = order_trackers.get_mut(&execution.order_id) {
-                            executions_cache.insert(execution.exec_id.clone(), execution.order_id);
-                            tracker.executions_tx.send(execution).unwrap();
+                        let order_id = execution.order_id;
+                        if let Some(tracker) = order_trackers.get_mut(&order_id) {
+                            executions_cache.insert(execution.exec_id.clone(), order_id);
+                            if tracker.executions_tx.send(*execution).is_err() {
+                                debug!(order_id, "Order tracker dropped, skipping execution");
+                            }
                         }
                     },
                     IBFrame::CommissionReport(report) => {
                         if let Some((_,order_id)) = executions_cache.remove_entry(&report.exec_id) {
                             if let Some(tracker) = order_trackers.get_mut(&order_id) {
-                                match tracker.commission_reports_tx.send(report) {
-                                    Err(error) => println!("{:?}", error),
-                                    _ => ()
+                                if tracker.commission_reports_tx.send(report).is_err() {
+                                    debug!(order_id, "Order tracker dropped, skipping commission report");
                                 }
                             }
                         }
                     },
                     IBFrame::OrderStatus(status) => {
                         if let Some(tracker) = order_trackers.get(&status.order_id) {
-                            match tracker.order_status_tx.send(Some(status)) {
-                                Err(error) => println!("Order Status send error"),
-                                _ => ()
+                            if tracker.order_status_tx.send(Some(status)).is_err() {
+                                debug!("Order tracker dropped, skipping order status");
                             }
                         }
                     }
@@ -262,7 +793,7 @@ impl IBClient
                                 }
                                 _ => true
                             };
-                            if !ok {tickers.remove_entry(&id);}    
+                            if !ok {tickers.remove_entry(&id); mkt_data_requests.remove(&id);}    
                         };
                     },
                     IBFrame::SizeTick{id, kind, size} => {
@@ -287,12 +818,12 @@ impl IBClient
                                     else {true}
                                 }
                                 TickType::ShortableShares => {
-                                    if let Err(_) = t.shortable_shares.send(Some(size)) {false}
+                                    if let Err(_) = t.shortable_shares.send(size.to_i32()) {false}
                                     else {true}
                                 }
                                 _ => true
                             };
-                            if !ok {tickers.remove_entry(&id);}    
+                            if !ok {tickers.remove_entry(&id); mkt_data_requests.remove(&id);}    
                         };
                     },
                     IBFrame::GenericTick{id, kind, val} => {
@@ -309,54 +840,275 @@ impl IBClient
                                 }
                                 _ => true
                             };
-                            if !ok {tickers.remove_entry(&id);}    //ticker is dead
+                            if !ok {tickers.remove_entry(&id); mkt_data_requests.remove(&id);}    //ticker is dead
                         };
                     },
                     IBFrame::Bars{id, data} => {
-                        if let Some((_, req)) = requests.remove_entry(&id) {
+                        //newer servers send the start and end of the series after the bars
+                        if reader_server_version.load(Ordering::SeqCst) >= constants::MIN_SERVER_VER_HISTORICAL_DATA_END {
+                            bars_cache.insert(id, data);
+                        }
+                        else if let Some((_, req)) = requests.remove_entry(&id) {
                             req.send(Response::Bars(data));
                         }
                     }
+                    IBFrame::BarsEnd{id, start_dt, end_dt} => {
+                        if let Some(data) = bars_cache.remove(&id) {
+                            if let Some((_, req)) = requests.remove_entry(&id) {
+                                let _ = req.send(Response::Bars(bars::BarSeries{start_dt, end_dt, ..data}));
+                            }
+                        }
+                    }
                     IBFrame::Error{id, code, msg} => {
-
+                        let state = match code {
+                            error_codes::CONNECTIVITY_LOST | error_codes::CONNECTIVITY_BROKEN => Some(ConnectionState::LostToIB),
+                            error_codes::CONNECTIVITY_RESTORED_DATA_LOST => Some(ConnectionState::RestoredDataLost),
+                            error_codes::CONNECTIVITY_RESTORED_DATA_KEPT => Some(ConnectionState::RestoredDataKept),
+                            _ => None
+                        };
+                        if let Some(state) = state {
+                            let _ = state_tx.send(state);
+                        }
+                        //TWS dropped all market data subscriptions, so they have to be reissued
+                        if code == error_codes::CONNECTIVITY_RESTORED_DATA_LOST {
+                            let server_version = reader_server_version.load(Ordering::SeqCst);
+                            for msg in resubscribe_market_data(&mut tickers, &mut mkt_data_requests, &reader_next_req_id, server_version) {
+                                if reader_write_tx.send(msg).await.is_err() {
+                                    break 'session;
+                                }
+                            }
+                        }
+                        if error_codes::is_farm_status(code) {
+                            //e.g. "Market data farm connection is OK:usfarm"
+                            let farm = msg.rsplit(':').next().unwrap_or_default();
+                            info!(code, farm, connected = error_codes::is_farm_connected(code), "{}", msg);
+                            continue;
+                        }
+                        if id < 0 || error_codes::is_warning(code) {
+                            warn!(code, id, "{}", msg);
+                            continue;
+                        }
+                        let error = TwsError{code, msg};
+                        //an order and a request may share the id, orders are checked first
+                        if order_reqs.contains_key(&id) || order_trackers.contains_key(&id) {
+                            if let Some(tracker) = order_trackers.get(&id) {
+                                let _ = tracker.error_tx.send(Some(error.clone()));
+                            }
+                            if let Some(sender) = order_reqs.remove(&id) {
+                                let _ = sender.send(Response::Error(error));
+                            }
+                            continue;
+                        }
+                        contract_details_cache.remove(&id);
+                        match requests.remove(&id) {
+                            Some(sender) => {
+                                mkt_data_requests.remove(&id);
+                                let _ = sender.send(Response::Error(error));
+                            },
+                            None => warn!(code = error.code, id, "{}", error.msg)
+                        }
                     }
                     _ => ()
                 };
             }
-        }, reader_abort_registration);
+        }.instrument(span), reader_abort_registration);
         let _reader_task = tokio::spawn(reader_fut);
-        let mut client = IBClient {
+        let client = IBClient {
             client_id,
-            writer_abort_handle,
-            reader_abort_handle,
-            keep_alive_abort_handle,
+            tasks: Arc::new(Tasks {
+                writer_abort_handle,
+                reader_abort_handle,
+                keep_alive_abort_handle
+            }),
+            writer_ctl_tx,
             write_tx,
             req_tx,
             server_version,
             account,
-            next_req_id: 0,
-            next_order_id: 0,
-            mkt_data_setting: MarketDataType::RealTime
+            connection_state,
+            pacing_stats,
+            last_heartbeat,
+            frames,
+            historical_pacer: Arc::new(HistoricalPacer::new(options.historical_pacing)),
+            next_req_id,
+            next_order_id: Arc::new(AtomicI32::new(handshake_order_id)),
+            request_timeout: options.request_timeout
         };
         //subscribe to account updates
-        msg = Outgoing::ReqAcctData.encode();
-        msg.push_str(&2i32.encode());
-        msg.push_str(&true.encode());
-        msg.push_str("\0");
-        client.write_tx.send(msg).await?;
+        if options.subscribe_account_updates {
+            let msg = IBRequest::ReqAccountUpdates{subscribe: true, account: None}.encode(client.server_version());
+            client.send(msg).await?;
+        }
         //get the latest order id
-        msg = Outgoing::ReqIds.encode();
-        msg.push_str("1\01\0");
-        let (resp_tx, resp_rx) = oneshot::channel();
-        client.req_tx.send(Request::OrderID(resp_tx))?;
-        client.write_tx.send(msg).await?;
-        match resp_rx.await {
-            Ok(id) => client.next_order_id = id,
-            Err(err) => return Err(Box::new(err))
+        if options.request_next_order_id {
+            let (resp_tx, resp_rx) = oneshot::channel();
+            client.req_tx.send(Request::OrderID(resp_tx))?;
+            client.send(IBRequest::ReqIds.encode(client.server_version())).await?;
+            match resp_rx.await {
+                Ok(id) => client.next_order_id.store(id, Ordering::SeqCst),
+                Err(err) => return Err(err.into())
+            }
         }
         Ok(client)
     }
 
+    /// Opens the socket, negotiates the protocol version and starts the API session for `client_id`.
+    /// The session counts as established once TWS hands out the next valid order id.
+    async fn open_session(options: &ConnectOptions, client_id: i32) -> AsyncResult<Session> {
+        let addr = (options.host.as_str(), options.port);
+        let stream = time::timeout(options.connect_timeout, TcpStream::connect(addr)).await??;
+        let (recv, trans) = stream.into_split();
+        let writer = ib_stream::IBWriter::new(trans);
+        let reader = ib_stream::IBReader::new(recv);
+        IBClient::start_session(options, client_id, writer, reader).await
+    }
+
+    /// Negotiates the protocol version and starts the API session over an open transport.
+    async fn start_session(options: &ConnectOptions, client_id: i32, mut writer: ib_stream::IBWriter,
+        mut reader: ib_stream::IBReader) -> AsyncResult<Session> {
+        writer.set_recorder(options.recorder.clone());
+        reader.set_recorder(options.recorder.clone());
+        //initiate handshake
+        writer.write_raw(b"API\0").await?;
+        let mut valid_versions = constants::MIN_CLIENT_VER.to_string();
+        valid_versions.push_str("..");
+        valid_versions.push_str(&constants::MAX_CLIENT_VER.to_string());
+        writer.write_payload(valid_versions.as_bytes()).await?;
+        let msg = time::timeout(options.handshake_timeout, reader.read()).await??;
+        let msg = String::from_utf8_lossy(&msg);
+        let server_version = match msg.split('\0').next().map(str::parse::<i32>) {
+            Some(Ok(version)) => version,
+            _ => return Err(IBError::Handshake("Invalid server version received".to_string()))
+        };
+        if server_version < constants::MIN_CLIENT_VER {
+            return Err(IBError::VersionMismatch{server_version});
+        }
+        //start API, from the protobuf version on its message id is binary like all others
+        let start_api = IBRequest::StartApi{client_id, optional_capabilities: options.optional_capabilities.clone()};
+        writer.write(&start_api.encode(server_version)).await?;
+
+        //wait until TWS either accepts the client id or rejects it
+        let next_order_id = time::timeout(options.handshake_timeout, IBClient::await_api_start(&mut reader, server_version)).await??;
+        Ok(match next_order_id {
+            Some(next_order_id) => Session::Ready{writer, reader, server_version, next_order_id},
+            None => Session::ClientIdInUse
+        })
+    }
+
+    /// Forwards messages from the socket to the dispatcher, with the time they were read, until the
+    /// socket fails. The task is aborted when the returned guard is dropped.
+    fn spawn_socket_reader(mut reader: ib_stream::IBReader, socket_tx: mpsc::Sender<AsyncResult<(DateTime<Utc>, Bytes)>>) -> AbortGuard {
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        tokio::spawn(Abortable::new(async move {
+            loop {
+                let msg = reader.read().await.map(|msg| (Utc::now(), msg));
+                let closed = msg.is_err();
+                if socket_tx.send(msg).await.is_err() || closed {
+                    break;
+                }
+            }
+        }, abort_registration));
+        AbortGuard(abort_handle)
+    }
+
+    /// Reopens the session after a connection loss, backing off exponentially between attempts.
+    async fn reopen_session(options: &ConnectOptions, client_id: i32) -> (ib_stream::IBWriter, ib_stream::IBReader, i32) {
+        let mut delay = options.reconnect_initial_delay;
+        loop {
+            time::sleep(delay).await;
+            match IBClient::open_session(options, client_id).await {
+                Ok(Session::Ready{writer, reader, server_version, ..}) => {
+                    info!(client_id, "Reconnected to TWS");
+                    return (writer, reader, server_version);
+                },
+                Ok(Session::ClientIdInUse) => warn!(client_id, "Reconnect failed: client id still in use"),
+                Err(err) => warn!(error = %err, "Reconnect failed")
+            }
+            delay = std::cmp::min(delay * 2, options.reconnect_max_delay);
+        }
+    }
+
+    async fn await_api_start(reader: &mut ib_stream::IBReader, server_version: i32) -> AsyncResult<Option<i32>> {
+        loop {
+            let msg = reader.read().await?;
+            match IBFrame::parse(&msg, server_version) {
+                Ok(IBFrame::OrderID(id)) => return Ok(Some(id)),
+                Ok(IBFrame::Error{code: error_codes::CLIENT_ID_IN_USE, ..}) => return Ok(None),
+                _ => ()
+            }
+        }
+    }
+
+    /// Shuts the connection down cleanly: market data and account subscriptions are cancelled,
+    /// all queued messages are written and the socket is closed. Pending requests fail with
+    /// `IBError::Cancelled`, requests made through remaining clones of this handle afterwards
+    /// with `IBError::ConnectionClosed`.
+    pub async fn disconnect(self) -> AsyncResult<()> {
+        self.tasks.keep_alive_abort_handle.abort();
+        let (ack_tx, ack_rx) = oneshot::channel();
+        //an error means the dispatcher is already gone, there is nothing left to cancel
+        if self.req_tx.send(Request::Disconnect(ack_tx)).is_ok() {
+            let _ = ack_rx.await;
+        }
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.writer_ctl_tx.send(WriterCommand::Close(ack_tx))?;
+        ack_rx.await?;
+        Ok(())
+    }
+
+    /// The client id of the API session, which may differ from the requested one if it was taken.
+    pub fn client_id(&self) -> i32 {
+        self.client_id
+    }
+
+    /// The protocol version negotiated with TWS, which decides the fields sent and expected for each message.
+    pub fn server_version(&self) -> i32 {
+        self.server_version.load(Ordering::SeqCst)
+    }
+
+    /// The last answered heartbeat or `req_current_time`, `None` before the first answer.
+    pub fn last_heartbeat(&self) -> Option<Heartbeat> {
+        *self.last_heartbeat.borrow()
+    }
+
+    /// Estimated offset of the TWS clock to the local clock, see `Heartbeat::clock_offset`.
+    pub fn clock_offset(&self) -> Option<chrono::Duration> {
+        self.last_heartbeat().map(|heartbeat| heartbeat.clock_offset())
+    }
+
+    /// Current time of TWS, in whole seconds.
+    pub async fn req_current_time(&self) -> AsyncResult<DateTime<Utc>> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        self.req_tx.send(Request::CurrentTime(resp_tx))?;
+        self.send(IBRequest::ReqCurrentTime.encode(self.server_version())).await?;
+        let response = match self.request_timeout {
+            Some(timeout) => time::timeout(timeout, resp_rx).await??,
+            None => resp_rx.await?
+        };
+        match response {
+            Response::CurrentTime(time) => Ok(time),
+            Response::Cancelled => Err(IBError::Cancelled),
+            _ => Err(IBError::InvalidResponse)
+        }
+    }
+
+    /// Counters of the pacing of outgoing messages, see `ConnectOptions::rate_limit`.
+    pub fn pacing_stats(&self) -> PacingStats {
+        self.pacing_stats.borrow().clone()
+    }
+
+    /// Every frame received from TWS from now on, including those the typed APIs ignore and those
+    /// that could not be decoded. A subscriber falling more than 1024 frames behind misses the
+    /// oldest ones and gets `RecvError::Lagged` once.
+    pub fn subscribe_frames(&self) -> broadcast::Receiver<Arc<ReceivedFrame>> {
+        self.frames.subscribe()
+    }
+
+    /// Watch on the state of the connection between this client, TWS and IB's servers.
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.connection_state.clone()
+    }
+
     pub fn net_liquidation_value(&self) -> Option<Decimal> {
         *self.account.net_liquidation.borrow()
     }
@@ -369,177 +1121,184 @@ impl IBClient
         *self.account.excess_liquidity.borrow()
     }
 
-    fn get_next_req_id(&mut self) -> i32 {
-        self.next_req_id += 1;
-        self.next_req_id
+    /// Changes the deadline for request/response calls made through this handle, `None` waits indefinitely.
+    pub fn set_request_timeout(&mut self, timeout: Option<Duration>) {
+        self.request_timeout = timeout;
+    }
+
+    /// Registers request `id` with the dispatcher, writes `msg` and waits for the response. On timeout, or if
+    /// the returned future is dropped, the registration is removed and `cancel_msg` is sent to TWS.
+    async fn send_request(&self, id: i32, registration: Request, msg: Bytes, resp_rx: oneshot::Receiver<Response>,
+        cancel_msg: Option<Bytes>) -> AsyncResult<Response> {
+        let mut guard = PendingRequest {
+            id,
+            order: matches!(registration, Request::Order{..}),
+            cancel_msg: None,
+            req_tx: self.req_tx.clone(),
+            done: false
+        };
+        self.req_tx.send(registration)?;
+        self.send(msg).await?;
+        //only a request that got queued has to be cancelled at TWS
+        guard.cancel_msg = cancel_msg;
+        let response = async {
+            let response = match self.request_timeout {
+                Some(timeout) => time::timeout(timeout, resp_rx).await,
+                None => Ok(resp_rx.await)
+            };
+            if response.is_err() {
+                debug!("Request timed out");
+            }
+            response
+        }.instrument(tracing::debug_span!("request", id)).await??;
+        guard.done = true;
+        match response {
+            Response::Cancelled => Err(IBError::Cancelled),
+            response => Ok(response)
+        }
+    }
+
+    //messages written while disconnected would never reach TWS, so they fail instead of being dropped
+    async fn send(&self, msg: Bytes) -> AsyncResult<()> {
+        if *self.connection_state.borrow() == ConnectionState::Disconnected {
+            return Err(IBError::ConnectionClosed);
+        }
+        self.write_tx.send(msg).await?;
+        Ok(())
+    }
+
+    fn get_next_req_id(&self) -> i32 {
+        self.next_req_id.fetch_add(1, Ordering::SeqCst) + 1
     }
 
-    fn get_next_order_id(&mut self) -> i32 {
-        self.next_order_id += 1;
-        self.next_order_id
+    fn get_next_order_id(&self) -> i32 {
+        self.next_order_id.fetch_add(1, Ordering::SeqCst) + 1
     }
 
-    pub async fn req_contract_details(&mut self, contract: &ib_contract::Contract) -> AsyncResult<Vec<ib_contract::ContractDetails>> {
-        let mut msg = Outgoing::ReqContractData.encode();
-        msg.push_str(&8i32.encode());
+    pub async fn req_contract_details(&self, contract: &ib_contract::Contract) -> AsyncResult<Vec<ib_contract::ContractDetails>> {
         let id = self.get_next_req_id();
-        msg.push_str(&id.encode());
-        msg.push_str(&contract.encode());
+        let msg = IBRequest::ReqContractDetails{req_id: id, contract: Box::new(contract.clone())}.encode(self.server_version());
         let (rep_tx, rep_rx) = oneshot::channel();
-        self.req_tx.send(Request::ReqWithID{id, sender: rep_tx})?;
-        self.write_tx.send(msg).await?;
-        match rep_rx.await {
-            Ok(response) => 
-            {
-                match response {
-                    Response::ContractDetails(contracts) => Ok(contracts),
-                    _ => Err(Box::new(ResponseError{}))
-                }
-            },
-            Err(error) => Err(Box::new(error))
+        match self.send_request(id, Request::ReqWithID{id, sender: rep_tx}, msg, rep_rx, None).await? {
+            Response::ContractDetails(contracts) => Ok(contracts),
+            Response::Error(error) => Err(error.into()),
+            _ => Err(IBError::InvalidResponse)
         }
     }
 
-    pub async fn place_order(&mut self, order: &order::Order) -> AsyncResult<order::OrderTracker> {
-        let mut msg = Outgoing::PlaceOrder.encode();
+    /// A timeout only stops waiting for the order confirmation, the order itself is not cancelled.
+    pub async fn place_order(&self, order: &order::Order) -> AsyncResult<order::OrderTracker> {
         let id = self.get_next_order_id();
-        msg.push_str(&id.encode());
-        msg.push_str(&order.encode());
+        let msg = IBRequest::PlaceOrder{order_id: id, order: Box::new(order.clone())}.encode(self.server_version());
         let (rep_tx, rep_rx) = oneshot::channel();
-        self.req_tx.send(Request::ReqWithID{id, sender: rep_tx})?;
-        println!("{:?}", msg);
-        self.write_tx.send(msg).await?;
-        match rep_rx.await {
-            Ok(response) => 
-            {
-                match response {
-                    Response::Order(tracker) => Ok(tracker),
-                    _ => Err(Box::new(ResponseError{}))
-                }
-            },
-            Err(error) => Err(Box::new(error))
+        match self.send_request(id, Request::Order{id, sender: rep_tx}, msg, rep_rx, None).await? {
+            Response::Order(tracker) => Ok(tracker),
+            Response::Error(error) => Err(error.into()),
+            _ => Err(IBError::InvalidResponse)
         }
     }
 
-    pub async fn req_market_data(&mut self, contract: &ib_contract::Contract, snapshot: bool, regulatory: bool, 
+    pub async fn req_market_data(&self, contract: &ib_contract::Contract, snapshot: bool, regulatory: bool, 
         additional_data: Option<Vec<GenericTickType>>) -> AsyncResult<ticker::Ticker> {
-        let mut msg = Outgoing::ReqMktData.encode();
-        msg.push_str("11\0"); //version
         let id = self.get_next_req_id();
-        msg.push_str(&id.encode());
-        msg.push_str(&contract.encode_for_ticker());
-        msg.push_str("0\0");
-        
-        if let Some(add_data) = additional_data {
-            for i in 0..add_data.len()-1 {
-                msg.push_str(&add_data[i].encode());
-                msg.push_str(",");
-            }
-            if let Some(gen_tick) = add_data.last() {
-                msg.push_str(&gen_tick.encode());
-            }
-        }
-        
-        msg.push_str("\0"); //generic tick data
-        msg.push_str(&snapshot.encode());
-        msg.push_str(&regulatory.encode());
-        msg.push_str("\0");
-        println!("{:?}", msg);
+        let request = IBRequest::ReqMarketData {
+            req_id: id,
+            contract: Box::new(contract.clone()),
+            generic_ticks: additional_data.unwrap_or_default(),
+            snapshot,
+            regulatory_snapshot: regulatory
+        };
+        let msg = request.encode(self.server_version());
         let (req_tx, req_rx) = oneshot::channel();
-        self.req_tx.send(Request::ReqWithID{id, sender: req_tx})?;
-        self.write_tx.send(msg).await?;
-        match req_rx.await {
-            Ok(response) => 
-            {
-                match response {
-                    Response::Ticker(ticker) => Ok(ticker),
-                    _ => Err(Box::new(ResponseError{}))
-                }
-            },
-            Err(err) => Err(Box::new(err))
+        //snapshots end on their own, streams need an explicit cancel
+        let cancel_msg = if snapshot {None} else {Some(IBRequest::CancelMarketData{req_id: id}.encode(self.server_version()))};
+        let registration = if snapshot {Request::ReqWithID{id, sender: req_tx}} else {Request::MarketData{request, sender: req_tx}};
+        match self.send_request(id, registration, msg, req_rx, cancel_msg).await? {
+            Response::Ticker(ticker) => Ok(ticker),
+            Response::Error(error) => Err(error.into()),
+            _ => Err(IBError::InvalidResponse)
         }
     }
 
-    pub async fn req_historical_data<Tz: TimeZone> (&mut self, contract: &ib_contract::Contract, end_date_time: &DateTime<Tz>, 
+    //identifies historical data requests for the same contract and data type, see `HistoricalPacing`
+    fn historical_contract_key(contract: &ib_contract::Contract, what_to_show: Option<&HistoricalDataType>) -> Bytes {
+        let mut contract_key = BytesMut::new();
+        contract.encode_for_hist_data(&mut contract_key);
+        match what_to_show {
+            Some(what_to_show) => what_to_show.encode(&mut contract_key),
+            None => contract_key.put_slice(b"ADJUSTED_LAST\0")
+        }
+        contract_key.freeze()
+    }
+
+    //sends a historical data request once pacing allows it, under a request id assigned when it is sent
+    async fn paced_historical_data(&self, request: IBRequest, contract_key: Bytes) -> AsyncResult<bars::BarSeries> {
+        //requests encoded before they get an id are identical if they ask for the same bars
+        let key = request.encode(self.server_version());
+        let client = self.clone();
+        self.historical_pacer.request(key, contract_key, async move {
+            let mut request = request;
+            let id = client.get_next_req_id();
+            request.set_id(id);
+            let server_version = client.server_version();
+            let (resp_tx, resp_rx) = oneshot::channel();
+            let cancel_msg = IBRequest::CancelHistoricalData{req_id: id}.encode(server_version);
+            let registration = Request::ReqWithID{id, sender: resp_tx};
+            match client.send_request(id, registration, request.encode(server_version), resp_rx, Some(cancel_msg)).await? {
+                Response::Bars(bars) => Ok(bars),
+                Response::Error(error) => Err(error.into()),
+                _ => Err(IBError::InvalidResponse)
+            }
+        }).await
+    }
+
+    /// Requests are paced to IB's limits (see `ConnectOptions::historical_pacing`), an identical request
+    /// in flight is answered with the same bars.
+    pub async fn req_historical_data<Tz: TimeZone> (&self, contract: &ib_contract::Contract, end_date_time: &DateTime<Tz>, 
         duration: HistoricalDataDuration, bar_period: HistoricalDataBarSize, what_to_show: HistoricalDataType, use_rth: bool) -> AsyncResult<bars::BarSeries>
         where
         <Tz as TimeZone>::Offset: std::fmt::Display
         {
-        let mut msg = Outgoing::ReqHistoricalData.encode();
-        let id = self.get_next_req_id();
-        msg.push_str(&id.encode());
-        msg.push_str(&contract.encode_for_hist_data());
-        msg.push_str(&end_date_time.format("%Y%m%d %H:%M:%S").to_string().encode());
-        msg.push_str(&bar_period.encode());
-        msg.push_str(&duration.encode());
-        msg.push_str(&use_rth.encode());
-        msg.push_str(&what_to_show.encode());
-        msg.push_str("1\00\0\0");
-        let (resp_tx, resp_rx) = oneshot::channel();
-        self.req_tx.send(Request::ReqWithID{id, sender: resp_tx});
-        self.write_tx.send(msg).await?;
-        match resp_rx.await {
-            Ok(response) => 
-            {
-                match response {
-                    Response::Bars(bars) => Ok(bars),
-                    _ => Err(Box::new(ResponseError{}))
-                }
-            },
-            Err(err) => Err(Box::new(err))
-        }
+        let contract_key = IBClient::historical_contract_key(contract, Some(&what_to_show));
+        let request = IBRequest::ReqHistoricalData {
+            req_id: 0,
+            contract: Box::new(contract.clone()),
+            end_date_time: Some(end_date_time.format("%Y%m%d %H:%M:%S").to_string()),
+            bar_size: bar_period,
+            duration,
+            use_rth,
+            what_to_show: Some(what_to_show)
+        };
+        self.paced_historical_data(request, contract_key).await
     }
 
-    pub async fn req_adj_historical_data(&mut self, contract: &ib_contract::Contract, duration: HistoricalDataDuration, bar_period: HistoricalDataBarSize, use_rth: bool) -> AsyncResult<bars::BarSeries> {
-        let mut msg = Outgoing::ReqHistoricalData.encode();
-        let id = self.get_next_req_id();
-        msg.push_str(&id.encode());
-        msg.push_str(&contract.encode_for_hist_data());
-        msg.push_str("\0");
-        msg.push_str(&bar_period.encode());
-        msg.push_str(&duration.encode());
-        msg.push_str(&use_rth.encode());
-        msg.push_str("ADJUSTED_LAST\0");
-        msg.push_str("1\00\0\0");
-        let (resp_tx, resp_rx) = oneshot::channel();
-        self.req_tx.send(Request::ReqWithID{id, sender: resp_tx});
-        self.write_tx.send(msg).await?;
-        match resp_rx.await {
-            Ok(response) => 
-            {
-                match response {
-                    Response::Bars(bars) => Ok(bars),
-                    _ => Err(Box::new(ResponseError{}))
-                }
-            },
-            Err(err) => Err(Box::new(err))
-        }
+    pub async fn req_adj_historical_data(&self, contract: &ib_contract::Contract, duration: HistoricalDataDuration, bar_period: HistoricalDataBarSize, use_rth: bool) -> AsyncResult<bars::BarSeries> {
+        let request = IBRequest::ReqHistoricalData {
+            req_id: 0,
+            contract: Box::new(contract.clone()),
+            end_date_time: None,
+            bar_size: bar_period,
+            duration,
+            use_rth,
+            what_to_show: None
+        };
+        self.paced_historical_data(request, IBClient::historical_contract_key(contract, None)).await
     }
 
-    pub async fn set_mkt_data_delayed(&mut self) -> AsyncResult<()> {
-        let mut msg = Outgoing::ReqMarketDataType.encode();
-        msg.push_str("1\0");
-        msg.push_str(&MarketDataType::Delayed.encode());
-        self.write_tx.send(msg).await?;
-        self.mkt_data_setting = MarketDataType::Delayed;
+    /// Switches market data to delayed quotes, kept across reconnects.
+    pub async fn set_mkt_data_delayed(&self) -> AsyncResult<()> {
+        let msg = IBRequest::ReqMarketDataType(MarketDataType::Delayed).encode(self.server_version());
+        self.send(msg).await?;
+        self.req_tx.send(Request::MarketDataType(MarketDataType::Delayed))?;
         Ok(())
     }
 
-    pub async fn set_mkt_data_real_time(&mut self) -> AsyncResult<()> {
-        let mut msg = Outgoing::ReqMarketDataType.encode();
-        msg.push_str("1\0");
-        msg.push_str(&MarketDataType::RealTime.encode());
-        self.write_tx.send(msg).await?;
-        self.mkt_data_setting = MarketDataType::RealTime;
+    /// Switches market data back to real time quotes.
+    pub async fn set_mkt_data_real_time(&self) -> AsyncResult<()> {
+        let msg = IBRequest::ReqMarketDataType(MarketDataType::RealTime).encode(self.server_version());
+        self.send(msg).await?;
+        self.req_tx.send(Request::MarketDataType(MarketDataType::RealTime))?;
         Ok(())
     }
 
 }
-
-impl Drop for IBClient {
-    fn drop(&mut self) {
-        self.keep_alive_abort_handle.abort();
-        self.writer_abort_handle.abort();
-        self.reader_abort_handle.abort();
-    }
-}
\ No newline at end of file
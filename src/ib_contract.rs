@@ -1,11 +1,14 @@
 use crate::ib_enums;
 use rust_decimal::prelude::*;
 use crate::ib_enums::*;
-use crate::utils::ib_message::Encodable;
+use crate::utils::ib_message::{Encodable, FieldCursor};
+use crate::error::DecodeError;
+use bytes::BytesMut;
 use chrono::{DateTime,NaiveDateTime,Utc,TimeZone};
 use chrono_tz::Tz;
 use chrono_tz::{UTC,US};
 #[derive(Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ComboLeg {
     pub con_id: i32,
     pub ratio: i32,
@@ -25,6 +28,7 @@ impl ComboLeg {
     }
 }
 #[derive(Default,Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
 pub struct DeltaNeutralContract {
     pub con_id: i32,
     pub delta: Decimal,
@@ -32,6 +36,7 @@ pub struct DeltaNeutralContract {
 }
 
 #[derive(Default,Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
 pub struct Contract {
     pub con_id: Option<i32>,
     pub symbol: Option<String>,
@@ -48,86 +53,122 @@ pub struct Contract {
     pub include_expired: Option<bool>,
     pub sec_id_type: Option<ib_enums::SecIdType>,
     pub sec_id: Option<String>,
+    /// Only used to look up bonds, from server version `MIN_SERVER_VER_BOND_ISSUERID` on.
+    pub issuer_id: Option<String>,
     pub combo_legs_description: Option<String>,
     pub combo_legs: Option<Vec<ComboLeg>>,
     pub delta_neutral_contract: Option<DeltaNeutralContract>,
 }
 
 impl Encodable for Contract {
-    fn encode(&self) -> String {
-        let mut code = String::new();
-        code.push_str(&self.con_id.encode());
-        code.push_str(&self.symbol.encode());
-        code.push_str(&self.sec_type.encode());
-        code.push_str(&self.last_trade_date_or_contract_month.encode());
-        code.push_str(&self.strike.encode());
-        code.push_str(&self.right.encode());
-        code.push_str(&self.multiplier.encode());
-        code.push_str(&self.exchange.encode());
-        code.push_str(&self.primary_exchange.encode());
-        code.push_str(&self.currency.encode());
-        code.push_str(&self.local_symbol.encode());
-        code.push_str(&self.trading_class.encode());
-        code.push_str(&self.include_expired.encode());
-        code.push_str(&self.sec_id_type.encode());
-        code.push_str(&self.sec_id.encode());
-        code
+    fn encode(&self, buf: &mut BytesMut) {
+        self.con_id.encode(buf);
+        self.symbol.encode(buf);
+        self.sec_type.encode(buf);
+        self.last_trade_date_or_contract_month.encode(buf);
+        self.strike.encode(buf);
+        self.right.encode(buf);
+        self.multiplier.encode(buf);
+        self.exchange.encode(buf);
+        self.primary_exchange.encode(buf);
+        self.currency.encode(buf);
+        self.local_symbol.encode(buf);
+        self.trading_class.encode(buf);
+        self.include_expired.encode(buf);
+        self.sec_id_type.encode(buf);
+        self.sec_id.encode(buf);
     }
 }
 
 impl Contract {
-    pub fn encode_for_order(&self) -> String {
-        let mut code = String::new();
-        code.push_str(&self.con_id.encode());
-        code.push_str(&self.symbol.encode());
-        code.push_str(&self.sec_type.encode());
-        code.push_str(&self.last_trade_date_or_contract_month.encode());
-        code.push_str(&self.strike.encode());
-        code.push_str(&self.right.encode());
-        code.push_str(&self.multiplier.encode());
-        code.push_str(&self.exchange.encode());
-        code.push_str(&self.primary_exchange.encode());
-        code.push_str(&self.currency.encode());
-        code.push_str(&self.local_symbol.encode());
-        code.push_str(&self.trading_class.encode());
-        code.push_str(&self.sec_id_type.encode());
-        code.push_str(&self.sec_id.encode());
-        code
+    pub fn encode_for_order(&self, buf: &mut BytesMut) {
+        self.con_id.encode(buf);
+        self.symbol.encode(buf);
+        self.sec_type.encode(buf);
+        self.last_trade_date_or_contract_month.encode(buf);
+        self.strike.encode(buf);
+        self.right.encode(buf);
+        self.multiplier.encode(buf);
+        self.exchange.encode(buf);
+        self.primary_exchange.encode(buf);
+        self.currency.encode(buf);
+        self.local_symbol.encode(buf);
+        self.trading_class.encode(buf);
+        self.sec_id_type.encode(buf);
+        self.sec_id.encode(buf);
     }
 
-    pub fn encode_for_ticker(&self) -> String {
-        let mut code = String::new();
-        code.push_str(&self.con_id.encode());
-        code.push_str(&self.symbol.encode());
-        code.push_str(&self.sec_type.encode());
-        code.push_str(&self.last_trade_date_or_contract_month.encode());
-        code.push_str(&self.strike.encode());
-        code.push_str(&self.right.encode());
-        code.push_str(&self.multiplier.encode());
-        code.push_str(&self.exchange.encode());
-        code.push_str(&self.primary_exchange.encode());
-        code.push_str(&self.currency.encode());
-        code.push_str(&self.local_symbol.encode());
-        code.push_str(&self.trading_class.encode());
-        code
+    pub fn encode_for_ticker(&self, buf: &mut BytesMut) {
+        self.con_id.encode(buf);
+        self.symbol.encode(buf);
+        self.sec_type.encode(buf);
+        self.last_trade_date_or_contract_month.encode(buf);
+        self.strike.encode(buf);
+        self.right.encode(buf);
+        self.multiplier.encode(buf);
+        self.exchange.encode(buf);
+        self.primary_exchange.encode(buf);
+        self.currency.encode(buf);
+        self.local_symbol.encode(buf);
+        self.trading_class.encode(buf);
     }
 
-    pub fn encode_for_hist_data(&self) -> String {
-        let mut code = String::new();
-        code.push_str(&self.con_id.encode());
-        code.push_str(&self.symbol.encode());
-        code.push_str(&self.sec_type.encode());
-        code.push_str(&self.last_trade_date_or_contract_month.encode());
-        code.push_str(&self.strike.encode());
-        code.push_str(&self.right.encode());
-        code.push_str(&self.multiplier.encode());
-        code.push_str(&self.exchange.encode());
-        code.push_str(&self.primary_exchange.encode());
-        code.push_str(&self.currency.encode());
-        code.push_str(&self.local_symbol.encode());
-        code.push_str(&self.trading_class.encode());
-        code.push_str(&self.include_expired.encode());
-        code
+    pub fn encode_for_hist_data(&self, buf: &mut BytesMut) {
+        self.con_id.encode(buf);
+        self.symbol.encode(buf);
+        self.sec_type.encode(buf);
+        self.last_trade_date_or_contract_month.encode(buf);
+        self.strike.encode(buf);
+        self.right.encode(buf);
+        self.multiplier.encode(buf);
+        self.exchange.encode(buf);
+        self.primary_exchange.encode(buf);
+        self.currency.encode(buf);
+        self.local_symbol.encode(buf);
+        self.trading_class.encode(buf);
+        self.include_expired.encode(buf);
+    }
+
+    /// Reads the fields written by `encode`.
+    pub(crate) fn decode(it: &mut FieldCursor) -> Result<Self, DecodeError> {
+        let mut contract = Contract::decode_for_hist_data(it)?;
+        contract.sec_id_type = it.opt()?;
+        contract.sec_id = it.opt()?;
+        Ok(contract)
+    }
+
+    /// Reads the fields written by `encode_for_order`.
+    pub(crate) fn decode_for_order(it: &mut FieldCursor) -> Result<Self, DecodeError> {
+        let mut contract = Contract::decode_for_ticker(it)?;
+        contract.sec_id_type = it.opt()?;
+        contract.sec_id = it.opt()?;
+        Ok(contract)
+    }
+
+    /// Reads the fields written by `encode_for_ticker`.
+    pub(crate) fn decode_for_ticker(it: &mut FieldCursor) -> Result<Self, DecodeError> {
+        Ok(Contract {
+            con_id: it.opt()?,
+            symbol: it.opt()?,
+            sec_type: it.opt()?,
+            last_trade_date_or_contract_month: it.opt()?,
+            strike: it.opt()?,
+            right: it.opt()?,
+            multiplier: it.opt()?,
+            exchange: it.opt()?,
+            primary_exchange: it.opt()?,
+            currency: it.opt()?,
+            local_symbol: it.opt()?,
+            trading_class: it.opt()?,
+            ..Default::default()
+        })
+    }
+
+    /// Reads the fields written by `encode_for_hist_data`.
+    pub(crate) fn decode_for_hist_data(it: &mut FieldCursor) -> Result<Self, DecodeError> {
+        let mut contract = Contract::decode_for_ticker(it)?;
+        contract.include_expired = it.opt()?;
+        Ok(contract)
     }
 
     pub fn stock_spread_smart_usd(contract_1: &Contract, ratio_1: i32, contract_2: &Contract, ratio_2: i32) -> Option<Contract> {
@@ -155,7 +196,8 @@ impl Contract {
     }
 }
 
-#[derive(Default,Debug)]
+#[derive(Default,Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
 pub struct ContractDetails {
     pub contract: Option<Contract>,
     pub market_name: Option<String>,
@@ -183,6 +225,11 @@ pub struct ContractDetails {
     pub real_expiration_date: Option<String>,
     pub last_trade_time: Option<String>,
     pub stock_type: Option<String>,
+    pub min_size: Option<Decimal>,
+    pub size_increment: Option<Decimal>,
+    pub suggested_size_increment: Option<Decimal>,
+    pub last_trade_date: Option<String>,
+    pub ineligibility_reasons: Option<Vec<(String, String)>>,
     pub cusip: Option<String>,
     pub ratings: Option<String>,
     pub desc_append: Option<String>,
@@ -226,6 +273,7 @@ impl ContractDetails {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ContractDescription {
     contract: Option<Contract>,
     derivative_sec_types_list: Option<Vec<String>>,
@@ -1,19 +1,141 @@
-use num_derive::FromPrimitive;
-use num_traits::FromPrimitive;
+use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::{FromPrimitive, ToPrimitive};
+use std::convert::TryFrom;
 use std::str::FromStr;
 use crate::utils::ib_message::Encodable;
 use crate::utils::ib_message::Decodable;
+use bytes::{BufMut, BytesMut};
+use std::fmt::Write;
 
 use enum_ordinalize;
 
 pub mod constants {
     pub const CLIENT_VERSION: i32 = 66;
+
+    //first server version supporting a message field, fields are only sent and decoded from this version on
+    pub const MIN_SERVER_VER_PEGGED_TO_BENCHMARK: i32 = 102;
+    pub const MIN_SERVER_VER_MODELS_SUPPORT: i32 = 103;
+    pub const MIN_SERVER_VER_EXT_OPERATOR: i32 = 105;
+    pub const MIN_SERVER_VER_SOFT_DOLLAR_TIER: i32 = 106;
+    pub const MIN_SERVER_VER_PAST_LIMIT: i32 = 109;
+    pub const MIN_SERVER_VER_MD_SIZE_MULTIPLIER: i32 = 110;
+    pub const MIN_SERVER_VER_CASH_QTY: i32 = 111;
+    pub const MIN_SERVER_VER_REQ_SMART_COMPONENTS: i32 = 114;
+    pub const MIN_SERVER_VER_AGG_GROUP: i32 = 121;
+    pub const MIN_SERVER_VER_UNDERLYING_INFO: i32 = 122;
+    pub const MIN_SERVER_VER_SYNT_REALTIME_BARS: i32 = 124;
+    pub const MIN_SERVER_VER_MARKET_RULES: i32 = 126;
+    pub const MIN_SERVER_VER_MARKET_CAP_PRICE: i32 = 131;
+    pub const MIN_SERVER_VER_PRE_OPEN_BID_ASK: i32 = 132;
+    pub const MIN_SERVER_VER_REAL_EXPIRATION_DATE: i32 = 134;
+    pub const MIN_SERVER_VER_LAST_LIQUIDITY: i32 = 136;
+    pub const MIN_SERVER_VER_DECISION_MAKER: i32 = 138;
+    pub const MIN_SERVER_VER_MIFID_EXECUTION: i32 = 139;
+    pub const MIN_SERVER_VER_AUTO_PRICE_FOR_HEDGE: i32 = 141;
+    pub const MIN_SERVER_VER_WHAT_IF_EXT_FIELDS: i32 = 142;
+    pub const MIN_SERVER_VER_ORDER_CONTAINER: i32 = 145;
+    pub const MIN_SERVER_VER_D_PEG_ORDERS: i32 = 148;
     pub const MIN_SERVER_VER_PRICE_MGMT_ALGO: i32 = 151;
+    pub const MIN_SERVER_VER_STOCK_TYPE: i32 = 152;
+    pub const MIN_SERVER_VER_DURATION: i32 = 158;
+    pub const MIN_SERVER_VER_POST_TO_ATS: i32 = 160;
+    pub const MIN_SERVER_VER_AUTO_CANCEL_PARENT: i32 = 162;
+    pub const MIN_SERVER_VER_FRACTIONAL_SIZE_SUPPORT: i32 = 163;
+    pub const MIN_SERVER_VER_SIZE_RULES: i32 = 164;
+    pub const MIN_SERVER_VER_ADVANCED_ORDER_REJECT: i32 = 166;
+    pub const MIN_SERVER_VER_MANUAL_ORDER_TIME: i32 = 169;
+    pub const MIN_SERVER_VER_PEGBEST_PEGMID_OFFSETS: i32 = 170;
+    pub const MIN_SERVER_VER_BOND_ISSUERID: i32 = 176;
+    pub const MIN_SERVER_VER_FA_PROFILE_DESUPPORT: i32 = 177;
+    pub const MIN_SERVER_VER_PENDING_PRICE_REVISION: i32 = 178;
+    pub const MIN_SERVER_VER_FUND_DATA_FIELDS: i32 = 179;
+    pub const MIN_SERVER_VER_LAST_TRADE_DATE: i32 = 182;
+    pub const MIN_SERVER_VER_CUSTOMER_ACCOUNT: i32 = 183;
+    pub const MIN_SERVER_VER_PROFESSIONAL_CUSTOMER: i32 = 184;
+    pub const MIN_SERVER_VER_BOND_ACCRUED_INTEREST: i32 = 185;
+    pub const MIN_SERVER_VER_INELIGIBILITY_REASONS: i32 = 186;
+    pub const MIN_SERVER_VER_RFQ_FIELDS: i32 = 187;
+    pub const MIN_SERVER_VER_INCLUDE_OVERNIGHT: i32 = 189;
+    pub const MIN_SERVER_VER_UNDO_RFQ_FIELDS: i32 = 190;
+    pub const MIN_SERVER_VER_PERM_ID_AS_LONG: i32 = 191;
+    pub const MIN_SERVER_VER_CME_TAGGING_FIELDS: i32 = 192;
+    pub const MIN_SERVER_VER_CME_TAGGING_FIELDS_IN_OPEN_ORDER: i32 = 193;
+    pub const MIN_SERVER_VER_ERROR_TIME: i32 = 194;
+    pub const MIN_SERVER_VER_FULL_ORDER_PREVIEW_FIELDS: i32 = 195;
+    pub const MIN_SERVER_VER_HISTORICAL_DATA_END: i32 = 196;
+    pub const MIN_SERVER_VER_SUBMITTER: i32 = 198;
+    pub const MIN_SERVER_VER_IMBALANCE_ONLY: i32 = 199;
+    /// Message ids are sent as 4 byte integers, and ids above `PROTOBUF_MSG_ID` carry protobuf messages.
+    pub const MIN_SERVER_VER_PROTOBUF: i32 = 201;
+
+    /// Offset added to the id of a message sent as protobuf.
+    pub const PROTOBUF_MSG_ID: i32 = 200;
+
     pub const MIN_CLIENT_VER: i32 = 100;
-    pub const MAX_CLIENT_VER: i32 = MIN_SERVER_VER_PRICE_MGMT_ALGO;
+    #[cfg(not(feature = "protobuf"))]
+    pub const MAX_CLIENT_VER: i32 = MIN_SERVER_VER_SIZE_RULES;
+    #[cfg(feature = "protobuf")]
+    pub const MAX_CLIENT_VER: i32 = MIN_SERVER_VER_PROTOBUF;
+}
+
+pub mod error_codes {
+    pub const MAX_RATE_EXCEEDED: i32 = 100;
+    pub const HISTORICAL_DATA_ERROR: i32 = 162;
+    pub const NO_SECURITY_DEFINITION: i32 = 200;
+    pub const ORDER_REJECTED: i32 = 201;
+    pub const CLIENT_ID_IN_USE: i32 = 326;
+    pub const NOT_CONNECTED: i32 = 504;
+    pub const CONNECTIVITY_LOST: i32 = 1100;
+    pub const CONNECTIVITY_RESTORED_DATA_LOST: i32 = 1101;
+    pub const CONNECTIVITY_RESTORED_DATA_KEPT: i32 = 1102;
+    pub const CONNECTIVITY_BROKEN: i32 = 2110;
+    pub const ORDER_WARNING: i32 = 399;
+    pub const DELAYED_DATA_DISPLAYED: i32 = 10167;
+    //order errors, 103 (duplicate order id) up to 111 (time in force incompatible with the order type)
+    pub const DUPLICATE_ORDER_ID: i32 = 103;
+    pub const TIF_INCOMPATIBLE: i32 = 111;
+    pub const ORDER_NOT_FOUND: i32 = 135;
+    pub const HISTORICAL_DATA_SERVICE_ERROR: i32 = 165;
+    pub const HISTORICAL_DATA_QUERY_NOT_FOUND: i32 = 366;
+    pub const MARKET_DATA_NOT_SUBSCRIBED: i32 = 354;
+    //10089 up to 10091: (part of) the market data needs an additional subscription for the API
+    pub const MARKET_DATA_NEEDS_API_SUBSCRIPTION: i32 = 10089;
+    pub const PARTIAL_MARKET_DATA_NEEDS_API_SUBSCRIPTION: i32 = 10091;
+    pub const DELAYED_DATA_NOT_ENABLED: i32 = 10168;
+
+    /// Status changes of the connections between TWS and IB's data farms.
+    pub fn is_farm_status(code: i32) -> bool {
+        matches!(code, 2103..=2108 | 2119 | 2157 | 2158)
+    }
+
+    /// Farm status messages reporting a working connection.
+    pub fn is_farm_connected(code: i32) -> bool {
+        matches!(code, 2104 | 2106 | 2158)
+    }
+
+    /// Errors rejecting an order or an order change.
+    pub fn is_order_rejection(code: i32) -> bool {
+        matches!(code, ORDER_REJECTED | DUPLICATE_ORDER_ID..=TIF_INCOMPATIBLE | ORDER_NOT_FOUND)
+    }
+
+    /// Errors failing a historical data request.
+    pub fn is_historical_data_error(code: i32) -> bool {
+        matches!(code, HISTORICAL_DATA_ERROR | HISTORICAL_DATA_SERVICE_ERROR | HISTORICAL_DATA_QUERY_NOT_FOUND)
+    }
+
+    /// Errors about missing market data permissions.
+    pub fn is_market_data_error(code: i32) -> bool {
+        matches!(code, MARKET_DATA_NOT_SUBSCRIBED | MARKET_DATA_NEEDS_API_SUBSCRIPTION..=PARTIAL_MARKET_DATA_NEEDS_API_SUBSCRIPTION |
+            DELAYED_DATA_NOT_ENABLED)
+    }
+
+    /// Informational messages that do not fail the request they refer to.
+    pub fn is_warning(code: i32) -> bool {
+        matches!(code, 2100..=2199 | ORDER_WARNING | DELAYED_DATA_DISPLAYED)
+    }
 }
 
-#[derive(FromPrimitive)]
+#[derive(FromPrimitive,ToPrimitive,Debug,Clone)]
 pub enum Incoming {
     TickPrice                                = 1,
     TickSize                                 = 2,
@@ -91,7 +213,13 @@ pub enum Incoming {
     TickByTick                              = 99,
     OrderBound                               = 100,
     CompletedOrder                            = 101,
-    CompletedOrdersEnd                        = 102
+    CompletedOrdersEnd                        = 102,
+    ReplaceFaEnd                              = 103,
+    WshMetaData                               = 104,
+    WshEventData                              = 105,
+    HistoricalSchedule                        = 106,
+    UserInfo                                  = 107,
+    HistoricalDataEnd                         = 108
 }
 
 impl FromStr for Incoming {
@@ -108,7 +236,15 @@ impl FromStr for Incoming {
     }
 }
 
-#[derive(enum_ordinalize::Ordinalize)]
+impl Decodable for Incoming {}
+
+impl Encodable for Incoming {
+    fn encode(&self, buf: &mut BytesMut) {
+        let _ = write!(buf, "{}\0", self.to_i32().unwrap_or_default());
+    }
+}
+
+#[derive(enum_ordinalize::Ordinalize,Clone,Copy,PartialEq,Eq)]
 pub enum Outgoing {
     // outgoing message IDs
     ReqMktData                  = 1, 
@@ -188,19 +324,40 @@ pub enum Outgoing {
     ReqCompletedOrders          = 99 
 }
 
+impl Outgoing {
+    /// Kind of an encoded message without its length prefix. The message id is either a text field
+    /// or, from server version `MIN_SERVER_VER_PROTOBUF` on, a 4 byte integer.
+    pub fn of_payload(payload: &[u8]) -> Option<Self> {
+        match payload {
+            //text ids start with a digit, binary ones with a zero byte
+            [0, b1, b2, b3, ..] => i8::try_from(i32::from_be_bytes([0, *b1, *b2, *b3])).ok(),
+            _ => payload.split(|byte| *byte == 0).next()
+                .and_then(|id| std::str::from_utf8(id).ok())
+                .and_then(|id| id.parse().ok())
+        }.and_then(Outgoing::from_ordinal)
+    }
+}
+
 impl Encodable for Outgoing {
-    fn encode(&self) -> String {
-        let ord = self.ordinal();
-        ord.to_string() + "\0"
+    fn encode(&self, buf: &mut BytesMut) {
+        let _ = write!(buf, "{}\0", self.ordinal());
+    }
+}
+
+impl FromStr for Outgoing {
+    type Err = ParseEnumError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().ok().and_then(Outgoing::from_ordinal).ok_or(ParseEnumError)
     }
 }
 
+impl Decodable for Outgoing {}
+
 #[derive(Debug)]
 pub struct ParseEnumError;
-// Some enums are only for decoding and implement the FromStr trait
-// Some enums are only for encoding and implement the encode method (might make it a trait)
+//enums convert to and from IB's wire codes with `Encodable` and `FromStr`
 
-#[derive(FromPrimitive,Debug,Clone)]
+#[derive(FromPrimitive,ToPrimitive,Debug,Clone)]
 pub enum TickType {
     BidSize,
     Bid,
@@ -311,6 +468,12 @@ impl FromStr for TickType {
 
 impl Decodable for TickType {}
 
+impl Encodable for TickType {
+    fn encode(&self, buf: &mut BytesMut) {
+        let _ = write!(buf, "{}\0", self.to_i32().unwrap_or_default());
+    }
+}
+
 #[derive(Debug,Clone)]
 pub enum GenericTickType {
     ShortableData,
@@ -323,8 +486,8 @@ pub enum GenericTickType {
 }
 
 impl Encodable for GenericTickType {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             GenericTickType::ShortableData => "236",
             GenericTickType::HistoricData => "165",
             GenericTickType::OptionHistoricalVol => "10",
@@ -332,11 +495,30 @@ impl Encodable for GenericTickType {
             GenericTickType::OptionOpenInterest => "101",
             GenericTickType::AuctionData => "225",
             GenericTickType::OptionVolume => "100",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
-#[derive(Debug,Clone)]
+impl FromStr for GenericTickType {
+    type Err = ParseEnumError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let res = match s {
+            "236" => GenericTickType::ShortableData,
+            "165" => GenericTickType::HistoricData,
+            "10" => GenericTickType::OptionHistoricalVol,
+            "106" => GenericTickType::OptionImpliedVol,
+            "101" => GenericTickType::OptionOpenInterest,
+            "225" => GenericTickType::AuctionData,
+            "100" => GenericTickType::OptionVolume,
+            &_ => return Err(ParseEnumError)
+        };
+        Ok(res)
+    }
+}
+
+impl Decodable for GenericTickType {}
+
+#[derive(Debug,PartialEq,Eq,Clone)]
 pub enum MarketDataType {
     RealTime = 1,
     Frozen = 2,
@@ -345,13 +527,13 @@ pub enum MarketDataType {
 }
 
 impl Encodable for MarketDataType {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             MarketDataType::RealTime => "1\0",
             MarketDataType::Frozen => "2\0",
             MarketDataType::Delayed => "3\0",
             MarketDataType::FrozenDelayed => "4\0"
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -381,16 +563,34 @@ pub enum FundamentalDataType {
 }
 
 impl Encodable for FundamentalDataType {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             FundamentalDataType::Snapshot => "ReportSnapShot\0",
             FundamentalDataType::FinSummary => "ReportsFinSummary\0",
             FundamentalDataType::Ratios => "ReportRatios\0",
             FundamentalDataType::FinStatements => "ReportsFinStatements\0",
             FundamentalDataType::Estimates => "RESC\0",
-        }.to_string()
+        }.as_bytes());
+    }
+}
+
+impl FromStr for FundamentalDataType {
+    type Err = ParseEnumError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let res = match s {
+            "ReportSnapShot" => FundamentalDataType::Snapshot,
+            "ReportsFinSummary" => FundamentalDataType::FinSummary,
+            "ReportRatios" => FundamentalDataType::Ratios,
+            "ReportsFinStatements" => FundamentalDataType::FinStatements,
+            "RESC" => FundamentalDataType::Estimates,
+            &_ => return Err(ParseEnumError)
+        };
+        Ok(res)
     }
 }
+
+impl Decodable for FundamentalDataType {}
+
 #[derive(Debug,PartialEq,Eq,Clone)]
 pub enum SecType {
     Stock,
@@ -408,8 +608,8 @@ pub enum SecType {
 }
 
 impl Encodable for SecType {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             SecType::Stock => "STK\0",
             SecType::Option => "OPT\0",
             SecType::Future => "FUT\0",
@@ -422,7 +622,7 @@ impl Encodable for SecType {
             SecType::Commodity => "CMDTY\0",
             SecType::News => "NEWS\0",
             SecType::MutualFund => "FUND\0",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -457,12 +657,12 @@ pub enum OptionRight {
 }
 
 impl Encodable for  OptionRight {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             OptionRight::Undefined => "0\0",
             OptionRight::Put => "PUT\0",
             OptionRight::Call => "CALL\0",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -490,14 +690,28 @@ pub enum SecIdType {
 }
 
 impl Encodable for SecIdType {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             SecIdType::Isin => "ISIN\0",
             SecIdType::Cusip => "CUSIP\0",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
+impl FromStr for SecIdType {
+    type Err = ParseEnumError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let res = match s {
+            "ISIN" => SecIdType::Isin,
+            "CUSIP" => SecIdType::Cusip,
+            &_ => return Err(ParseEnumError)
+        };
+        Ok(res)
+    }
+}
+
+impl Decodable for SecIdType {}
+
 #[derive(Debug,Clone)]
 pub enum ComboAction {
     Buy,
@@ -506,12 +720,12 @@ pub enum ComboAction {
 }
 
 impl Encodable for ComboAction {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             ComboAction::Buy => "BUY\0",
             ComboAction::Sell => "SELL\0",
             ComboAction::ShortSell => "SSELL\0",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -538,13 +752,13 @@ pub enum OptionOpenClose {
 }
 
 impl Encodable for OptionOpenClose {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             OptionOpenClose::Same => "0\0",
             OptionOpenClose::Open => "1\0",
             OptionOpenClose::Close => "2\0",
             OptionOpenClose::Unknown => "3\0",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -571,12 +785,12 @@ pub enum ShortSaleSlot {
 }
 
 impl Encodable for ShortSaleSlot {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             ShortSaleSlot::NoSlot => "0\0",
             ShortSaleSlot::Broker => "1\0",
             ShortSaleSlot::ThirdParty => "2\0"
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -603,13 +817,13 @@ pub enum Action {
 }
 
 impl Encodable for Action {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             Action::Buy => "BUY\0",
             Action::Sell => "SELL\0",
             Action::SellShort => "SSELL\0",
             Action::SellLong => "SLONG\0",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -661,11 +875,12 @@ pub enum OrderType {
     RelativeMarket,
     Volatility,
     PeggedToBenchmark,
+    PeggedToBest,
 }
 
 impl Encodable for OrderType {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             OrderType::NoOrderType => "None\0",
             OrderType::Limit => "LMT\0",
             OrderType::Market => "MKT\0",
@@ -687,11 +902,12 @@ impl Encodable for OrderType {
             OrderType::StopWithProtection => "STP PRT\0",
             OrderType::TrailingStop => "TRAIL\0",
             OrderType::TrailingStopLimit => "TRAIL LIMIT\0",
-            OrderType::RelativeLimit => "Rel + LMT\0",
-            OrderType::RelativeMarket => "Rel + MKT\0",
+            OrderType::RelativeLimit => "REL + LMT\0",
+            OrderType::RelativeMarket => "REL + MKT\0",
             OrderType::Volatility => "VOL\0",
             OrderType::PeggedToBenchmark => "PEG BENCH\0",
-        }.to_string()
+            OrderType::PeggedToBest => "PEG BEST\0",
+        }.as_bytes());
     }
 }
 
@@ -724,6 +940,7 @@ impl FromStr for OrderType {
             "REL + MKT" => Ok(OrderType::RelativeMarket),
             "VOL" => Ok(OrderType::Volatility),
             "PEG BENCH" => Ok(OrderType::PeggedToBenchmark),
+            "PEG BEST" => Ok(OrderType::PeggedToBest),
             &_ => Err(ParseEnumError)
         }
     }
@@ -749,8 +966,8 @@ pub enum TriggerMethod {
 }
 
 impl Encodable for TriggerMethod {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             TriggerMethod::Default => "0\0",
             TriggerMethod::DoubleBidAsk => "1\0",
             TriggerMethod::Last => "2\0",
@@ -758,7 +975,7 @@ impl Encodable for TriggerMethod {
             TriggerMethod::BidAsk => "4\0",
             TriggerMethod::LastOrBidAsk => "7\0",
             TriggerMethod::MidPoint => "8\0"
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -792,8 +1009,8 @@ pub enum TimeInForce {
 }
 
 impl Encodable for TimeInForce {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             TimeInForce::Day => "DAY\0",
             TimeInForce::GoodTillCancel => "GTC\0",
             TimeInForce::ImmediateOrCancel => "IOC\0",
@@ -801,7 +1018,7 @@ impl Encodable for TimeInForce {
             TimeInForce::GoodOnOpen => "OPG\0",
             TimeInForce::FillOrKill => "FOK\0",
             TimeInForce::DayUntilCancel => "DTC\0",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -837,8 +1054,8 @@ pub enum Rule80A {
 }
 
 impl Encodable for Rule80A {
-    fn encode(&self) -> String {
-        match *self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match *self {
             Rule80A::Individual => "I\0",
             Rule80A::Agency => "A\0",
             Rule80A::AgentOtherMember => "W\0",
@@ -848,7 +1065,7 @@ impl Encodable for Rule80A {
             Rule80A::IndividualPT => "K\0",
             Rule80A::AgencyPT => "Y\0",
             Rule80A::AgentOtherMemberPT => "N\0",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -879,11 +1096,11 @@ pub enum OrderOpenClose {
 }
 
 impl Encodable for OrderOpenClose {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             OrderOpenClose::Open => "O\0",
             OrderOpenClose::Close => "C\0",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -908,12 +1125,12 @@ pub enum Origin {
 }
 
 impl Encodable for Origin {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             Origin::Customer => "0\0",
             Origin::Firm => "1\0",
             Origin::Unknown => "2\0"
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -940,13 +1157,13 @@ pub enum AuctionStrategy {
 }
 
 impl Encodable for AuctionStrategy {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             AuctionStrategy::NoAuctionStrategy => "0\0",
             AuctionStrategy::Match => "1\0",
             AuctionStrategy::Improvement => "2\0",
             AuctionStrategy::Transparent => "3\0",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -975,13 +1192,13 @@ pub enum OCAType {
 }
 
 impl Encodable for OCAType {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             OCAType::NoOCAType => "0\0",
             OCAType::CancelWithBlock => "1\0",
             OCAType::ReduceWithBlock => "2\0",
             OCAType::ReduceNonBlock => "3\0",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -1009,12 +1226,12 @@ pub enum VolatilityType {
 }
 
 impl Encodable for VolatilityType {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             VolatilityType::NoVolType => "0\0",
             VolatilityType::Daily => "1\0",
             VolatilityType::Annual => "2\0",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -1040,12 +1257,12 @@ pub enum ReferencePriceType {
 }
 
 impl Encodable for ReferencePriceType {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             ReferencePriceType::NoRefPriceType => "0\0",
             ReferencePriceType::Average => "1\0",
             ReferencePriceType::BidOrAsk => "2\0"
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -1069,10 +1286,10 @@ pub enum BasisPointsType {
 }
 
 impl Encodable for BasisPointsType {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             BasisPointsType::Undefined => "?\0",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -1098,14 +1315,14 @@ pub enum HedgeType {
 }
 
 impl Encodable for HedgeType {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             HedgeType::Undefined => "?\0",
             HedgeType::Delta => "D\0",
             HedgeType::Beta => "B\0",
             HedgeType::Forex => "F\0",
             HedgeType::Pair => "P\0",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -1133,12 +1350,12 @@ pub enum ClearingIntent {
 }
 
 impl Encodable for ClearingIntent {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             ClearingIntent::InteractiveBrokers => "IB\0",
             ClearingIntent::Away => "Away\0",
             ClearingIntent::PTA => "PTA\0",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -1163,11 +1380,11 @@ pub enum UsePriceMgmtAlgo {
 }
 
 impl Encodable for UsePriceMgmtAlgo {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             UsePriceMgmtAlgo::DontUse => "0\0",
             UsePriceMgmtAlgo::Use => "1\0",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -1191,11 +1408,11 @@ pub enum Side {
 }
 
 impl Encodable for Side {
-    fn encode(&self) -> String {
-        match self {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(match self {
             Side::Long => "BOT\0",
             Side::Short => "SLD\0",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
@@ -1224,9 +1441,8 @@ pub enum OrderConditionType {
 }
 
 impl Encodable for OrderConditionType {
-    fn encode(&self) -> String {
-        let ord = self.ordinal();
-        ord.to_string() + "\0"
+    fn encode(&self, buf: &mut BytesMut) {
+        let _ = write!(buf, "{}\0", self.ordinal());
     }
 }
 
@@ -1246,6 +1462,7 @@ impl FromStr for OrderConditionType {
 
 impl Decodable for OrderConditionType {}
 
+#[derive(Debug,Clone)]
 pub enum IBAccountField {
     AccountType,
     NetLiquidation,
@@ -1318,6 +1535,14 @@ impl FromStr for IBAccountField {
 
 impl Decodable for IBAccountField {}
 
+impl Encodable for IBAccountField {
+    fn encode(&self, buf: &mut BytesMut) {
+        //the keys TWS sends are the variant names
+        let _ = write!(buf, "{:?}\0", self);
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
 pub enum HistoricalDataType {
     //AdjustedLast is not included here, because it's special!
     Trades,
@@ -1336,9 +1561,9 @@ pub enum HistoricalDataType {
 }
 
 impl Encodable for HistoricalDataType {
-    fn encode(&self) -> String {
+    fn encode(&self, buf: &mut BytesMut) {
         use HistoricalDataType::*;
-        match self {
+        buf.put_slice(match self {
             Trades => "TRADES\0",
             Midpoint => "MIDPOINT\0",
             Bid => "BID\0",
@@ -1353,10 +1578,37 @@ impl Encodable for HistoricalDataType {
             HistoricalDataType::YieldBidAsk => "YIELD_BID_ASK\0",
             HistoricalDataType::YieldLast => "YIELD_LAST\0"
 
-        }.to_string()
+        }.as_bytes());
     }
 }
 
+impl FromStr for HistoricalDataType {
+    type Err = ParseEnumError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use HistoricalDataType::*;
+        let res = match s {
+            "TRADES" => Trades,
+            "MIDPOINT" => Midpoint,
+            "BID" => Bid,
+            "ASK" => Ask,
+            "BID_ASK" => BidAsk,
+            "HISTORICAL_VOLATILITY" => HistoricalVolatility,
+            "OPTION_IMPLIED_VOLATILITY" => OptionImpliedVolatility,
+            "REBATE_RATE" => RebateRate,
+            "FEE_RATE" => FeeRate,
+            "YIELD_BID" => YieldBid,
+            "YIELD_ASK" => YieldAsk,
+            "YIELD_BID_ASK" => YieldBidAsk,
+            "YIELD_LAST" => YieldLast,
+            &_ => return Err(ParseEnumError)
+        };
+        Ok(res)
+    }
+}
+
+impl Decodable for HistoricalDataType {}
+
+#[derive(Debug,Clone,PartialEq)]
 pub enum HistoricalDataBarSize {
     OneSec,
     FiveSecs,
@@ -1382,9 +1634,9 @@ pub enum HistoricalDataBarSize {
 }
 
 impl Encodable for HistoricalDataBarSize {
-    fn encode(&self) -> String {
+    fn encode(&self, buf: &mut BytesMut) {
         use HistoricalDataBarSize::*;
-        match self {
+        buf.put_slice(match self {
             OneSec => "1 secs\0",
             FiveSecs => "5 secs\0",
             TenSecs => "10 secs\0",
@@ -1406,10 +1658,45 @@ impl Encodable for HistoricalDataBarSize {
             OneDay => "1 day\0",
             OneWeek => "1 week\0",
             OneMonth => "1 month\0",
-        }.to_string()
+        }.as_bytes());
     }
 }
 
+impl FromStr for HistoricalDataBarSize {
+    type Err = ParseEnumError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use HistoricalDataBarSize::*;
+        let res = match s {
+            "1 secs" => OneSec,
+            "5 secs" => FiveSecs,
+            "10 secs" => TenSecs,
+            "15 secs" => FifteenSecs,
+            "30 secs" => ThirtySecs,
+            "1 min" => OneMin,
+            "2 mins" => TwoMins,
+            "3 mins" => ThreeMins,
+            "5 mins" => FiveMins,
+            "10 mins" => TenMins,
+            "15 mins" => FifteenMins,
+            "20 mins" => TwentyMins,
+            "30 mins" => ThirtyMins,
+            "1 hour" => OneHour,
+            "2 hours" => TwoHours,
+            "3 hours" => ThreeHours,
+            "4 hours" => FourHours,
+            "8 hours" => EightHours,
+            "1 day" => OneDay,
+            "1 week" => OneWeek,
+            "1 month" => OneMonth,
+            &_ => return Err(ParseEnumError)
+        };
+        Ok(res)
+    }
+}
+
+impl Decodable for HistoricalDataBarSize {}
+
+#[derive(Debug,Clone,PartialEq)]
 pub enum HistoricalDataDuration {
     Seconds(i32),
     Days(i32),
@@ -1419,14 +1706,68 @@ pub enum HistoricalDataDuration {
 }
 
 impl Encodable for HistoricalDataDuration {
-    fn encode(&self) -> String {
+    fn encode(&self, buf: &mut BytesMut) {
         use HistoricalDataDuration::*;
-        match self {
-            Seconds(count) => count.to_string() + " S\0",
-            Days(count) => count.to_string() + " D\0",
-            Weeks(count) => count.to_string() + " W\0",
-            Months(count) => count.to_string() + " M\0",
-            Years(count) => count.to_string() + " Y\0"
-        }
+        let (count, unit) = match self {
+            Seconds(count) => (count, "S"),
+            Days(count) => (count, "D"),
+            Weeks(count) => (count, "W"),
+            Months(count) => (count, "M"),
+            Years(count) => (count, "Y")
+        };
+        let _ = write!(buf, "{} {}\0", count, unit);
+    }
+}
+
+impl FromStr for HistoricalDataDuration {
+    type Err = ParseEnumError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use HistoricalDataDuration::*;
+        let (count, unit) = s.split_once(' ').ok_or(ParseEnumError)?;
+        let count = count.parse().map_err(|_| ParseEnumError)?;
+        let res = match unit {
+            "S" => Seconds(count),
+            "D" => Days(count),
+            "W" => Weeks(count),
+            "M" => Months(count),
+            "Y" => Years(count),
+            &_ => return Err(ParseEnumError)
+        };
+        Ok(res)
     }
 }
+
+impl Decodable for HistoricalDataDuration {}
+
+//serialized as the wire code, so e.g. `SecType::Stock` is "STK"
+#[cfg(feature = "serde")]
+macro_rules! serde_as_wire_code {
+    ($($t:ty),*) => {
+        $(
+            impl serde::Serialize for $t {
+                fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    let mut buf = BytesMut::new();
+                    self.encode(&mut buf);
+                    let code = buf.strip_suffix(b"\0").unwrap_or(&buf);
+                    serializer.serialize_str(std::str::from_utf8(code).map_err(serde::ser::Error::custom)?)
+                }
+            }
+
+            impl<'de> serde::Deserialize<'de> for $t {
+                fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    let code = String::deserialize(deserializer)?;
+                    code.parse().map_err(|_| serde::de::Error::custom(format!("Unknown {} code {:?}", stringify!($t), code)))
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "serde")]
+serde_as_wire_code!(
+    Incoming, Outgoing, TickType, GenericTickType, MarketDataType, FundamentalDataType, SecType, OptionRight, SecIdType,
+    ComboAction, OptionOpenClose, ShortSaleSlot, Action, OrderType, TriggerMethod, TimeInForce, Rule80A, OrderOpenClose,
+    Origin, AuctionStrategy, OCAType, VolatilityType, ReferencePriceType, BasisPointsType, HedgeType, ClearingIntent,
+    UsePriceMgmtAlgo, Side, OrderConditionType, IBAccountField, HistoricalDataType, HistoricalDataBarSize,
+    HistoricalDataDuration
+);
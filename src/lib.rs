@@ -1,9 +1,17 @@
 pub mod ib_enums;
+pub mod error;
 mod utils;
 pub mod ib_client;
 mod account;
-mod frame;
+pub mod frame;
+#[cfg(feature = "protobuf")]
+mod proto;
 pub mod ib_contract;
 pub mod order;
+pub mod request;
 pub mod ticker;
-pub mod bars;
\ No newline at end of file
+pub mod bars;
+pub mod recorder;
+pub mod pacing;
+#[cfg(feature = "mock-gateway")]
+pub mod mock_gateway;
@@ -0,0 +1,409 @@
+//! In-process stand-in for TWS / IB Gateway, for testing [`IBClient`](crate::ib_client::IBClient) without a live account.
+//!
+//! The gateway listens on localhost, performs the server side of the handshake and answers
+//! `ReqIds` and `ReqCurrentTime` by itself. Every other request is answered by the handlers
+//! registered with [`MockGateway::on`]. Unsolicited events such as ticks, fills or errors are
+//! sent with [`MockGateway::push`]. Messages that are not made of text fields, such as protobuf
+//! messages, are sent with [`MockGateway::on_raw`] and [`MockGateway::push_raw`]. Handlers
+//! registered with [`MockGateway::on_request`] work with decoded [`IBRequest`]s and answer with
+//! [`IBFrame`]s instead of text fields.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use rs_ib_api::ib_client::{IBClient, ConnectOptions};
+//! use rs_ib_api::ib_enums::Outgoing;
+//! use rs_ib_api::mock_gateway::{MockGateway, frames};
+//!
+//! let gateway = MockGateway::start().await?;
+//! //reply to every contract details request with an empty result
+//! gateway.on(Outgoing::ReqContractData, |msg| vec![frames::contract_data_end(msg[2].parse().unwrap())]);
+//! let client = IBClient::connect(ConnectOptions::new(gateway.port(), 1)).await?;
+//! # Ok(())
+//! # }
+//! ```
+use crate::frame::IBFrame;
+use crate::ib_enums::{constants, error_codes, Outgoing};
+use crate::request::IBRequest;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicI32, Ordering};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use futures::future::{Abortable, AbortHandle};
+
+/// Fields of a message, without the terminating nulls.
+pub type Fields = Vec<String>;
+
+/// Server version the gateway negotiates by default, the newest one the [`frames`] builders
+/// follow.
+pub const DEFAULT_SERVER_VERSION: i32 = constants::MIN_SERVER_VER_SIZE_RULES;
+
+type FieldsHandler = Box<dyn Fn(&[String]) -> Vec<Fields> + Send + Sync>;
+type RawHandler = Box<dyn Fn(&[String]) -> Vec<Vec<u8>> + Send + Sync>;
+type RequestHandler = Box<dyn Fn(&IBRequest) -> Vec<IBFrame> + Send + Sync>;
+
+enum Handler {
+    Fields(FieldsHandler),
+    Raw(RawHandler),
+    Request(RequestHandler)
+}
+
+struct Shared {
+    handlers: Mutex<HashMap<i32, Handler>>,
+    received: Mutex<Vec<Fields>>,
+    received_count: watch::Sender<usize>,
+    connections: Mutex<Vec<mpsc::UnboundedSender<Vec<u8>>>>,
+    rejected_client_ids: Mutex<HashSet<i32>>,
+    next_order_id: AtomicI32,
+    server_version: i32
+}
+
+/// Scriptable fake gateway, shut down when dropped.
+pub struct MockGateway {
+    port: u16,
+    shared: Arc<Shared>,
+    received_rx: watch::Receiver<usize>,
+    abort_handle: AbortHandle
+}
+
+impl MockGateway {
+    /// Starts a gateway on a free localhost port, negotiating `DEFAULT_SERVER_VERSION`.
+    pub async fn start() -> io::Result<Self> {
+        MockGateway::with_server_version(DEFAULT_SERVER_VERSION).await
+    }
+
+    pub async fn with_server_version(server_version: i32) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let port = listener.local_addr()?.port();
+        let (received_count, received_rx) = watch::channel(0);
+        let shared = Arc::new(Shared {
+            handlers: Mutex::new(HashMap::new()),
+            received: Mutex::new(Vec::new()),
+            received_count,
+            connections: Mutex::new(Vec::new()),
+            rejected_client_ids: Mutex::new(HashSet::new()),
+            next_order_id: AtomicI32::new(1),
+            server_version
+        });
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        let accept_shared = shared.clone();
+        tokio::spawn(Abortable::new(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let shared = accept_shared.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = serve(stream, shared).await {
+                        tracing::debug!(error = %err, "Mock gateway connection closed");
+                    }
+                });
+            }
+        }, abort_registration));
+        Ok(MockGateway {
+            port,
+            shared,
+            received_rx,
+            abort_handle
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Answers every message of type `kind` with the frames returned by `handler`.
+    /// The handler gets all fields of the request, starting with the message id.
+    pub fn on<F>(&self, kind: Outgoing, handler: F)
+    where
+    F: Fn(&[String]) -> Vec<Fields> + Send + Sync + 'static,
+    {
+        self.shared.handlers.lock().unwrap().insert(kind.ordinal() as i32, Handler::Fields(Box::new(handler)));
+    }
+
+    /// Like [`on`](MockGateway::on), but the handler returns message payloads which are sent as they
+    /// are, without the length prefix.
+    pub fn on_raw<F>(&self, kind: Outgoing, handler: F)
+    where
+    F: Fn(&[String]) -> Vec<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.shared.handlers.lock().unwrap().insert(kind.ordinal() as i32, Handler::Raw(Box::new(handler)));
+    }
+
+    /// Like [`on`](MockGateway::on), but the handler gets the decoded request and its frames are
+    /// encoded for the negotiated server version. Frames that cannot be encoded are not sent.
+    pub fn on_request<F>(&self, kind: Outgoing, handler: F)
+    where
+    F: Fn(&IBRequest) -> Vec<IBFrame> + Send + Sync + 'static,
+    {
+        self.shared.handlers.lock().unwrap().insert(kind.ordinal() as i32, Handler::Request(Box::new(handler)));
+    }
+
+    /// Sends an unsolicited message to all connected clients.
+    pub fn push(&self, fields: Fields) {
+        self.push_raw(self.shared.encode(&fields));
+    }
+
+    /// Sends an unsolicited message payload as it is to all connected clients.
+    pub fn push_raw(&self, payload: Vec<u8>) {
+        self.shared.connections.lock().unwrap().retain(|conn| conn.send(payload.clone()).is_ok());
+    }
+
+    /// Answers the handshake of `client_id` with error 326, as TWS does for a client id in use.
+    pub fn reject_client_id(&self, client_id: i32) {
+        self.shared.rejected_client_ids.lock().unwrap().insert(client_id);
+    }
+
+    /// Order id handed out on connect and for `ReqIds`.
+    pub fn set_next_order_id(&self, id: i32) {
+        self.shared.next_order_id.store(id, Ordering::SeqCst);
+    }
+
+    /// All messages received from clients so far, including the handshake.
+    pub fn received(&self) -> Vec<Fields> {
+        self.shared.received.lock().unwrap().clone()
+    }
+
+    /// Waits until a message of type `kind` has been received and returns the first one.
+    pub async fn wait_for(&self, kind: Outgoing) -> Fields {
+        let id = (kind.ordinal() as i32).to_string();
+        let mut received_rx = self.received_rx.clone();
+        loop {
+            if let Some(msg) = self.received().into_iter().find(|msg| msg[0] == id) {
+                return msg;
+            }
+            if received_rx.changed().await.is_err() {
+                panic!("Mock gateway stopped");
+            }
+        }
+    }
+
+    /// Drops all client connections, as if TWS had been shut down.
+    pub fn disconnect_all(&self) {
+        self.shared.connections.lock().unwrap().clear();
+    }
+}
+
+impl Drop for MockGateway {
+    fn drop(&mut self) {
+        self.abort_handle.abort();
+        self.disconnect_all();
+    }
+}
+
+fn encode_fields(fields: &[String]) -> Vec<u8> {
+    let mut msg = String::new();
+    for field in fields {
+        msg.push_str(field);
+        msg.push('\0');
+    }
+    msg.into_bytes()
+}
+
+fn encode_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Reads a message without its length prefix.
+async fn read_frame<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).await?;
+    let mut msg = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut msg).await?;
+    Ok(msg)
+}
+
+/// Splits a message into its fields. A binary message id, as sent from server version
+/// `MIN_SERVER_VER_PROTOBUF` on, is returned as text like the other fields.
+fn split_fields(mut msg: &[u8], binary_msg_id: bool) -> io::Result<Fields> {
+    let mut fields = Vec::new();
+    if binary_msg_id {
+        if msg.len() < 4 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Missing message id"));
+        }
+        fields.push(u32::from_be_bytes([msg[0], msg[1], msg[2], msg[3]]).to_string());
+        msg = &msg[4..];
+    }
+    let msg = String::from_utf8_lossy(msg);
+    fields.extend(msg.split('\0').map(str::to_string));
+    //drop the empty field after the terminating null
+    if fields.last().is_some_and(|field| field.is_empty()) {
+        fields.pop();
+    }
+    Ok(fields)
+}
+
+impl Shared {
+    fn record(&self, msg: &[String]) {
+        let mut received = self.received.lock().unwrap();
+        received.push(msg.to_vec());
+        let _ = self.received_count.send(received.len());
+    }
+
+    fn binary_msg_ids(&self) -> bool {
+        self.server_version >= constants::MIN_SERVER_VER_PROTOBUF
+    }
+
+    /// Encodes a text message the way `server_version` expects it.
+    fn encode(&self, fields: &[String]) -> Vec<u8> {
+        let mut fields = fields.to_vec();
+        //errors lost their version field
+        if self.server_version >= constants::MIN_SERVER_VER_ERROR_TIME && fields.first().map(String::as_str) == Some("4") {
+            fields.remove(1);
+        }
+        match fields.first().and_then(|id| id.parse::<u32>().ok()) {
+            Some(msg_id) if self.binary_msg_ids() => {
+                let mut payload = msg_id.to_be_bytes().to_vec();
+                payload.extend(encode_fields(&fields[1..]));
+                payload
+            },
+            _ => encode_fields(&fields)
+        }
+    }
+
+    fn answer(&self, payload: &[u8], msg: &[String]) -> Vec<Vec<u8>> {
+        let id: i32 = match msg.first().and_then(|id| id.parse().ok()) {
+            Some(id) => id,
+            None => return Vec::new()
+        };
+        let replies = match self.handlers.lock().unwrap().get(&id) {
+            Some(Handler::Raw(handler)) => return handler(msg),
+            Some(Handler::Request(handler)) => {
+                let request = match IBRequest::decode(payload, self.server_version) {
+                    Ok(request) => request,
+                    Err(err) => {
+                        tracing::warn!(error = %err, "Mock gateway could not decode request");
+                        return Vec::new();
+                    }
+                };
+                //the gateway adds the length prefix itself
+                return handler(&request).iter()
+                    .filter_map(|frame| frame.encode(self.server_version))
+                    .map(|msg| msg[4..].to_vec())
+                    .collect();
+            },
+            Some(Handler::Fields(handler)) => handler(msg),
+            None if id == Outgoing::ReqIds.ordinal() as i32 => {
+                vec![frames::next_valid_id(self.next_order_id.load(Ordering::SeqCst))]
+            },
+            None if id == Outgoing::ReqCurrentTime.ordinal() as i32 => {
+                vec![frames::current_time(chrono::Utc::now().timestamp())]
+            },
+            None => Vec::new()
+        };
+        replies.iter().map(|reply| self.encode(reply)).collect()
+    }
+}
+
+async fn serve(mut stream: TcpStream, shared: Arc<Shared>) -> io::Result<()> {
+    //handshake: "API\0", the supported version range, then StartApi
+    let mut prefix = [0u8; 4];
+    stream.read_exact(&mut prefix).await?;
+    if &prefix != b"API\0" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Missing API prefix"));
+    }
+    let versions = split_fields(&read_frame(&mut stream).await?, false)?;
+    shared.record(&versions);
+    let server_version = shared.server_version.to_string();
+    stream.write_all(&encode_frame(&encode_fields(&[server_version, "20210101 00:00:00 UTC".to_string()]))).await?;
+    let binary_msg_ids = shared.binary_msg_ids();
+    let start_api = split_fields(&read_frame(&mut stream).await?, binary_msg_ids)?;
+    shared.record(&start_api);
+    let client_id: i32 = start_api.get(2).and_then(|id| id.parse().ok()).unwrap_or_default();
+    if shared.rejected_client_ids.lock().unwrap().contains(&client_id) {
+        let msg = frames::error_msg(-1, error_codes::CLIENT_ID_IN_USE, "Unable to connect as the client id is already in use.");
+        stream.write_all(&encode_frame(&shared.encode(&msg))).await?;
+        return Ok(());
+    }
+    let next_valid_id = frames::next_valid_id(shared.next_order_id.load(Ordering::SeqCst));
+    stream.write_all(&encode_frame(&shared.encode(&next_valid_id))).await?;
+
+    let (push_tx, mut push_rx) = mpsc::unbounded_channel();
+    shared.connections.lock().unwrap().push(push_tx);
+    let (mut recv, mut trans) = stream.into_split();
+    let (reply_tx, mut reply_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    let reader_shared = shared.clone();
+    let reader = tokio::spawn(async move {
+        while let Ok(payload) = read_frame(&mut recv).await {
+            let fields = match split_fields(&payload, binary_msg_ids) {
+                Ok(fields) => fields,
+                Err(_) => break
+            };
+            reader_shared.record(&fields);
+            for reply in reader_shared.answer(&payload, &fields) {
+                if reply_tx.send(reply).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    loop {
+        let msg = tokio::select! {
+            Some(msg) = reply_rx.recv() => msg,
+            msg = push_rx.recv() => match msg {
+                Some(msg) => msg,
+                //disconnected by the test
+                None => break
+            },
+            else => break
+        };
+        if trans.write_all(&encode_frame(&msg)).await.is_err() {
+            break;
+        }
+    }
+    reader.abort();
+    Ok(())
+}
+
+/// Builders for frames commonly sent by TWS.
+pub mod frames {
+    use super::Fields;
+    use crate::ib_enums::constants;
+
+    fn fields(values: &[&str]) -> Fields {
+        values.iter().map(|val| val.to_string()).collect()
+    }
+
+    pub fn next_valid_id(order_id: i32) -> Fields {
+        fields(&["9", "1", &order_id.to_string()])
+    }
+
+    pub fn current_time(unix_time: i64) -> Fields {
+        fields(&["49", "1", &unix_time.to_string()])
+    }
+
+    /// The version field is dropped when sent to clients from server version `MIN_SERVER_VER_ERROR_TIME` on.
+    pub fn error_msg(id: i32, code: i32, msg: &str) -> Fields {
+        fields(&["4", "2", &id.to_string(), &code.to_string(), msg])
+    }
+
+    pub fn contract_data_end(req_id: i32) -> Fields {
+        fields(&["52", "1", &req_id.to_string()])
+    }
+
+    pub fn account_value(key: &str, value: &str, currency: &str, account: &str) -> Fields {
+        fields(&["6", "2", key, value, currency, account])
+    }
+
+    pub fn tick_price(id: i32, tick_type: i32, price: f64, size: i32) -> Fields {
+        fields(&["1", "6", &id.to_string(), &tick_type.to_string(), &price.to_string(), &size.to_string(), "0"])
+    }
+
+    pub fn tick_size(id: i32, tick_type: i32, size: i32) -> Fields {
+        fields(&["2", "6", &id.to_string(), &tick_type.to_string(), &size.to_string()])
+    }
+
+    pub fn order_status(order_id: i32, status: &str, filled: &str, remaining: &str, avg_fill_price: f64) -> Fields {
+        fields(&["3", &order_id.to_string(), status, filled, remaining, &avg_fill_price.to_string(),
+            "0", "0", &avg_fill_price.to_string(), "0", "", ""])
+    }
+
+    /// Payload of a protobuf message, given its message id without the `PROTOBUF_MSG_ID` offset
+    /// and the encoded message.
+    pub fn protobuf(msg_id: i32, body: &[u8]) -> Vec<u8> {
+        let mut payload = ((msg_id + constants::PROTOBUF_MSG_ID) as u32).to_be_bytes().to_vec();
+        payload.extend_from_slice(body);
+        payload
+    }
+}
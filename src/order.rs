@@ -1,13 +1,16 @@
 use crate::ib_enums::*;
 use rust_decimal::prelude::*;
-use crate::utils::ib_message::Encodable;
-use crate::ib_contract::Contract;
+use crate::utils::ib_message::{Encodable, FieldCursor};
+use crate::error::DecodeError;
+use bytes::{BufMut, BytesMut};
+use crate::ib_contract::{ComboLeg, Contract, DeltaNeutralContract};
 use crossbeam::channel;
 use tokio::sync::watch;
+use crate::error::TwsError;
 #[derive(Debug,Clone)]
 pub struct OrderTracker {
     order_rx: watch::Receiver<Order>,
-    error: Option<(i32,String)>,
+    error_rx: watch::Receiver<Option<TwsError>>,
     //order_state: OrderState,
     order_state_rx: watch::Receiver<OrderState>,
     order_status_rx: watch::Receiver<Option<OrderStatus>>,
@@ -23,7 +26,8 @@ pub struct OrderTrackerSender {
     pub order_tx: watch::Sender<Order>,
     pub order_status_tx: watch::Sender<Option<OrderStatus>>,
     pub order_state_tx: watch::Sender<OrderState>,
-    pub commission_reports_tx: channel::Sender<CommissionReport>
+    pub commission_reports_tx: channel::Sender<CommissionReport>,
+    pub error_tx: watch::Sender<Option<TwsError>>
 }
 
 impl OrderTracker {
@@ -33,16 +37,18 @@ impl OrderTracker {
         let (order_status_tx, order_status_rx) = watch::channel(None);
         let (order_state_tx, order_state_rx) = watch::channel(order_state);
         let (order_tx, order_rx) = watch::channel(order);
+        let (error_tx, error_rx) = watch::channel(None);
         (OrderTrackerSender {
             order_tx,
             executions_tx,
             commission_reports_tx,
             order_status_tx,
             order_state_tx,
+            error_tx,
         },
         OrderTracker {
             order_rx,
-            error: None,
+            error_rx,
             commission_reports: Vec::new(),
             executions: Vec::new(),
             executions_rx,
@@ -71,6 +77,11 @@ impl OrderTracker {
         }
     }
 
+    /// Last error TWS reported for this order, e.g. a rejection.
+    pub fn error(&self) -> Option<TwsError> {
+        self.error_rx.borrow().clone()
+    }
+
     pub fn is_filled(&self) -> bool {
         if let Some(stat) = &*self.order_status_rx.borrow() {
             if &stat.status == "Filled" {
@@ -100,7 +111,7 @@ impl OrderTracker {
 
     pub fn commissions_paid(&mut self) -> Option<Decimal> {
         self.update_com();
-        if self.commission_reports.len() > 0 {
+        if !self.commission_reports.is_empty() {
             Some(self.commission_reports.iter().fold(Decimal::new(0, 2), |acc, x| acc + x.commission))
         }
         else {
@@ -110,6 +121,7 @@ impl OrderTracker {
 }
 
 #[derive(Default,Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
 pub struct SoftDollarTier {
     pub name: Option<String>,
     pub val: Option<String>,
@@ -117,6 +129,7 @@ pub struct SoftDollarTier {
 }
 
 #[derive(Default,Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
 pub struct Order {
 
     //contract
@@ -125,7 +138,7 @@ pub struct Order {
     //order identification
     pub order_id: i32,
     pub client_id: usize,
-    pub perm_id: i32,
+    pub perm_id: i64,
 
     //main order fields
     pub action: Action,
@@ -299,9 +312,30 @@ pub struct Order {
     pub imbalance_only: bool,
     pub route_marketable_to_bbo: bool,
     pub parent_perm_id: Option<usize>,
-    pub use_price_mgmt_algo: Option<UsePriceMgmtAlgo>
+    pub use_price_mgmt_algo: Option<UsePriceMgmtAlgo>,
+    pub duration: Option<i32>,
+    pub post_to_ats: Option<i32>,
+    pub advanced_error_override: Option<String>,
+    pub manual_order_time: Option<String>,
+
+    // IBKR ATS and pegged to best/mid fields
+    pub min_trade_qty: Option<i32>,
+    pub min_compete_size: Option<i32>,
+    pub compete_against_best_offset: Option<f64>,
+    pub mid_offset_at_whole: Option<f64>,
+    pub mid_offset_at_half: Option<f64>,
+
+    pub customer_account: Option<String>,
+    pub professional_customer: bool,
+    pub bond_accrued_interest: Option<String>,
+    pub include_overnight: bool,
+    pub manual_order_indicator: Option<i32>,
+    pub submitter: Option<String>
 }
 
+/// Value of `compete_against_best_offset` to compete against the best offset up to the midpoint.
+pub const COMPETE_AGAINST_BEST_OFFSET_UP_TO_MID: f64 = f64::INFINITY;
+
 impl Order {
     fn new() -> Self {
         Order {
@@ -354,219 +388,570 @@ impl Order {
     }
 }
 
-impl Encodable for Order {
-    fn encode(&self) -> String {
-        let mut code = String::new();
-        code.push_str(&self.contract.encode_for_order());
-        code.push_str(&self.action.encode());
-        code.push_str(&self.total_qty.encode());
-        code.push_str(&self.order_type.encode());
-        code.push_str(&self.lmt_price.encode());
-        code.push_str(&self.aux_price.encode());
-        code.push_str(&self.tif.encode());
-        code.push_str(&self.oca_group.encode());
-        code.push_str(&self.account.encode());
-        code.push_str(&self.open_close.encode());
-        code.push_str(&self.origin.encode());
-        code.push_str(&self.order_ref.encode());
-        code.push_str(&self.transmit.encode());
-        code.push_str(&self.parent_id.encode());
-        code.push_str(&self.block_order.encode());
-        code.push_str(&self.sweep_to_fill.encode());
-        code.push_str(&self.display_size.encode());
-        code.push_str(&self.trigger_method.encode());
-        code.push_str(&self.outside_rth.encode());
-        code.push_str(&self.hidden.encode());
+impl Order {
+    /// Writes the order fields of a `PlaceOrder` message, leaving out fields `server_version` does not know.
+    pub fn encode(&self, buf: &mut BytesMut, server_version: i32) {
+        self.contract.encode_for_order(buf);
+        self.action.encode(buf);
+        self.total_qty.encode(buf);
+        self.order_type.encode(buf);
+        self.lmt_price.encode(buf);
+        self.aux_price.encode(buf);
+        self.tif.encode(buf);
+        self.oca_group.encode(buf);
+        self.account.encode(buf);
+        self.open_close.encode(buf);
+        self.origin.encode(buf);
+        self.order_ref.encode(buf);
+        self.transmit.encode(buf);
+        self.parent_id.encode(buf);
+        self.block_order.encode(buf);
+        self.sweep_to_fill.encode(buf);
+        self.display_size.encode(buf);
+        self.trigger_method.encode(buf);
+        self.outside_rth.encode(buf);
+        self.hidden.encode(buf);
         if let Some(sec) = &self.contract.sec_type {
             if *sec == SecType::Combo {
                 match &self.contract.combo_legs {
                     Some(legs) => {
-                        code.push_str(&legs.len().encode());
+                        legs.len().encode(buf);
                         for leg in legs {
-                            code.push_str(&leg.con_id.encode());
-                            code.push_str(&leg.ratio.encode());
-                            code.push_str(&leg.action.encode());
-                            code.push_str(&leg.exchange.encode());
-                            code.push_str(&leg.open_close.encode());
-                            code.push_str(&leg.shortsale_slot.encode());
-                            code.push_str(&leg.designated_location.encode());
-                            code.push_str(&leg.exempt_code.encode());
+                            leg.con_id.encode(buf);
+                            leg.ratio.encode(buf);
+                            leg.action.encode(buf);
+                            leg.exchange.encode(buf);
+                            leg.open_close.encode(buf);
+                            leg.shortsale_slot.encode(buf);
+                            leg.designated_location.encode(buf);
+                            leg.exempt_code.encode(buf);
                         };
                     }
-                    None => code.push_str("0\0"),
+                    None => buf.put_slice(b"0\0"),
                 }
                 match &self.order_combo_legs {
                     Some(legs) => {
-                        code.push_str(&legs.len().encode());
+                        legs.len().encode(buf);
                         for leg in legs {
-                            code.push_str(&leg.encode());
+                            leg.encode(buf);
                         }
                     }
-                    None => code.push_str("0\0"),
+                    None => buf.put_slice(b"0\0"),
                 }
                 match &self.smart_combo_routing_params {
                     Some(tag_val_list) => {
-                        code.push_str(&tag_val_list.len().encode());
+                        tag_val_list.len().encode(buf);
                         for tv in tag_val_list {
-                            code.push_str(&tv.0.encode());
-                            code.push_str(&tv.1.encode());
+                            tv.0.encode(buf);
+                            tv.1.encode(buf);
                         };
                     }
-                    None => code.push_str("0\0"),
+                    None => buf.put_slice(b"0\0"),
                 }
             }  
         }
-        code.push_str("\0"); //deprecated shares allocation field
-        code.push_str(&self.discretionary_amt.encode());
-        code.push_str(&self.good_after_time.encode());
-        code.push_str(&self.good_till_date.encode());
-        code.push_str(&self.fa_group.encode());
-        code.push_str(&self.fa_method.encode());
-        code.push_str(&self.fa_percentage.encode());
-        code.push_str(&self.fa_profile.encode());
-        code.push_str(&self.model_code.encode());
-        code.push_str(&self.short_sale_slot.encode());
-        code.push_str(&self.designated_location.encode());
-        code.push_str(&self.exempt_code.encode());
-        code.push_str(&self.oca_type.encode());
-        code.push_str(&self.rule_80A.encode());
-        code.push_str(&self.settling_firm.encode());
-        code.push_str(&self.all_or_none.encode());
-        code.push_str(&self.min_qty.encode());
-        code.push_str(&self.percent_offset.encode());
-        code.push_str(&self.e_trade_only.encode());
-        code.push_str(&self.firm_quote_only.encode());
-        code.push_str(&self.nbbo_price_cap.encode());
-        code.push_str(&self.auction_strategy.encode());
-        code.push_str(&self.starting_price.encode());
-        code.push_str(&self.stock_ref_price.encode());
-        code.push_str(&self.delta.encode());
-        code.push_str(&self.stock_range_lower.encode());
-        code.push_str(&self.stock_range_upper.encode());
-        code.push_str(&self.override_percentage_constraints.encode());
-        code.push_str(&self.volatility.encode());
-        code.push_str(&self.volatility_type.encode());
-        code.push_str(&self.delta_neutral_order_type.encode());
-        code.push_str(&self.delta_neutral_aux_price.encode());
+        buf.put_slice(b"\0"); //deprecated shares allocation field
+        self.discretionary_amt.encode(buf);
+        self.good_after_time.encode(buf);
+        self.good_till_date.encode(buf);
+        self.fa_group.encode(buf);
+        self.fa_method.encode(buf);
+        self.fa_percentage.encode(buf);
+        if server_version < constants::MIN_SERVER_VER_FA_PROFILE_DESUPPORT {
+            self.fa_profile.encode(buf);
+        }
+        if server_version >= constants::MIN_SERVER_VER_MODELS_SUPPORT {
+            self.model_code.encode(buf);
+        }
+        self.short_sale_slot.encode(buf);
+        self.designated_location.encode(buf);
+        self.exempt_code.encode(buf);
+        self.oca_type.encode(buf);
+        self.rule_80A.encode(buf);
+        self.settling_firm.encode(buf);
+        self.all_or_none.encode(buf);
+        self.min_qty.encode(buf);
+        self.percent_offset.encode(buf);
+        self.e_trade_only.encode(buf);
+        self.firm_quote_only.encode(buf);
+        self.nbbo_price_cap.encode(buf);
+        self.auction_strategy.encode(buf);
+        self.starting_price.encode(buf);
+        self.stock_ref_price.encode(buf);
+        self.delta.encode(buf);
+        self.stock_range_lower.encode(buf);
+        self.stock_range_upper.encode(buf);
+        self.override_percentage_constraints.encode(buf);
+        self.volatility.encode(buf);
+        self.volatility_type.encode(buf);
+        self.delta_neutral_order_type.encode(buf);
+        self.delta_neutral_aux_price.encode(buf);
         if self.delta_neutral_order_type.is_some() {
-            code.push_str(&self.delta_neutral_con_id.encode());
-            code.push_str(&self.delta_neutral_settling_firm.encode());
-            code.push_str(&self.delta_neutral_clearing_account.encode());
-            code.push_str(&self.delta_neutral_clearing_intent.encode());
-            code.push_str(&self.delta_neutral_open_close.encode());
-            code.push_str(&self.delta_neutral_short_sale.encode());
-            code.push_str(&self.delta_neutral_designated_location.encode());
-        }
-        code.push_str(&self.continuous_update.encode());
-        code.push_str(&self.reference_price_type.encode());
-        code.push_str(&self.trail_stop_price.encode());
-        code.push_str(&self.trailing_percent.encode());
-        code.push_str(&self.scale_init_level_size.encode());
-        code.push_str(&self.scale_subs_level_size.encode());
-        code.push_str(&self.scale_price_increment.encode());
+            self.delta_neutral_con_id.encode(buf);
+            self.delta_neutral_settling_firm.encode(buf);
+            self.delta_neutral_clearing_account.encode(buf);
+            self.delta_neutral_clearing_intent.encode(buf);
+            self.delta_neutral_open_close.encode(buf);
+            self.delta_neutral_short_sale.encode(buf);
+            self.delta_neutral_designated_location.encode(buf);
+        }
+        self.continuous_update.encode(buf);
+        self.reference_price_type.encode(buf);
+        self.trail_stop_price.encode(buf);
+        self.trailing_percent.encode(buf);
+        self.scale_init_level_size.encode(buf);
+        self.scale_subs_level_size.encode(buf);
+        self.scale_price_increment.encode(buf);
         if let Some(inc) = self.scale_price_increment {
             if inc > 0.0 {
-                code.push_str(&self.scale_price_adjust_value.encode());
-                code.push_str(&self.scale_price_adjust_interval.encode());
-                code.push_str(&self.scale_profit_offset.encode());
-                code.push_str(&self.scale_auto_reset.encode());
-                code.push_str(&self.scale_init_position.encode());
-                code.push_str(&self.scale_init_fill_qty.encode());
-                code.push_str(&self.scale_random_percent.encode());
+                self.scale_price_adjust_value.encode(buf);
+                self.scale_price_adjust_interval.encode(buf);
+                self.scale_profit_offset.encode(buf);
+                self.scale_auto_reset.encode(buf);
+                self.scale_init_position.encode(buf);
+                self.scale_init_fill_qty.encode(buf);
+                self.scale_random_percent.encode(buf);
             }
         }
-        code.push_str(&self.scale_table.encode());
-        code.push_str(&self.active_start_time.encode());
-        code.push_str(&self.active_stop_time.encode());
-        code.push_str(&self.hedge_type.encode());
+        self.scale_table.encode(buf);
+        self.active_start_time.encode(buf);
+        self.active_stop_time.encode(buf);
+        self.hedge_type.encode(buf);
         if self.hedge_type.is_some() {
-            code.push_str(&self.hedge_param.encode());
+            self.hedge_param.encode(buf);
         }
-        code.push_str(&self.opt_out_smart_routing.encode());
-        code.push_str(&self.clearing_account.encode());
-        code.push_str(&self.clearing_intent.encode());
-        code.push_str(&self.not_held.encode());
+        self.opt_out_smart_routing.encode(buf);
+        self.clearing_account.encode(buf);
+        self.clearing_intent.encode(buf);
+        self.not_held.encode(buf);
         match &self.contract.delta_neutral_contract {
             Some(dn) => {
-                code.push_str("1\0");
-                code.push_str(&dn.con_id.encode());
-                code.push_str(&dn.delta.encode());
-                code.push_str(&dn.price.encode());
+                buf.put_slice(b"1\0");
+                dn.con_id.encode(buf);
+                dn.delta.encode(buf);
+                dn.price.encode(buf);
             }
-            None => code.push_str("0\0")
+            None => buf.put_slice(b"0\0")
         };
-        code.push_str(&self.algo_strategy.encode());
+        self.algo_strategy.encode(buf);
         if self.algo_strategy.is_some() {
             match &self.algo_params {
                 Some(params) => {
-                    code.push_str(&params.len().encode());
+                    params.len().encode(buf);
                     for param in params {
-                        code.push_str(&param.0.encode());
-                        code.push_str(&param.1.encode());
+                        param.0.encode(buf);
+                        param.1.encode(buf);
+                    };
+                },
+                None => buf.put_slice(b"0\0")
+            }
+        }
+        self.algo_id.encode(buf);
+        self.what_if.encode(buf);
+        self.order_misc_options.encode(buf);
+        self.solicited.encode(buf);
+        self.randomize_size.encode(buf);
+        self.randomize_price.encode(buf);
+
+        if server_version >= constants::MIN_SERVER_VER_PEGGED_TO_BENCHMARK {
+            if self.order_type == OrderType::PeggedToBenchmark {
+                self.reference_contract_id.encode(buf);
+                self.is_pegged_change_amount_decrease.encode(buf);
+                self.pegged_change_amount.encode(buf);
+                self.reference_change_amount.encode(buf);
+                self.reference_exchange_id.encode(buf);
+            }
+
+            match &self.conditions {
+                Some(conds) => {
+                    conds.len().encode(buf);
+                    for cond in conds {
+                        //C++ API has some facility for external notification here
+                        cond.encode(buf);
                     };
+                    self.conditions_ignore_rth.encode(buf);
+                    self.conditions_cancel_order.encode(buf);
+                }
+                None => buf.put_slice(b"0\0")
+            }
+
+            self.adjusted_order_type.encode(buf);
+            self.trigger_price.encode(buf);
+            self.lmt_price_offset.encode(buf);
+            self.adjusted_stop_price.encode(buf);
+            self.adjusted_stop_limit_price.encode(buf);
+            self.adjusted_trailing_amount.encode(buf);
+            self.adjustable_trailing_unit.encode(buf);
+        }
+        if server_version >= constants::MIN_SERVER_VER_EXT_OPERATOR {
+            self.ext_operator.encode(buf);
+        }
+        if server_version >= constants::MIN_SERVER_VER_SOFT_DOLLAR_TIER {
+            match &self.soft_dollar_tier {
+                Some (tier) => {
+                    tier.name.encode(buf);
+                    tier.val.encode(buf);
                 },
-                None => code.push_str("0\0")
+                None => buf.put_slice(b"\0\0")
+            }
+        }
+        if server_version >= constants::MIN_SERVER_VER_CASH_QTY {
+            self.cash_qty.encode(buf);
+        }
+        if server_version >= constants::MIN_SERVER_VER_DECISION_MAKER {
+            self.mifid_2_decision_maker.encode(buf);
+            self.mifid_2_decision_algo.encode(buf);
+        }
+        if server_version >= constants::MIN_SERVER_VER_MIFID_EXECUTION {
+            self.mifid_2_execution_trader.encode(buf);
+            self.mifid_2_execution_algo.encode(buf);
+        }
+        if server_version >= constants::MIN_SERVER_VER_AUTO_PRICE_FOR_HEDGE {
+            self.dont_use_auto_price_for_hedge.encode(buf);
+        }
+        if server_version >= constants::MIN_SERVER_VER_ORDER_CONTAINER {
+            self.is_oms_container.encode(buf);
+        }
+        if server_version >= constants::MIN_SERVER_VER_D_PEG_ORDERS {
+            self.discretionary_up_to_limit_price.encode(buf);
+        }
+        if server_version >= constants::MIN_SERVER_VER_PRICE_MGMT_ALGO {
+            self.use_price_mgmt_algo.encode(buf);
+        }
+        if server_version >= constants::MIN_SERVER_VER_DURATION {
+            self.duration.encode(buf);
+        }
+        if server_version >= constants::MIN_SERVER_VER_POST_TO_ATS {
+            self.post_to_ats.encode(buf);
+        }
+        if server_version >= constants::MIN_SERVER_VER_AUTO_CANCEL_PARENT {
+            self.auto_cancel_parent.encode(buf);
+        }
+        if server_version >= constants::MIN_SERVER_VER_ADVANCED_ORDER_REJECT {
+            self.advanced_error_override.encode(buf);
+        }
+        if server_version >= constants::MIN_SERVER_VER_MANUAL_ORDER_TIME {
+            self.manual_order_time.encode(buf);
+        }
+        if server_version >= constants::MIN_SERVER_VER_PEGBEST_PEGMID_OFFSETS {
+            let mut send_mid_offsets = false;
+            if self.contract.exchange.as_deref() == Some("IBKRATS") {
+                self.min_trade_qty.encode(buf);
+            }
+            if self.order_type == OrderType::PeggedToBest {
+                self.min_compete_size.encode(buf);
+                match self.compete_against_best_offset {
+                    Some(offset) if offset == COMPETE_AGAINST_BEST_OFFSET_UP_TO_MID => {
+                        buf.put_slice(b"Infinity\0");
+                        send_mid_offsets = true;
+                    },
+                    offset => offset.encode(buf)
+                }
+            }
+            else if self.order_type == OrderType::PeggedToMidpoint {
+                send_mid_offsets = true;
+            }
+            if send_mid_offsets {
+                self.mid_offset_at_whole.encode(buf);
+                self.mid_offset_at_half.encode(buf);
+            }
+        }
+        if server_version >= constants::MIN_SERVER_VER_CUSTOMER_ACCOUNT {
+            self.customer_account.encode(buf);
+        }
+        if server_version >= constants::MIN_SERVER_VER_PROFESSIONAL_CUSTOMER {
+            self.professional_customer.encode(buf);
+        }
+        if (constants::MIN_SERVER_VER_RFQ_FIELDS..constants::MIN_SERVER_VER_UNDO_RFQ_FIELDS).contains(&server_version) {
+            buf.put_slice(b"\0\0"); //external user id and manual order indicator of the withdrawn RFQ fields
+        }
+        if server_version >= constants::MIN_SERVER_VER_INCLUDE_OVERNIGHT {
+            self.include_overnight.encode(buf);
+        }
+        if server_version >= constants::MIN_SERVER_VER_CME_TAGGING_FIELDS {
+            self.manual_order_indicator.encode(buf);
+        }
+        if server_version >= constants::MIN_SERVER_VER_IMBALANCE_ONLY {
+            self.imbalance_only.encode(buf);
+        }
+    }
+
+    /// Reads the order fields written by `encode` for the same `server_version`. The order id is
+    /// not part of them and left at 0.
+    pub(crate) fn decode(it: &mut FieldCursor, server_version: i32) -> Result<Self, DecodeError> {
+        let mut order = Order {
+            contract: Contract::decode_for_order(it)?,
+            action: it.req()?,
+            total_qty: it.req()?,
+            order_type: it.req()?,
+            lmt_price: it.opt()?,
+            aux_price: it.opt()?,
+            tif: it.opt()?,
+            oca_group: it.opt()?,
+            account: it.opt()?,
+            open_close: it.opt()?,
+            origin: it.opt()?,
+            order_ref: it.opt()?,
+            transmit: it.req()?,
+            parent_id: it.req()?,
+            block_order: it.req()?,
+            sweep_to_fill: it.req()?,
+            display_size: it.opt()?,
+            trigger_method: it.opt()?,
+            outside_rth: it.req()?,
+            hidden: it.req()?,
+            ..Default::default()
+        };
+        if order.contract.sec_type == Some(SecType::Combo) {
+            let n_legs: usize = it.req()?;
+            let mut legs = Vec::new();
+            for _ in 0..n_legs {
+                legs.push(ComboLeg {
+                    con_id: it.req()?,
+                    ratio: it.req()?,
+                    action: it.req()?,
+                    exchange: it.req()?,
+                    open_close: it.opt()?,
+                    shortsale_slot: it.opt()?,
+                    designated_location: it.opt()?,
+                    exempt_code: it.opt()?
+                });
+            }
+            order.contract.combo_legs = if legs.is_empty() {None} else {Some(legs)};
+            let n_order_legs: usize = it.req()?;
+            let mut order_legs = Vec::new();
+            for _ in 0..n_order_legs {
+                order_legs.push(it.opt()?);
+            }
+            order.order_combo_legs = if order_legs.is_empty() {None} else {Some(order_legs)};
+            let n_params: usize = it.req()?;
+            let mut params = Vec::new();
+            for _ in 0..n_params {
+                params.push((it.req()?, it.req()?));
+            }
+            order.smart_combo_routing_params = if params.is_empty() {None} else {Some(params)};
+        }
+        it.skip()?; //deprecated shares allocation field
+        order.discretionary_amt = it.req()?;
+        order.good_after_time = it.opt()?;
+        order.good_till_date = it.opt()?;
+        order.fa_group = it.opt()?;
+        order.fa_method = it.opt()?;
+        order.fa_percentage = it.opt()?;
+        if server_version < constants::MIN_SERVER_VER_FA_PROFILE_DESUPPORT {
+            order.fa_profile = it.opt()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_MODELS_SUPPORT {
+            order.model_code = it.opt()?;
+        }
+        order.short_sale_slot = it.opt()?;
+        order.designated_location = it.opt()?;
+        order.exempt_code = it.req()?;
+        order.oca_type = it.opt()?;
+        order.rule_80A = it.opt()?;
+        order.settling_firm = it.opt()?;
+        order.all_or_none = it.req()?;
+        order.min_qty = it.opt()?;
+        order.percent_offset = it.opt()?;
+        order.e_trade_only = it.req()?;
+        order.firm_quote_only = it.req()?;
+        order.nbbo_price_cap = it.opt()?;
+        order.auction_strategy = it.opt()?;
+        order.starting_price = it.opt()?;
+        order.stock_ref_price = it.opt()?;
+        order.delta = it.opt()?;
+        order.stock_range_lower = it.opt()?;
+        order.stock_range_upper = it.opt()?;
+        order.override_percentage_constraints = it.req()?;
+        order.volatility = it.opt()?;
+        order.volatility_type = it.opt()?;
+        order.delta_neutral_order_type = it.opt()?;
+        order.delta_neutral_aux_price = it.opt()?;
+        if order.delta_neutral_order_type.is_some() {
+            order.delta_neutral_con_id = it.req()?;
+            order.delta_neutral_settling_firm = it.opt()?;
+            order.delta_neutral_clearing_account = it.opt()?;
+            order.delta_neutral_clearing_intent = it.opt()?;
+            order.delta_neutral_open_close = it.opt()?;
+            order.delta_neutral_short_sale = it.req()?;
+            order.delta_neutral_designated_location = it.opt()?;
+        }
+        order.continuous_update = it.req()?;
+        order.reference_price_type = it.opt()?;
+        order.trail_stop_price = it.opt()?;
+        order.trailing_percent = it.opt()?;
+        order.scale_init_level_size = it.opt()?;
+        order.scale_subs_level_size = it.opt()?;
+        order.scale_price_increment = it.opt()?;
+        if let Some(inc) = order.scale_price_increment {
+            if inc > 0.0 {
+                order.scale_price_adjust_value = it.opt()?;
+                order.scale_price_adjust_interval = it.opt()?;
+                order.scale_profit_offset = it.opt()?;
+                order.scale_auto_reset = it.req()?;
+                order.scale_init_position = it.opt()?;
+                order.scale_init_fill_qty = it.opt()?;
+                order.scale_random_percent = it.req()?;
             }
         }
-        code.push_str(&self.algo_id.encode());
-        code.push_str(&self.what_if.encode());
-        code.push_str(&self.order_misc_options.encode());
-        code.push_str(&self.solicited.encode());
-        code.push_str(&self.randomize_size.encode());
-        code.push_str(&self.randomize_price.encode());
-
-        if self.order_type == OrderType::PeggedToBenchmark {
-            code.push_str(&self.reference_contract_id.encode());
-            code.push_str(&self.is_pegged_change_amount_decrease.encode());
-            code.push_str(&self.pegged_change_amount.encode());
-            code.push_str(&self.reference_change_amount.encode());
-            code.push_str(&self.reference_exchange_id.encode());
-        }
-
-        match &self.conditions {
-            Some(conds) => {
-                code.push_str(&conds.len().encode());
-                for cond in conds {
-                    //C++ API has some facility for external notification here
-                    code.push_str(&cond.encode());
-                };
-                code.push_str(&self.conditions_ignore_rth.encode());
-                code.push_str(&self.conditions_cancel_order.encode());
+        order.scale_table = it.opt()?;
+        order.active_start_time = it.opt()?;
+        order.active_stop_time = it.opt()?;
+        order.hedge_type = it.opt()?;
+        if order.hedge_type.is_some() {
+            order.hedge_param = it.opt()?;
+        }
+        order.opt_out_smart_routing = it.req()?;
+        order.clearing_account = it.opt()?;
+        order.clearing_intent = it.opt()?;
+        order.not_held = it.req()?;
+        if it.req()? {
+            order.contract.delta_neutral_contract = Some(DeltaNeutralContract {
+                con_id: it.req()?,
+                delta: it.req()?,
+                price: it.req()?
+            });
+        }
+        order.algo_strategy = it.opt()?;
+        if order.algo_strategy.is_some() {
+            let n_params: usize = it.req()?;
+            let mut params = Vec::new();
+            for _ in 0..n_params {
+                params.push((it.req()?, it.req()?));
+            }
+            order.algo_params = if params.is_empty() {None} else {Some(params)};
+        }
+        order.algo_id = it.opt()?;
+        order.what_if = it.req()?;
+        order.order_misc_options = decode_tag_values(it.opt_str()?);
+        order.solicited = it.req()?;
+        order.randomize_size = it.req()?;
+        order.randomize_price = it.req()?;
+
+        if server_version >= constants::MIN_SERVER_VER_PEGGED_TO_BENCHMARK {
+            if order.order_type == OrderType::PeggedToBenchmark {
+                order.reference_contract_id = it.req()?;
+                order.is_pegged_change_amount_decrease = it.req()?;
+                order.pegged_change_amount = it.req()?;
+                order.reference_change_amount = it.req()?;
+                order.reference_exchange_id = it.opt()?;
+            }
+            let n_conditions: usize = it.req()?;
+            if n_conditions > 0 {
+                let mut conditions = Vec::new();
+                for _ in 0..n_conditions {
+                    conditions.push(it.req()?);
+                }
+                order.conditions = Some(conditions);
+                order.conditions_ignore_rth = it.req()?;
+                order.conditions_cancel_order = it.req()?;
+            }
+            order.adjusted_order_type = it.opt()?;
+            order.trigger_price = it.opt()?;
+            order.lmt_price_offset = it.opt()?;
+            order.adjusted_stop_price = it.opt()?;
+            order.adjusted_stop_limit_price = it.opt()?;
+            order.adjusted_trailing_amount = it.opt()?;
+            order.adjustable_trailing_unit = it.req()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_EXT_OPERATOR {
+            order.ext_operator = it.opt()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_SOFT_DOLLAR_TIER {
+            let name = it.opt()?;
+            let val = it.opt()?;
+            if name.is_some() || val.is_some() {
+                order.soft_dollar_tier = Some(SoftDollarTier{name, val, display_name: None});
             }
-            None => code.push_str("0\0")
-        }
-
-        code.push_str(&self.adjusted_order_type.encode());
-        code.push_str(&self.trigger_price.encode());
-        code.push_str(&self.lmt_price_offset.encode());
-        code.push_str(&self.adjusted_stop_price.encode());
-        code.push_str(&self.adjusted_stop_limit_price.encode());
-        code.push_str(&self.adjusted_trailing_amount.encode());
-        code.push_str(&self.adjustable_trailing_unit.encode());
-        code.push_str(&self.ext_operator.encode());
-        match &self.soft_dollar_tier {
-            Some (tier) => {
-                code.push_str(&tier.name.encode());
-                code.push_str(&tier.val.encode());
-            },
-            None => code.push_str("\0\0")
-        }
-        code.push_str(&self.cash_qty.encode());
-
-        code.push_str(&self.mifid_2_decision_maker.encode());
-        code.push_str(&self.mifid_2_decision_algo.encode());
-        code.push_str(&self.mifid_2_execution_trader.encode());
-        code.push_str(&self.mifid_2_execution_algo.encode());
-
-        code.push_str(&self.dont_use_auto_price_for_hedge.encode());
-        code.push_str(&self.is_oms_container.encode());
-        code.push_str(&self.discretionary_up_to_limit_price.encode());
-        code.push_str(&self.use_price_mgmt_algo.encode());
-        code
+        }
+        if server_version >= constants::MIN_SERVER_VER_CASH_QTY {
+            order.cash_qty = it.opt()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_DECISION_MAKER {
+            order.mifid_2_decision_maker = it.opt()?;
+            order.mifid_2_decision_algo = it.opt()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_MIFID_EXECUTION {
+            order.mifid_2_execution_trader = it.opt()?;
+            order.mifid_2_execution_algo = it.opt()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_AUTO_PRICE_FOR_HEDGE {
+            order.dont_use_auto_price_for_hedge = it.req()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_ORDER_CONTAINER {
+            order.is_oms_container = it.req()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_D_PEG_ORDERS {
+            order.discretionary_up_to_limit_price = it.req()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_PRICE_MGMT_ALGO {
+            order.use_price_mgmt_algo = it.opt()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_DURATION {
+            order.duration = it.opt()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_POST_TO_ATS {
+            order.post_to_ats = it.opt()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_AUTO_CANCEL_PARENT {
+            order.auto_cancel_parent = it.req()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_ADVANCED_ORDER_REJECT {
+            order.advanced_error_override = it.opt()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_MANUAL_ORDER_TIME {
+            order.manual_order_time = it.opt()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_PEGBEST_PEGMID_OFFSETS {
+            let mut mid_offsets = order.order_type == OrderType::PeggedToMidpoint;
+            if order.contract.exchange.as_deref() == Some("IBKRATS") {
+                order.min_trade_qty = it.opt()?;
+            }
+            if order.order_type == OrderType::PeggedToBest {
+                order.min_compete_size = it.opt()?;
+                //"Infinity" parses to COMPETE_AGAINST_BEST_OFFSET_UP_TO_MID
+                order.compete_against_best_offset = it.opt()?;
+                mid_offsets = order.compete_against_best_offset == Some(COMPETE_AGAINST_BEST_OFFSET_UP_TO_MID);
+            }
+            if mid_offsets {
+                order.mid_offset_at_whole = it.opt()?;
+                order.mid_offset_at_half = it.opt()?;
+            }
+        }
+        if server_version >= constants::MIN_SERVER_VER_CUSTOMER_ACCOUNT {
+            order.customer_account = it.opt()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_PROFESSIONAL_CUSTOMER {
+            order.professional_customer = it.req()?;
+        }
+        if (constants::MIN_SERVER_VER_RFQ_FIELDS..constants::MIN_SERVER_VER_UNDO_RFQ_FIELDS).contains(&server_version) {
+            it.skip()?; //external user id and manual order indicator of the withdrawn RFQ fields
+            it.skip()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_INCLUDE_OVERNIGHT {
+            order.include_overnight = it.req()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_CME_TAGGING_FIELDS {
+            order.manual_order_indicator = it.opt()?;
+        }
+        if server_version >= constants::MIN_SERVER_VER_IMBALANCE_ONLY {
+            order.imbalance_only = it.req()?;
+        }
+        Ok(order)
     }
+}
+
+//tag value lists such as the misc options are sent as a single "tag=value;" field
+fn decode_tag_values(field: Option<&str>) -> Option<Vec<(String,String)>> {
+    let tag_values: Vec<(String,String)> = field?.split(';')
+        .filter_map(|tag_value| tag_value.split_once('='))
+        .map(|(tag, val)| (tag.to_string(), val.to_string()))
+        .collect();
+    if tag_values.is_empty() {None} else {Some(tag_values)}
 }  
 
 #[derive(Default,Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
 pub struct OrderState {
     pub status: Option<String>,
     pub init_margin_before: Option<Decimal>,
@@ -585,21 +970,27 @@ pub struct OrderState {
     pub warning_text: Option<String>,
     pub completed_time: Option<String>,
     pub completed_status: Option<String>,
+    pub margin_currency: Option<String>,
+    pub suggested_size: Option<Decimal>,
+    pub reject_reason: Option<String>,
 }
 #[derive(Default,Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
 pub struct OrderStatus {
     pub order_id: i32,
     pub status: String,
     pub filled: Decimal,
     pub remaining: Decimal,
     pub avg_fill_price: Decimal,
-    pub perm_id: i32,
+    pub perm_id: i64,
     pub parent_id: usize,
     pub last_fill_price: Decimal,
     pub client_id:  usize,
-    pub why_held: Option<String>
+    pub why_held: Option<String>,
+    pub mkt_cap_price: Option<Decimal>
 }
 #[derive(Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Execution {
     pub exec_id: String,
     pub time: String,
@@ -608,7 +999,7 @@ pub struct Execution {
     pub side: Side,
     pub shares: Decimal,
     pub price: Decimal,
-    pub perm_id: i32,
+    pub perm_id: i64,
     pub client_id: usize,
     pub order_id: i32,
     pub contract: Contract,
@@ -619,9 +1010,12 @@ pub struct Execution {
     pub ev_rule: Option<String>,
     pub ev_multiplier: Option<Decimal>,
     pub model_code: Option<String>,
-    pub last_liquidity: Option<i32>
+    pub last_liquidity: Option<i32>,
+    pub pending_price_revision: Option<bool>,
+    pub submitter: Option<String>
 }
 #[derive(Default,Debug,Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
 pub struct CommissionReport {
     pub exec_id: String,
     pub commission: Decimal,
@@ -0,0 +1,362 @@
+//! Pacing of outgoing messages.
+//!
+//! TWS disconnects clients sending more than 50 messages per second, so the writer task holds
+//! messages back in a token bucket. Queued messages are sent by lane: cancels and orders go out
+//! before other requests, which in turn go out before bulk data requests such as market data or
+//! contract details. A cancel never overtakes the subscription it cancels: if the market data or
+//! historical data request is still queued, the request and its cancel are both dropped.
+//!
+//! Historical data requests have stricter limits of their own, which the client keeps with a
+//! separate scheduler, see [`HistoricalPacing`].
+use crate::bars::BarSeries;
+use crate::error::AsyncResult;
+use crate::ib_enums::Outgoing;
+use crate::request::IBRequest;
+use bytes::Bytes;
+use futures::future::{BoxFuture, FutureExt, WeakShared};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::{self, Instant};
+
+/// Token bucket for outgoing messages.
+///
+/// At most `burst` messages are sent back to back, after that messages are spaced to
+/// `messages_per_sec`. A second can therefore see up to `burst + messages_per_sec` messages,
+/// which the default keeps at the limit of 50 enforced by TWS.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct RateLimit {
+    messages_per_sec: f64,
+    burst: u32
+}
+
+impl RateLimit {
+    pub fn new(messages_per_sec: u32, burst: u32) -> Self {
+        RateLimit {
+            messages_per_sec: messages_per_sec.max(1) as f64,
+            burst: burst.max(1)
+        }
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit::new(40, 10)
+    }
+}
+
+/// Priority of an outgoing message, from highest to lowest.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum Lane {
+    /// Order placement and cancels of orders and subscriptions.
+    Urgent,
+    Normal,
+    /// Requests for market data, contract details, historical data and the like. The market data
+    /// type goes here as well, as it applies to the market data requests sent after it.
+    Bulk
+}
+
+impl Lane {
+    const ALL: [Lane; 3] = [Lane::Urgent, Lane::Normal, Lane::Bulk];
+
+    /// Lane of an encoded message without its length prefix, by its message id, see `Outgoing::of_payload`.
+    pub fn of(payload: &[u8]) -> Self {
+        match Outgoing::of_payload(payload) {
+            Some(Outgoing::PlaceOrder) | Some(Outgoing::CancelOrder) | Some(Outgoing::ReqGlobalCancel)
+            | Some(Outgoing::CancelMktData) | Some(Outgoing::CancelMktDepth) | Some(Outgoing::CancelHistoricalData)
+            | Some(Outgoing::CancelRealTimeBars) | Some(Outgoing::CancelScannerSubscription)
+            | Some(Outgoing::CancelTickByTickData) | Some(Outgoing::CancelHistogramData)
+            | Some(Outgoing::CancelHeadTimestamp) => Lane::Urgent,
+            Some(Outgoing::ReqMktData) | Some(Outgoing::ReqContractData) | Some(Outgoing::ReqMktDepth)
+            | Some(Outgoing::ReqHistoricalData) | Some(Outgoing::ReqRealTimeBars) | Some(Outgoing::ReqScannerSubscription)
+            | Some(Outgoing::ReqSecDefOptParams) | Some(Outgoing::ReqMatchingSymbols) | Some(Outgoing::ReqHeadTimestamp)
+            | Some(Outgoing::ReqHistogramData) | Some(Outgoing::ReqHistoricalTicks) | Some(Outgoing::ReqTickByTickData)
+            | Some(Outgoing::ReqFundamentalData) | Some(Outgoing::ReqCalcImpliedVolat)
+            | Some(Outgoing::ReqCalcOptionPrice) | Some(Outgoing::ReqMarketDataType) => Lane::Bulk,
+            _ => Lane::Normal
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Lane::Urgent => 0,
+            Lane::Normal => 1,
+            Lane::Bulk => 2
+        }
+    }
+}
+
+/// Counters of the writer's pacing since the client connected.
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct PacingStats {
+    /// Messages passed on to the socket.
+    pub sent: u64,
+    /// Messages that had to wait for the rate limit.
+    pub delayed: u64,
+    /// Messages currently waiting, per lane in the order urgent, normal, bulk.
+    pub queued: [usize; 3],
+    /// Longest time a message spent waiting.
+    pub max_wait: Duration
+}
+
+struct Queued {
+    at: Instant,
+    msg: Bytes,
+    //kind and id of the subscription the message opens, so a cancel can withdraw it
+    opens: Option<(Outgoing, i32)>
+}
+
+/// Queues messages by lane and releases them at the configured rate.
+pub(crate) struct Pacer {
+    limit: Option<RateLimit>,
+    tokens: f64,
+    refilled: Instant,
+    queues: [VecDeque<Queued>; 3],
+    //needed to find the request id of subscriptions and their cancels
+    server_version: Arc<AtomicI32>,
+    stats: PacingStats,
+    stats_tx: watch::Sender<PacingStats>
+}
+
+impl Pacer {
+    pub(crate) fn new(limit: Option<RateLimit>, server_version: Arc<AtomicI32>) -> (Self, watch::Receiver<PacingStats>) {
+        let (stats_tx, stats_rx) = watch::channel(PacingStats::default());
+        let pacer = Pacer {
+            limit,
+            tokens: limit.map(|limit| limit.burst as f64).unwrap_or_default(),
+            refilled: Instant::now(),
+            queues: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            server_version,
+            stats: PacingStats::default(),
+            stats_tx
+        };
+        (pacer, stats_rx)
+    }
+
+    pub(crate) fn push(&mut self, msg: Bytes) {
+        let payload = msg.get(4..).unwrap_or_default();
+        let request = match Outgoing::of_payload(payload) {
+            Some(Outgoing::ReqMktData) | Some(Outgoing::CancelMktData) | Some(Outgoing::ReqHistoricalData)
+            | Some(Outgoing::CancelHistoricalData) => IBRequest::decode(payload, self.server_version.load(Ordering::SeqCst)).ok(),
+            _ => None
+        };
+        let opens = match request {
+            Some(IBRequest::ReqMarketData{req_id, ..}) => Some((Outgoing::ReqMktData, req_id)),
+            Some(IBRequest::ReqHistoricalData{req_id, ..}) => Some((Outgoing::ReqHistoricalData, req_id)),
+            //a subscription that is still queued is dropped instead of being opened and closed right away
+            Some(IBRequest::CancelMarketData{req_id}) if self.withdraw((Outgoing::ReqMktData, req_id)) => return,
+            Some(IBRequest::CancelHistoricalData{req_id}) if self.withdraw((Outgoing::ReqHistoricalData, req_id)) => return,
+            _ => None
+        };
+        let lane = Lane::of(payload);
+        self.queues[lane.index()].push_back(Queued{at: Instant::now(), msg, opens});
+        self.stats.queued[lane.index()] += 1;
+    }
+
+    //removes the queued message opening `subscription`, false if it has been sent already
+    fn withdraw(&mut self, subscription: (Outgoing, i32)) -> bool {
+        for lane in Lane::ALL.iter() {
+            let queue = &mut self.queues[lane.index()];
+            if let Some(pos) = queue.iter().position(|queued| queued.opens == Some(subscription)) {
+                queue.remove(pos);
+                self.stats.queued[lane.index()] -= 1;
+                let _ = self.stats_tx.send(self.stats.clone());
+                return true;
+            }
+        }
+        false
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.queues.iter().all(VecDeque::is_empty)
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if let Some(limit) = self.limit {
+            let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * limit.messages_per_sec).min(limit.burst as f64);
+            self.refilled = now;
+        }
+    }
+
+    /// Takes the next message of the highest lane if the rate limit allows to send it now.
+    pub(crate) fn pop(&mut self) -> Option<Bytes> {
+        let now = Instant::now();
+        self.refill(now);
+        if self.limit.is_some() && self.tokens < 1.0 {
+            return None;
+        }
+        let lane = Lane::ALL.iter().find(|lane| !self.queues[lane.index()].is_empty())?;
+        let Queued{at: queued_at, msg, ..} = self.queues[lane.index()].pop_front()?;
+        if self.limit.is_some() {
+            self.tokens -= 1.0;
+        }
+        let wait = now.saturating_duration_since(queued_at);
+        self.stats.queued[lane.index()] -= 1;
+        self.stats.sent += 1;
+        if wait > Duration::from_millis(1) {
+            self.stats.delayed += 1;
+        }
+        self.stats.max_wait = self.stats.max_wait.max(wait);
+        let _ = self.stats_tx.send(self.stats.clone());
+        Some(msg)
+    }
+
+    /// When the next queued message may be sent, `None` if nothing is queued.
+    pub(crate) fn next_send(&self) -> Option<Instant> {
+        if self.is_empty() {
+            return None;
+        }
+        match self.limit {
+            Some(limit) if self.tokens < 1.0 => {
+                let missing = (1.0 - self.tokens) / limit.messages_per_sec;
+                Some(self.refilled + Duration::from_secs_f64(missing))
+            },
+            _ => Some(Instant::now())
+        }
+    }
+}
+
+/// Limits IB enforces on historical data requests. Breaking them gets requests rejected with
+/// error 162, reported as `IBError::Pacing`.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct HistoricalPacing {
+    /// Minimum time between identical requests.
+    pub identical_interval: Duration,
+    /// Maximum number of requests for the same contract, exchange and data type within `same_contract_window`,
+    /// 0 for no limit.
+    pub same_contract_limit: usize,
+    pub same_contract_window: Duration,
+    /// Maximum number of requests within `total_window`, 0 for no limit.
+    pub total_limit: usize,
+    pub total_window: Duration
+}
+
+impl Default for HistoricalPacing {
+    fn default() -> Self {
+        HistoricalPacing {
+            identical_interval: Duration::from_secs(15),
+            same_contract_limit: 6,
+            same_contract_window: Duration::from_secs(2),
+            total_limit: 60,
+            total_window: Duration::from_secs(600)
+        }
+    }
+}
+
+type BarsRequest = BoxFuture<'static, AsyncResult<BarSeries>>;
+
+struct SentRequest {
+    at: Instant,
+    request: Bytes,
+    contract: Bytes
+}
+
+#[derive(Default)]
+struct HistoricalState {
+    sent: VecDeque<SentRequest>,
+    in_flight: HashMap<Bytes, WeakShared<BarsRequest>>
+}
+
+/// Delays historical data requests to stay within `HistoricalPacing` and merges identical
+/// requests in flight. Shared by all clones of a client.
+pub(crate) struct HistoricalPacer {
+    limits: Option<HistoricalPacing>,
+    state: Arc<Mutex<HistoricalState>>
+}
+
+impl HistoricalPacer {
+    pub(crate) fn new(limits: Option<HistoricalPacing>) -> Self {
+        HistoricalPacer {
+            limits,
+            state: Arc::new(Mutex::new(HistoricalState::default()))
+        }
+    }
+
+    /// Runs `send` once the limits allow it, unless an identical request is in flight, whose result is
+    /// returned instead. `request` identifies the request regardless of its id, `contract` its contract,
+    /// exchange and data type.
+    pub(crate) async fn request<F>(&self, request: Bytes, contract: Bytes, send: F) -> AsyncResult<BarSeries>
+    where
+    F: Future<Output = AsyncResult<BarSeries>> + Send + 'static,
+    {
+        let shared = {
+            let mut state = self.state.lock().unwrap();
+            //a request is dropped with its last waiter
+            state.in_flight.retain(|_, weak| weak.upgrade().is_some());
+            let running = state.in_flight.get(&request)
+                .and_then(WeakShared::upgrade)
+                .filter(|shared| shared.peek().is_none());
+            match running {
+                Some(shared) => shared,
+                None => {
+                    let turn = HistoricalPacer::wait_turn(self.state.clone(), self.limits, request.clone(), contract);
+                    let shared = async move {
+                        turn.await;
+                        send.await
+                    }.boxed().shared();
+                    if let Some(weak) = shared.downgrade() {
+                        state.in_flight.insert(request, weak);
+                    }
+                    shared
+                }
+            }
+        };
+        shared.await
+    }
+
+    /// Waits until sending the request keeps within the limits and counts it as sent.
+    async fn wait_turn(state: Arc<Mutex<HistoricalState>>, limits: Option<HistoricalPacing>, request: Bytes, contract: Bytes) {
+        let limits = match limits {
+            Some(limits) => limits,
+            None => return
+        };
+        loop {
+            let next = {
+                let mut state = state.lock().unwrap();
+                let now = Instant::now();
+                let horizon = limits.total_window.max(limits.identical_interval).max(limits.same_contract_window);
+                while state.sent.front().is_some_and(|sent| now.saturating_duration_since(sent.at) >= horizon) {
+                    state.sent.pop_front();
+                }
+                match state.next_slot(&limits, &request, &contract, now) {
+                    Some(next) => next,
+                    None => {
+                        state.sent.push_back(SentRequest{at: now, request, contract});
+                        return;
+                    }
+                }
+            };
+            time::sleep_until(next).await;
+        }
+    }
+}
+
+impl HistoricalState {
+    /// Earliest time the request may be sent, `None` if it may be sent at `now`.
+    fn next_slot(&self, limits: &HistoricalPacing, request: &[u8], contract: &[u8], now: Instant) -> Option<Instant> {
+        let mut next = now;
+        if let Some(sent) = self.sent.iter().rev().find(|sent| sent.request == request) {
+            next = next.max(sent.at + limits.identical_interval);
+        }
+        //the request waits for the oldest one within the window to drop out of it
+        let same_contract: Vec<Instant> = self.sent.iter()
+            .filter(|sent| sent.contract == contract && now.saturating_duration_since(sent.at) < limits.same_contract_window)
+            .map(|sent| sent.at)
+            .collect();
+        if limits.same_contract_limit > 0 && same_contract.len() >= limits.same_contract_limit {
+            next = next.max(same_contract[same_contract.len() - limits.same_contract_limit] + limits.same_contract_window);
+        }
+        let total: Vec<Instant> = self.sent.iter()
+            .filter(|sent| now.saturating_duration_since(sent.at) < limits.total_window)
+            .map(|sent| sent.at)
+            .collect();
+        if limits.total_limit > 0 && total.len() >= limits.total_limit {
+            next = next.max(total[total.len() - limits.total_limit] + limits.total_window);
+        }
+        if next > now {Some(next)} else {None}
+    }
+}
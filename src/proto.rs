@@ -0,0 +1,247 @@
+//! Protobuf encoding of messages, used by TWS from server version `MIN_SERVER_VER_PROTOBUF` on.
+//!
+//! A protobuf message is sent with its message id plus `PROTOBUF_MSG_ID` as a 4 byte integer,
+//! followed by the encoded message. The definitions below mirror the `.proto` files of IB's API
+//! release, limited to the fields this client uses. TWS moves messages over to protobuf step by
+//! step; messages without a definition here are reported as `IBFrame::NotImplemented`, like
+//! text messages the client does not handle.
+use crate::error::DecodeError;
+use crate::frame::IBFrame;
+use crate::ib_contract;
+use crate::ib_enums::*;
+use crate::order;
+use crate::utils::ib_message::Decodable;
+use num_traits::FromPrimitive;
+use prost::Message;
+use rust_decimal::prelude::*;
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ErrorMessage {
+    #[prost(int32, optional, tag = "1")]
+    pub id: Option<i32>,
+    #[prost(int64, optional, tag = "2")]
+    pub error_time: Option<i64>,
+    #[prost(int32, optional, tag = "3")]
+    pub error_code: Option<i32>,
+    #[prost(string, optional, tag = "4")]
+    pub error_msg: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    pub advanced_order_reject_json: Option<String>
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct OrderStatus {
+    #[prost(int32, optional, tag = "1")]
+    pub order_id: Option<i32>,
+    #[prost(string, optional, tag = "2")]
+    pub status: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub filled: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub remaining: Option<String>,
+    #[prost(double, optional, tag = "5")]
+    pub avg_fill_price: Option<f64>,
+    #[prost(int64, optional, tag = "6")]
+    pub perm_id: Option<i64>,
+    #[prost(int32, optional, tag = "7")]
+    pub parent_id: Option<i32>,
+    #[prost(double, optional, tag = "8")]
+    pub last_fill_price: Option<f64>,
+    #[prost(int32, optional, tag = "9")]
+    pub client_id: Option<i32>,
+    #[prost(string, optional, tag = "10")]
+    pub why_held: Option<String>,
+    #[prost(double, optional, tag = "11")]
+    pub mkt_cap_price: Option<f64>
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Contract {
+    #[prost(int32, optional, tag = "1")]
+    pub con_id: Option<i32>,
+    #[prost(string, optional, tag = "2")]
+    pub symbol: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub sec_type: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub last_trade_date_or_contract_month: Option<String>,
+    #[prost(double, optional, tag = "5")]
+    pub strike: Option<f64>,
+    #[prost(string, optional, tag = "6")]
+    pub right: Option<String>,
+    #[prost(double, optional, tag = "7")]
+    pub multiplier: Option<f64>,
+    #[prost(string, optional, tag = "8")]
+    pub exchange: Option<String>,
+    #[prost(string, optional, tag = "9")]
+    pub primary_exch: Option<String>,
+    #[prost(string, optional, tag = "10")]
+    pub currency: Option<String>,
+    #[prost(string, optional, tag = "11")]
+    pub local_symbol: Option<String>,
+    #[prost(string, optional, tag = "12")]
+    pub trading_class: Option<String>
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Execution {
+    #[prost(int32, optional, tag = "1")]
+    pub order_id: Option<i32>,
+    #[prost(string, optional, tag = "2")]
+    pub exec_id: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub time: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub acct_number: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    pub exchange: Option<String>,
+    #[prost(string, optional, tag = "6")]
+    pub side: Option<String>,
+    #[prost(string, optional, tag = "7")]
+    pub shares: Option<String>,
+    #[prost(double, optional, tag = "8")]
+    pub price: Option<f64>,
+    #[prost(int64, optional, tag = "9")]
+    pub perm_id: Option<i64>,
+    #[prost(int32, optional, tag = "10")]
+    pub client_id: Option<i32>,
+    #[prost(bool, optional, tag = "11")]
+    pub is_liquidation: Option<bool>,
+    #[prost(string, optional, tag = "12")]
+    pub cum_qty: Option<String>,
+    #[prost(double, optional, tag = "13")]
+    pub avg_price: Option<f64>,
+    #[prost(string, optional, tag = "14")]
+    pub order_ref: Option<String>,
+    #[prost(string, optional, tag = "15")]
+    pub ev_rule: Option<String>,
+    #[prost(double, optional, tag = "16")]
+    pub ev_multiplier: Option<f64>,
+    #[prost(string, optional, tag = "17")]
+    pub model_code: Option<String>,
+    #[prost(int32, optional, tag = "18")]
+    pub last_liquidity: Option<i32>,
+    #[prost(bool, optional, tag = "19")]
+    pub is_price_revision_pending: Option<bool>,
+    #[prost(string, optional, tag = "20")]
+    pub submitter: Option<String>
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ExecutionDetails {
+    #[prost(int32, optional, tag = "1")]
+    pub req_id: Option<i32>,
+    #[prost(message, optional, tag = "2")]
+    pub contract: Option<Contract>,
+    #[prost(message, optional, tag = "3")]
+    pub execution: Option<Execution>
+}
+
+//errors of protobuf messages report the tag of the offending field as position
+fn missing(msg_id: i32, tag: usize) -> DecodeError {
+    DecodeError{msg_id: Some(msg_id), position: Some(tag), value: Some(String::new())}
+}
+
+fn req<T>(val: Option<T>, msg_id: i32, tag: usize) -> Result<T, DecodeError> {
+    val.ok_or_else(|| missing(msg_id, tag))
+}
+
+/// Decodes a field TWS sends as text, such as decimal quantities and enum values.
+fn text<T: Decodable>(val: Option<String>, msg_id: i32, tag: usize) -> Result<Option<T>, DecodeError> {
+    match val.as_deref() {
+        None | Some("") => Ok(None),
+        Some(val) => T::decode_str(val)
+            .map(Some)
+            .map_err(|_| DecodeError{msg_id: Some(msg_id), position: Some(tag), value: Some(val.to_string())})
+    }
+}
+
+fn decimal(val: Option<f64>, msg_id: i32, tag: usize) -> Result<Option<Decimal>, DecodeError> {
+    match val {
+        None => Ok(None),
+        Some(val) => Decimal::from_f64(val)
+            .map(Some)
+            .ok_or_else(|| DecodeError{msg_id: Some(msg_id), position: Some(tag), value: Some(val.to_string())})
+    }
+}
+
+fn contract(proto: Contract, msg_id: i32) -> Result<ib_contract::Contract, DecodeError> {
+    Ok(ib_contract::Contract {
+        con_id: proto.con_id,
+        symbol: proto.symbol,
+        sec_type: text(proto.sec_type, msg_id, 3)?,
+        last_trade_date_or_contract_month: proto.last_trade_date_or_contract_month,
+        strike: decimal(proto.strike, msg_id, 5)?,
+        right: text(proto.right, msg_id, 6)?,
+        multiplier: proto.multiplier.map(|multiplier| multiplier.to_string()),
+        exchange: proto.exchange,
+        primary_exchange: proto.primary_exch,
+        currency: proto.currency,
+        local_symbol: proto.local_symbol,
+        trading_class: proto.trading_class,
+        ..Default::default()
+    })
+}
+
+/// Decodes the protobuf message `msg_id`, given without the `PROTOBUF_MSG_ID` offset.
+pub(crate) fn parse(msg_id: i32, msg: &[u8]) -> Result<IBFrame, DecodeError> {
+    let msg_type: Incoming = match FromPrimitive::from_i32(msg_id) {
+        Some(msg_type) => msg_type,
+        None => return Err(DecodeError{msg_id: Some(msg_id), position: Some(1), value: Some(msg_id.to_string())})
+    };
+    let truncated = |_| DecodeError{msg_id: Some(msg_id), ..Default::default()};
+    Ok(match msg_type {
+        Incoming::ErrMsg => {
+            let error = ErrorMessage::decode(msg).map_err(truncated)?;
+            IBFrame::Error {
+                id: error.id.unwrap_or(-1),
+                code: req(error.error_code, msg_id, 3)?,
+                msg: error.error_msg.unwrap_or_default()
+            }
+        },
+        Incoming::OrderStatus => {
+            let status = OrderStatus::decode(msg).map_err(truncated)?;
+            IBFrame::OrderStatus(order::OrderStatus {
+                order_id: req(status.order_id, msg_id, 1)?,
+                status: req(status.status, msg_id, 2)?,
+                filled: req(text(status.filled, msg_id, 3)?, msg_id, 3)?,
+                remaining: req(text(status.remaining, msg_id, 4)?, msg_id, 4)?,
+                avg_fill_price: decimal(status.avg_fill_price, msg_id, 5)?.unwrap_or_default(),
+                perm_id: status.perm_id.unwrap_or_default(),
+                parent_id: status.parent_id.unwrap_or_default() as usize,
+                last_fill_price: decimal(status.last_fill_price, msg_id, 8)?.unwrap_or_default(),
+                client_id: status.client_id.unwrap_or_default() as usize,
+                why_held: status.why_held,
+                mkt_cap_price: decimal(status.mkt_cap_price, msg_id, 11)?
+            })
+        },
+        Incoming::ExecutionData => {
+            let details = ExecutionDetails::decode(msg).map_err(truncated)?;
+            let execution = req(details.execution, msg_id, 3)?;
+            IBFrame::Execution(Box::new(order::Execution {
+                order_id: req(execution.order_id, msg_id, 1)?,
+                contract: contract(req(details.contract, msg_id, 2)?, msg_id)?,
+                exec_id: req(execution.exec_id, msg_id, 2)?,
+                time: req(execution.time, msg_id, 3)?,
+                acct_number: req(execution.acct_number, msg_id, 4)?,
+                exchange: req(execution.exchange, msg_id, 5)?,
+                side: req(text(execution.side, msg_id, 6)?, msg_id, 6)?,
+                shares: req(text(execution.shares, msg_id, 7)?, msg_id, 7)?,
+                price: req(decimal(execution.price, msg_id, 8)?, msg_id, 8)?,
+                perm_id: execution.perm_id.unwrap_or_default(),
+                client_id: execution.client_id.unwrap_or_default() as usize,
+                liquidation: execution.is_liquidation.unwrap_or_default() as i32,
+                cum_qty: text(execution.cum_qty, msg_id, 12)?.unwrap_or_default(),
+                avg_price: decimal(execution.avg_price, msg_id, 13)?.unwrap_or_default(),
+                order_ref: execution.order_ref,
+                ev_rule: execution.ev_rule,
+                ev_multiplier: decimal(execution.ev_multiplier, msg_id, 16)?,
+                model_code: execution.model_code,
+                last_liquidity: execution.last_liquidity,
+                pending_price_revision: execution.is_price_revision_pending,
+                submitter: execution.submitter
+            }))
+        },
+        _ => IBFrame::NotImplemented
+    })
+}
@@ -0,0 +1,230 @@
+//! Recording of the raw wire traffic of a session, and replay of recordings through the client.
+//!
+//! A recording has one line per message: an RFC 3339 timestamp, the direction (`in` from TWS,
+//! `out` to TWS) and the message payload without its length prefix, separated by tabs. Null
+//! separators are written as `\0`, backslashes as `\\` and other non-printable bytes as `\xNN`.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use rs_ib_api::ib_client::{IBClient, ConnectOptions};
+//! use rs_ib_api::recorder::{self, Recorder, ReplaySpeed};
+//!
+//! let recorder = Recorder::create("session.log")?;
+//! let client = IBClient::connect(ConnectOptions::new(4002, 1).record_to(recorder)).await?;
+//! //...
+//! let replayed = recorder::replay("session.log", ConnectOptions::new(4002, 1), ReplaySpeed::AsFastAsPossible).await?;
+//! # Ok(())
+//! # }
+//! ```
+use crate::error::AsyncResult;
+use crate::ib_client::{IBClient, ConnectOptions};
+use crate::ib_enums::Outgoing;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Direction {
+    /// Received from TWS.
+    In,
+    /// Sent to TWS.
+    Out
+}
+
+enum Command {
+    Write(String),
+    Flush(oneshot::Sender<io::Result<()>>)
+}
+
+/// Appends the messages of a session to a file. Clones write to the same file.
+///
+/// The file is written by a thread of its own, so recording never blocks the client's tasks. The
+/// thread stops once the last clone is dropped.
+#[derive(Debug,Clone)]
+pub struct Recorder {
+    tx: mpsc::UnboundedSender<Command>
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = LineWriter::new(File::create(path)?);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        thread::Builder::new().name("ib-recorder".to_string()).spawn(move || {
+            while let Some(cmd) = rx.blocking_recv() {
+                match cmd {
+                    Command::Write(line) => if let Err(err) = file.write_all(line.as_bytes()) {
+                        tracing::warn!(error = %err, "Could not record message");
+                    },
+                    Command::Flush(ack) => {
+                        let _ = ack.send(file.flush());
+                    }
+                }
+            }
+        })?;
+        Ok(Recorder{tx})
+    }
+
+    pub(crate) fn record(&self, direction: Direction, payload: &[u8]) {
+        let line = format!("{}\t{}\t{}\n", Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            match direction {Direction::In => "in", Direction::Out => "out"}, escape(payload));
+        //only fails once the writer thread is gone, which happens after the last clone is dropped
+        let _ = self.tx.send(Command::Write(line));
+    }
+
+    /// Waits until all messages recorded so far are written to the file.
+    pub async fn flush(&self) -> io::Result<()> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        let stopped = || io::Error::new(io::ErrorKind::BrokenPipe, "Recorder stopped");
+        self.tx.send(Command::Flush(ack_tx)).map_err(|_| stopped())?;
+        ack_rx.await.map_err(|_| stopped())?
+    }
+}
+
+/// A message read back from a recording.
+#[derive(Debug,Clone)]
+pub struct RecordedFrame {
+    pub time: DateTime<Utc>,
+    pub direction: Direction,
+    pub payload: Vec<u8>
+}
+
+fn escape(payload: &[u8]) -> String {
+    let mut escaped = String::with_capacity(payload.len());
+    for byte in payload {
+        match byte {
+            0 => escaped.push_str("\\0"),
+            b'\\' => escaped.push_str("\\\\"),
+            0x20..=0x7e => escaped.push(*byte as char),
+            _ => {let _ = write!(escaped, "\\x{:02x}", byte);}
+        }
+    }
+    escaped
+}
+
+fn unescape(escaped: &str) -> Option<Vec<u8>> {
+    let mut payload = Vec::with_capacity(escaped.len());
+    let mut bytes = escaped.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            payload.push(byte);
+            continue;
+        }
+        match bytes.next()? {
+            b'0' => payload.push(0),
+            b'\\' => payload.push(b'\\'),
+            b'x' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                payload.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            },
+            _ => return None
+        }
+    }
+    Some(payload)
+}
+
+fn is_current_time_request(payload: &[u8]) -> bool {
+    matches!(Outgoing::of_payload(payload), Some(Outgoing::ReqCurrentTime))
+}
+
+/// Reads all messages of a recording.
+pub fn read_recording<P: AsRef<Path>>(path: P) -> io::Result<Vec<RecordedFrame>> {
+    let mut frames = Vec::new();
+    for (n, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid recording at line {}", n + 1));
+        let mut parts = line.splitn(3, '\t');
+        let time = parts.next().and_then(|time| DateTime::parse_from_rfc3339(time).ok()).ok_or_else(invalid)?;
+        let direction = match parts.next() {
+            Some("in") => Direction::In,
+            Some("out") => Direction::Out,
+            _ => return Err(invalid())
+        };
+        let payload = parts.next().and_then(unescape).ok_or_else(invalid)?;
+        frames.push(RecordedFrame{time: time.with_timezone(&Utc), direction, payload});
+    }
+    Ok(frames)
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum ReplaySpeed {
+    /// Keep the original gaps between incoming messages.
+    Original,
+    AsFastAsPossible
+}
+
+/// Connects a client to a recorded session instead of TWS.
+///
+/// Incoming messages are delivered in lockstep with the client: a message is only sent once the
+/// client has sent as many messages as it had at that point of the recording, so responses never
+/// arrive before the requests the client makes during replay. The client has to issue its requests
+/// in the same order as during the recording to get the same request ids. Current time requests are
+/// left out of the count, as heartbeats go out on a timer rather than in step with the session.
+pub async fn replay<P: AsRef<Path>>(path: P, options: ConnectOptions, speed: ReplaySpeed) -> AsyncResult<IBClient> {
+    let frames = read_recording(path)?;
+    let (client_end, gateway_end) = tokio::io::duplex(64 * 1024);
+    let (mut recv, mut trans) = tokio::io::split(gateway_end);
+    //count the messages sent by the client, starting with the raw "API\0" prefix
+    let (sent_tx, mut sent_rx) = watch::channel(0usize);
+    let counter = tokio::spawn(async move {
+        let mut prefix = [0u8; 4];
+        if recv.read_exact(&mut prefix).await.is_err() {
+            return;
+        }
+        let mut sent = 1;
+        let _ = sent_tx.send(sent);
+        loop {
+            let mut len = [0u8; 4];
+            if recv.read_exact(&mut len).await.is_err() {
+                return;
+            }
+            let mut msg = vec![0u8; u32::from_be_bytes(len) as usize];
+            if recv.read_exact(&mut msg).await.is_err() {
+                return;
+            }
+            if !is_current_time_request(&msg) {
+                sent += 1;
+                let _ = sent_tx.send(sent);
+            }
+        }
+    });
+    tokio::spawn(async move {
+        let start = time::Instant::now();
+        let first = frames.first().map(|frame| frame.time);
+        let mut expected = 0;
+        for frame in frames {
+            if frame.direction == Direction::Out {
+                if !is_current_time_request(&frame.payload) {
+                    expected += 1;
+                }
+                continue;
+            }
+            while *sent_rx.borrow() < expected {
+                if sent_rx.changed().await.is_err() {
+                    return;
+                }
+            }
+            if let (ReplaySpeed::Original, Some(first)) = (speed, first) {
+                let offset = (frame.time - first).to_std().unwrap_or(Duration::from_secs(0));
+                time::sleep_until(start + offset).await;
+            }
+            let mut msg = (frame.payload.len() as u32).to_be_bytes().to_vec();
+            msg.extend_from_slice(&frame.payload);
+            if trans.write_all(&msg).await.is_err() {
+                return;
+            }
+        }
+        //keep the session open until the client goes away
+        let _ = counter.await;
+    });
+    IBClient::connect_with_stream(client_end, options).await
+}
@@ -0,0 +1,266 @@
+//! Requests sent to TWS.
+//!
+//! Every request the client sends is an [`IBRequest`], encoded for the server version negotiated
+//! on connect. Requests decode again the way TWS reads them, so a fake gateway or a proxy between
+//! client and TWS can work with the same types.
+use crate::error::DecodeError;
+use crate::ib_contract::Contract;
+use crate::ib_enums::*;
+use crate::order::Order;
+use crate::utils::ib_message::{Decodable, Encodable, FieldCursor, MessageBuilder};
+use bytes::{BufMut, Bytes};
+use std::convert::TryFrom;
+
+#[derive(Debug,Clone)]
+pub enum IBRequest {
+    StartApi{client_id: i32, optional_capabilities: String},
+    /// Asks for the next valid order id.
+    ReqIds,
+    /// `account` is only needed for financial advisor accounts.
+    ReqAccountUpdates{subscribe: bool, account: Option<String>},
+    ReqMarketData{
+        req_id: i32, contract: Box<Contract>, generic_ticks: Vec<GenericTickType>, snapshot: bool, regulatory_snapshot: bool
+    },
+    CancelMarketData{req_id: i32},
+    ReqMarketDataType(MarketDataType),
+    ReqCurrentTime,
+    /// The contract's `issuer_id` is sent from server version `MIN_SERVER_VER_BOND_ISSUERID` on.
+    ReqContractDetails{req_id: i32, contract: Box<Contract>},
+    PlaceOrder{order_id: i32, order: Box<Order>},
+    ReqOpenOrders,
+    /// Without `what_to_show` adjusted trades are requested, which can only end now and so have
+    /// no `end_date_time`.
+    ReqHistoricalData{
+        req_id: i32, contract: Box<Contract>, end_date_time: Option<String>, bar_size: HistoricalDataBarSize,
+        duration: HistoricalDataDuration, use_rth: bool, what_to_show: Option<HistoricalDataType>
+    },
+    CancelHistoricalData{req_id: i32}
+}
+
+impl IBRequest {
+    pub fn kind(&self) -> Outgoing {
+        match self {
+            IBRequest::StartApi{..} => Outgoing::StartApi,
+            IBRequest::ReqIds => Outgoing::ReqIds,
+            IBRequest::ReqAccountUpdates{..} => Outgoing::ReqAcctData,
+            IBRequest::ReqMarketData{..} => Outgoing::ReqMktData,
+            IBRequest::CancelMarketData{..} => Outgoing::CancelMktData,
+            IBRequest::ReqMarketDataType(_) => Outgoing::ReqMarketDataType,
+            IBRequest::ReqCurrentTime => Outgoing::ReqCurrentTime,
+            IBRequest::ReqContractDetails{..} => Outgoing::ReqContractData,
+            IBRequest::PlaceOrder{..} => Outgoing::PlaceOrder,
+            IBRequest::ReqOpenOrders => Outgoing::ReqOpenOrders,
+            IBRequest::ReqHistoricalData{..} => Outgoing::ReqHistoricalData,
+            IBRequest::CancelHistoricalData{..} => Outgoing::CancelHistoricalData
+        }
+    }
+
+    /// Request or order id the answers to this request refer to.
+    pub fn id(&self) -> Option<i32> {
+        match self {
+            IBRequest::ReqMarketData{req_id, ..} | IBRequest::CancelMarketData{req_id} | IBRequest::ReqContractDetails{req_id, ..}
+            | IBRequest::ReqHistoricalData{req_id, ..} | IBRequest::CancelHistoricalData{req_id} => Some(*req_id),
+            IBRequest::PlaceOrder{order_id, ..} => Some(*order_id),
+            _ => None
+        }
+    }
+
+    pub(crate) fn set_id(&mut self, id: i32) {
+        match self {
+            IBRequest::ReqMarketData{req_id, ..} | IBRequest::CancelMarketData{req_id} | IBRequest::ReqContractDetails{req_id, ..}
+            | IBRequest::ReqHistoricalData{req_id, ..} | IBRequest::CancelHistoricalData{req_id} => *req_id = id,
+            IBRequest::PlaceOrder{order_id, ..} => *order_id = id,
+            _ => ()
+        }
+    }
+
+    /// The length-prefixed message for TWS running `server_version`.
+    pub fn encode(&self, server_version: i32) -> Bytes {
+        let mut msg = MessageBuilder::new(self.kind().ordinal() as i32, server_version);
+        match self {
+            IBRequest::StartApi{client_id, optional_capabilities} => {
+                msg.push_raw(b"2\0").push(client_id).push(optional_capabilities);
+            },
+            IBRequest::ReqIds => {
+                msg.push_raw(b"1\x001\0"); //version and number of ids, which TWS ignores
+            },
+            IBRequest::ReqAccountUpdates{subscribe, account} => {
+                msg.push_raw(b"2\0").push(subscribe).push(account);
+            },
+            IBRequest::ReqMarketData{req_id, contract, generic_ticks, snapshot, regulatory_snapshot} => {
+                msg.push_raw(b"11\0").push(req_id);
+                let buf = msg.buf();
+                contract.encode_for_ticker(buf);
+                buf.put_slice(b"0\0"); //no delta neutral contract
+                for (i, tick) in generic_ticks.iter().enumerate() {
+                    if i > 0 {
+                        buf.put_u8(b',');
+                    }
+                    tick.encode(buf);
+                }
+                buf.put_u8(0);
+                msg.push(snapshot);
+                if server_version >= constants::MIN_SERVER_VER_REQ_SMART_COMPONENTS {
+                    msg.push(regulatory_snapshot);
+                }
+                msg.push_raw(b"\0"); //market data options
+            },
+            IBRequest::CancelMarketData{req_id} => {
+                msg.push_raw(b"2\0").push(req_id);
+            },
+            IBRequest::ReqMarketDataType(kind) => {
+                msg.push_raw(b"1\0").push(kind);
+            },
+            IBRequest::ReqCurrentTime => {
+                msg.push_raw(b"1\0");
+            },
+            IBRequest::ReqContractDetails{req_id, contract} => {
+                msg.push_raw(b"8\0").push(req_id).push(contract.as_ref());
+                if server_version >= constants::MIN_SERVER_VER_BOND_ISSUERID {
+                    msg.push(&contract.issuer_id);
+                }
+            },
+            IBRequest::PlaceOrder{order_id, order} => {
+                if server_version < constants::MIN_SERVER_VER_ORDER_CONTAINER {
+                    msg.push_raw(b"45\0"); //version
+                }
+                msg.push(order_id);
+                order.encode(msg.buf(), server_version);
+            },
+            IBRequest::ReqOpenOrders => {
+                msg.push_raw(b"1\0");
+            },
+            IBRequest::ReqHistoricalData{req_id, contract, end_date_time, bar_size, duration, use_rth, what_to_show} => {
+                let synthetic_bars = server_version >= constants::MIN_SERVER_VER_SYNT_REALTIME_BARS;
+                if !synthetic_bars {
+                    msg.push_raw(b"6\0"); //version
+                }
+                msg.push(req_id);
+                contract.encode_for_hist_data(msg.buf());
+                msg.push(end_date_time).push(bar_size).push(duration).push(use_rth);
+                match what_to_show {
+                    Some(what_to_show) => msg.push(what_to_show),
+                    None => msg.push_raw(b"ADJUSTED_LAST\0")
+                };
+                //date format, keep up to date and chart options
+                msg.push_raw(b"1\0");
+                if synthetic_bars {
+                    msg.push_raw(b"0\0");
+                }
+                msg.push_raw(b"\0");
+            },
+            IBRequest::CancelHistoricalData{req_id} => {
+                msg.push_raw(b"1\0").push(req_id);
+            }
+        }
+        msg.finish()
+    }
+
+    /// Decodes a message sent to TWS running `server_version`, without its length prefix.
+    /// Requests this crate does not send are an error.
+    pub fn decode(msg: &[u8], server_version: i32) -> Result<Self, DecodeError> {
+        let (msg_id, mut it) = if server_version >= constants::MIN_SERVER_VER_PROTOBUF {
+            if msg.len() < 4 {
+                return Err(DecodeError::default());
+            }
+            let msg_id = i32::from_be_bytes([msg[0], msg[1], msg[2], msg[3]]);
+            (msg_id, FieldCursor::after_msg_id(&msg[4..], msg_id))
+        }
+        else {
+            let mut it = FieldCursor::new(msg);
+            let msg_id: i32 = it.req()?;
+            it.set_msg_id(msg_id);
+            (msg_id, it)
+        };
+        let unknown = || DecodeError{msg_id: Some(msg_id), position: Some(1), value: Some(msg_id.to_string())};
+        let kind = match i8::try_from(msg_id).ok().and_then(Outgoing::from_ordinal) {
+            Some(kind) => kind,
+            None => return Err(unknown())
+        };
+        Ok(match kind {
+            Outgoing::StartApi => {
+                it.skip()?; //skip version
+                IBRequest::StartApi {
+                    client_id: it.req()?,
+                    optional_capabilities: it.opt()?.unwrap_or_default()
+                }
+            },
+            Outgoing::ReqIds => IBRequest::ReqIds,
+            Outgoing::ReqAcctData => {
+                it.skip()?; //skip version
+                IBRequest::ReqAccountUpdates {
+                    subscribe: it.req()?,
+                    account: it.opt()?
+                }
+            },
+            Outgoing::ReqMktData => {
+                it.skip()?; //skip version
+                let req_id = it.req()?;
+                let contract = Box::new(Contract::decode_for_ticker(&mut it)?);
+                it.skip()?; //delta neutral contract flag
+                let mut generic_ticks = Vec::new();
+                if let Some(ticks) = it.opt_str()? {
+                    for tick in ticks.split(',') {
+                        generic_ticks.push(GenericTickType::decode_str(tick)?);
+                    }
+                }
+                IBRequest::ReqMarketData {
+                    req_id,
+                    contract,
+                    generic_ticks,
+                    snapshot: it.req()?,
+                    regulatory_snapshot: if server_version >= constants::MIN_SERVER_VER_REQ_SMART_COMPONENTS {it.req()?} else {false}
+                }
+            },
+            Outgoing::CancelMktData => {
+                it.skip()?; //skip version
+                IBRequest::CancelMarketData{req_id: it.req()?}
+            },
+            Outgoing::ReqMarketDataType => {
+                it.skip()?; //skip version
+                IBRequest::ReqMarketDataType(it.req()?)
+            },
+            Outgoing::ReqCurrentTime => IBRequest::ReqCurrentTime,
+            Outgoing::ReqContractData => {
+                it.skip()?; //skip version
+                let req_id = it.req()?;
+                let mut contract = Contract::decode(&mut it)?;
+                if server_version >= constants::MIN_SERVER_VER_BOND_ISSUERID {
+                    contract.issuer_id = it.opt()?;
+                }
+                IBRequest::ReqContractDetails{req_id, contract: Box::new(contract)}
+            },
+            Outgoing::PlaceOrder => {
+                if server_version < constants::MIN_SERVER_VER_ORDER_CONTAINER {
+                    it.skip()?; //skip version
+                }
+                let order_id = it.req()?;
+                let mut order = Order::decode(&mut it, server_version)?;
+                order.order_id = order_id;
+                IBRequest::PlaceOrder{order_id, order: Box::new(order)}
+            },
+            Outgoing::ReqOpenOrders => IBRequest::ReqOpenOrders,
+            Outgoing::ReqHistoricalData => {
+                if server_version < constants::MIN_SERVER_VER_SYNT_REALTIME_BARS {
+                    it.skip()?; //skip version
+                }
+                let req_id = it.req()?;
+                let contract = Box::new(Contract::decode_for_hist_data(&mut it)?);
+                let end_date_time = it.opt()?;
+                let bar_size = it.req()?;
+                let duration = it.req()?;
+                let use_rth = it.req()?;
+                let what_to_show = match it.req_str()? {
+                    "ADJUSTED_LAST" => None,
+                    what_to_show => Some(HistoricalDataType::decode_str(what_to_show)?)
+                };
+                IBRequest::ReqHistoricalData{req_id, contract, end_date_time, bar_size, duration, use_rth, what_to_show}
+            },
+            Outgoing::CancelHistoricalData => {
+                it.skip()?; //skip version
+                IBRequest::CancelHistoricalData{req_id: it.req()?}
+            },
+            _ => return Err(unknown())
+        })
+    }
+}
@@ -29,22 +29,22 @@ impl fmt::Display for ShortAvailability {
 
 pub struct Ticker {
     bid: watch::Receiver<Option<f64>>,
-    bid_size: watch::Receiver<Option<i32>>,
+    bid_size: watch::Receiver<Option<Decimal>>,
     ask: watch::Receiver<Option<f64>>,
-    ask_size: watch::Receiver<Option<i32>>,
+    ask_size: watch::Receiver<Option<Decimal>>,
     last: watch::Receiver<Option<f64>>,
-    last_size: watch::Receiver<Option<i32>>,
+    last_size: watch::Receiver<Option<Decimal>>,
     shortable_shares: watch::Receiver<Option<i32>>,
     short_availability: watch::Receiver<Option<ShortAvailability>>
 }
 
 pub struct TickerSender {
     pub bid: watch::Sender<Option<f64>>,
-    pub bid_size: watch::Sender<Option<i32>>,
+    pub bid_size: watch::Sender<Option<Decimal>>,
     pub ask: watch::Sender<Option<f64>>,
-    pub ask_size: watch::Sender<Option<i32>>,
+    pub ask_size: watch::Sender<Option<Decimal>>,
     pub last: watch::Sender<Option<f64>>,
-    pub last_size: watch::Sender<Option<i32>>,
+    pub last_size: watch::Sender<Option<Decimal>>,
     pub shortable_shares: watch::Sender<Option<i32>>,
     pub short_availability: watch::Sender<Option<ShortAvailability>>
 }
@@ -101,11 +101,11 @@ impl Ticker {
         self.ask.borrow().clone()
     }
 
-    pub fn bid_size(&self) -> Option<i32> {
+    pub fn bid_size(&self) -> Option<Decimal> {
         self.bid_size.borrow().clone()
     }
 
-    pub fn ask_size(&self) -> Option<i32> {
+    pub fn ask_size(&self) -> Option<Decimal> {
         self.ask_size.borrow().clone()
     }
 
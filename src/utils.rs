@@ -1,13 +1,17 @@
 pub mod ib_message {
     use std::{convert::TryInto};
-    use std::{error::Error, fmt};
     use std::str;
+    use crate::error::DecodeError;
+    use crate::ib_enums::constants;
+    use bytes::{BufMut, Bytes, BytesMut};
     use rust_decimal::prelude::*;
+    use std::cell::RefCell;
+    use std::fmt::Write;
     pub trait IBMessage {
         fn to_ib_message(&self) -> Result<Vec<u8>, std::num::TryFromIntError>;
     }
 
-    impl IBMessage for &str {
+    impl IBMessage for &[u8] {
         fn to_ib_message(&self) -> Result<Vec<u8>, std::num::TryFromIntError> {
             let msg_len: u32 = match self.len().try_into() {
                 Ok(val) => val,
@@ -16,190 +20,435 @@ pub mod ib_message {
             let len_bytes = msg_len.to_be_bytes();
             let mut res = Vec::with_capacity(self.len() + 4);
             res.extend_from_slice(&len_bytes);
-            res.extend_from_slice(&self.as_bytes());
+            res.extend_from_slice(self);
             Ok(res)
         }
     }
-    #[derive(Debug)]
-    pub struct IBDecodeError;
-    impl Error for IBDecodeError {}
-
-    impl fmt::Display for IBDecodeError {
-        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            write!(f, "Decoding value failed!")
-        }
-    }
     pub trait Decodable
     where
     Self: std::str::FromStr + Sized,
     {
-        fn decode_str(val: &str) -> Result<Self, IBDecodeError> {
+        fn decode_str(val: &str) -> Result<Self, DecodeError> {
             match Self::from_str(val) {
                 Ok(val) => Ok(val),
-                Err(_) => Err(IBDecodeError{})
+                Err(_) => Err(DecodeError::new(val))
+            }
+        }
+
+        /// Decodes a raw field. Types without a parser of their own go through `decode_str`,
+        /// which does not allocate for valid UTF-8.
+        fn decode_bytes(val: &[u8]) -> Result<Self, DecodeError> {
+            match str::from_utf8(val) {
+                Ok(val) => Self::decode_str(val),
+                Err(_) => Err(DecodeError::new(&String::from_utf8_lossy(val)))
             }
         }
     }
 
-    impl Decodable for i32 {}
-    impl Decodable for u32 {}
-    impl Decodable for usize {}
-    impl Decodable for isize {}
+    //integers are parsed straight from the bytes, with the same rules as `FromStr`
+    macro_rules! decodable_int {
+        ($($int:ty),*) => {$(
+            impl Decodable for $int {
+                fn decode_bytes(val: &[u8]) -> Result<Self, DecodeError> {
+                    let (negative, digits) = match val {
+                        [b'-', digits @ ..] if <$int>::MIN != 0 => (true, digits),
+                        [b'+', digits @ ..] => (false, digits),
+                        digits => (false, digits)
+                    };
+                    if digits.is_empty() {
+                        return Err(DecodeError::new(&String::from_utf8_lossy(val)));
+                    }
+                    let mut res: $int = 0;
+                    for digit in digits {
+                        let next = match digit {
+                            b'0'..=b'9' => res.checked_mul(10).and_then(|res| {
+                                let digit = (digit - b'0') as $int;
+                                if negative {res.checked_sub(digit)} else {res.checked_add(digit)}
+                            }),
+                            _ => None
+                        };
+                        res = match next {
+                            Some(next) => next,
+                            None => return Err(DecodeError::new(&String::from_utf8_lossy(val)))
+                        };
+                    }
+                    Ok(res)
+                }
+            }
+        )*}
+    }
+
+    decodable_int!(i32, u32, usize, isize, i64);
     impl Decodable for f64 {}
     impl Decodable for Decimal {}
-    impl Decodable for String {}
-    impl Decodable for i64 {}
+    impl Decodable for String {
+        //strings keep the lossy conversion of invalid UTF-8 the reader always applied
+        fn decode_bytes(val: &[u8]) -> Result<Self, DecodeError> {
+            Ok(String::from_utf8_lossy(val).into_owned())
+        }
+    }
 
-    
     impl Decodable for bool {
-        fn decode_str(val: &str) -> Result<Self, IBDecodeError> {
+        fn decode_str(val: &str) -> Result<Self, DecodeError> {
             match val {
                 "" | "0" => Ok(false),
                 "1" => Ok(true),
-                &_ => Err(IBDecodeError{})
+                &_ => Err(DecodeError::new(val))
             }
         }
     }
 
-    pub fn decode<T>(stream: &mut std::str::Split<'_, &str>) -> Option<T> 
-    where
-    T: std::str::FromStr + Sized + Decodable,
-    <T as std::str::FromStr>::Err: std::fmt::Debug,
-    {
-        let str_val = stream.next().unwrap();
-        //println!("{}", str_val);
-        match str_val {
-            "" | "1.7976931348623157E308" => return None,
-            _ => match T::decode_str(str_val) {
-                Ok(val) => return Some(val),
-                Err(_) => panic!{"{} could not be decoded", str_val}
+    /// Reads the fields of an incoming message one by one, reporting the position of the field
+    /// that failed instead of panicking on malformed or truncated messages.
+    ///
+    /// Fields are decoded straight from the received bytes: numbers are parsed in place and
+    /// only fields decoded as `String` are copied, see `opt_str` for borrowing them instead.
+    pub struct FieldCursor<'a> {
+        rest: Option<&'a [u8]>,
+        position: usize,
+        msg_id: Option<i32>
+    }
+
+    impl<'a> FieldCursor<'a> {
+        pub fn new(msg: &'a [u8]) -> Self {
+            FieldCursor {
+                rest: Some(msg),
+                position: 0,
+                msg_id: None
+            }
+        }
+
+        /// Cursor over the fields following a message id that was sent as a binary integer.
+        pub fn after_msg_id(msg: &'a [u8], msg_id: i32) -> Self {
+            FieldCursor {
+                rest: Some(msg),
+                position: 1,
+                msg_id: Some(msg_id)
+            }
+        }
+
+        /// Message id included in errors for the remaining fields.
+        pub fn set_msg_id(&mut self, msg_id: i32) {
+            self.msg_id = Some(msg_id);
+        }
+
+        fn error(&self, value: Option<&[u8]>) -> DecodeError {
+            DecodeError {
+                msg_id: self.msg_id,
+                position: Some(self.position),
+                value: value.map(|val| String::from_utf8_lossy(val).into_owned())
+            }
+        }
+
+        //like splitting on '\0', the terminator of the last field is followed by an empty field
+        fn next_field(&mut self) -> Result<&'a [u8], DecodeError> {
+            self.position += 1;
+            let rest = match self.rest {
+                Some(rest) => rest,
+                None => return Err(self.error(None))
+            };
+            match rest.iter().position(|byte| *byte == 0) {
+                Some(end) => {
+                    self.rest = Some(&rest[end + 1..]);
+                    Ok(&rest[..end])
+                },
+                None => {
+                    self.rest = None;
+                    Ok(rest)
+                }
+            }
+        }
+
+        pub fn skip(&mut self) -> Result<(), DecodeError> {
+            self.next_field().map(|_| ())
+        }
+
+        /// Decodes an optional field, empty values and IB's "unset" double are `None`.
+        pub fn opt<T: Decodable>(&mut self) -> Result<Option<T>, DecodeError> {
+            let val = self.next_field()?;
+            match val {
+                b"" | b"1.7976931348623157E308" => Ok(None),
+                _ => match T::decode_bytes(val) {
+                    Ok(val) => Ok(Some(val)),
+                    Err(_) => Err(self.error(Some(val)))
+                }
+            }
+        }
+
+        /// Decodes a field that has to be set.
+        pub fn req<T: Decodable>(&mut self) -> Result<T, DecodeError> {
+            match self.opt()? {
+                Some(val) => Ok(val),
+                None => Err(self.error(Some(b"")))
+            }
+        }
+
+        /// Borrows an optional text field from the message, empty values are `None`.
+        pub fn opt_str(&mut self) -> Result<Option<&'a str>, DecodeError> {
+            let val = self.next_field()?;
+            match str::from_utf8(val) {
+                Ok("") => Ok(None),
+                Ok(val) => Ok(Some(val)),
+                Err(_) => Err(self.error(Some(val)))
+            }
+        }
+
+        /// Borrows a text field that has to be set.
+        pub fn req_str(&mut self) -> Result<&'a str, DecodeError> {
+            match self.opt_str()? {
+                Some(val) => Ok(val),
+                None => Err(self.error(Some(b"")))
             }
         }
     }
 
-    pub trait Encodable 
+    /// A field of an outgoing message, written to the message buffer together with its terminating null.
+    pub trait Encodable
     {
-        fn encode(&self) -> String;
+        fn encode(&self, buf: &mut BytesMut);
     }
 
     impl Encodable for f64 {
-        fn encode(&self) -> String {
-            self.to_string() +"\0"
+        fn encode(&self, buf: &mut BytesMut) {
+            let _ = write!(buf, "{}\0", self);
         }
     }
     impl Encodable for i32 {
-        fn encode(&self) -> String {
-            self.to_string() +"\0"
+        fn encode(&self, buf: &mut BytesMut) {
+            let _ = write!(buf, "{}\0", self);
         }
     }
     impl Encodable for i64 {
-        fn encode(&self) -> String {
-            self.to_string() +"\0"
+        fn encode(&self, buf: &mut BytesMut) {
+            let _ = write!(buf, "{}\0", self);
         }
     }
     impl Encodable for Decimal {
-        fn encode(&self) -> String {
-            self.to_string() +"\0"
+        fn encode(&self, buf: &mut BytesMut) {
+            let _ = write!(buf, "{}\0", self);
         }
     }
     impl Encodable for usize {
-        fn encode(&self) -> String {
-            self.to_string() +"\0"
+        fn encode(&self, buf: &mut BytesMut) {
+            let _ = write!(buf, "{}\0", self);
+        }
+    }
+
+    impl Encodable for isize {
+        fn encode(&self, buf: &mut BytesMut) {
+            let _ = write!(buf, "{}\0", self);
         }
     }
     impl Encodable for String {
-        fn encode(&self) -> String {
-            self.to_string() +"\0"
+        fn encode(&self, buf: &mut BytesMut) {
+            self.as_str().encode(buf);
         }
     }
     impl Encodable for &str {
-        fn encode(&self) -> String {
-            self.to_string() +"\0"
+        fn encode(&self, buf: &mut BytesMut) {
+            buf.put_slice(self.as_bytes());
+            buf.put_u8(0);
         }
     }
 
     impl Encodable for bool {
-        fn encode(&self) -> String {
-            if *self {"1\0".to_string()} else {"0\0".to_string()}
+        fn encode(&self, buf: &mut BytesMut) {
+            buf.put_slice(if *self {b"1\0"} else {b"0\0"});
         }
     }
 
     impl<T: Encodable> Encodable for Option<T> {
-        fn encode(&self) -> String {
+        fn encode(&self, buf: &mut BytesMut) {
             match self {
-                Some(val) => val.encode(),
-                None => "\0".to_string()
+                Some(val) => val.encode(buf),
+                None => buf.put_u8(0)
             }
         }
     }
 
     impl Encodable for Vec<(String,String)> {
-        fn encode(&self) -> String {
-            let mut code = String::new();
+        fn encode(&self, buf: &mut BytesMut) {
             for tv in self {
-                code.push_str(&tv.0);
-                code.push_str("=");
-                code.push_str(&tv.1);
-                code.push_str(";");
+                buf.put_slice(tv.0.as_bytes());
+                buf.put_u8(b'=');
+                buf.put_slice(tv.1.as_bytes());
+                buf.put_u8(b';');
             }
-            code.push_str("\0");
-            code
+            buf.put_u8(0);
         }
     }
 
-    pub fn push_enc<T: Encodable>(str: &mut String, val: T) {
-        str.push_str(&val.encode());
+    //most requests fit, orders grow the buffer once
+    const MESSAGE_CAPACITY: usize = 512;
+
+    thread_local! {
+        //messages are split off this buffer, its allocation is reclaimed once they have been sent and dropped
+        static SCRATCH: RefCell<BytesMut> = RefCell::new(BytesMut::new());
+    }
+
+    /// Builds a message in place, the length prefix is reserved up front and filled in by `finish`.
+    pub struct MessageBuilder {
+        buf: BytesMut
+    }
+
+    impl MessageBuilder {
+        /// Starts a message for `server_version`, which from `MIN_SERVER_VER_PROTOBUF` on expects
+        /// the message id as a 4 byte integer instead of a text field.
+        pub fn new(msg_id: i32, server_version: i32) -> Self {
+            let mut buf = SCRATCH.with(|scratch| scratch.replace(BytesMut::new()));
+            buf.reserve(MESSAGE_CAPACITY);
+            buf.put_u32(0);
+            if server_version >= constants::MIN_SERVER_VER_PROTOBUF {
+                buf.put_i32(msg_id);
+            }
+            else {
+                msg_id.encode(&mut buf);
+            }
+            MessageBuilder{buf}
+        }
+
+        pub fn push<T: Encodable>(&mut self, field: &T) -> &mut Self {
+            field.encode(&mut self.buf);
+            self
+        }
+
+        /// Appends fields that are already encoded, nulls included.
+        pub fn push_raw(&mut self, fields: &[u8]) -> &mut Self {
+            self.buf.put_slice(fields);
+            self
+        }
+
+        /// The message buffer, for encoders writing several fields at once.
+        pub fn buf(&mut self) -> &mut BytesMut {
+            &mut self.buf
+        }
+
+        /// The length-prefixed message.
+        pub fn finish(mut self) -> Bytes {
+            let len = (self.buf.len() - 4) as u32;
+            self.buf[..4].copy_from_slice(&len.to_be_bytes());
+            let msg = self.buf.split().freeze();
+            SCRATCH.with(|scratch| scratch.replace(self.buf));
+            msg
+        }
     }
 }
 
 pub mod ib_stream {
     use super::ib_message::IBMessage;
+    use tracing::debug;
     use std::convert::TryInto;
-    use std::error::Error;
+    use std::io;
+    use bytes::{Buf, Bytes, BytesMut};
+    use tokio::io::{AsyncRead, AsyncWrite};
     use tokio::io::AsyncWriteExt;
     use tokio::io::AsyncReadExt;
-    use tokio::net::tcp::OwnedReadHalf;
-    use tokio::net::tcp::OwnedWriteHalf;
-    pub type AsyncResult<T> = Result<T, Box<dyn Error>>;
+    pub use crate::error::AsyncResult;
+    use crate::recorder::{Direction, Recorder};
+
+    /// Largest frame accepted from TWS, anything bigger is treated as a corrupt stream.
+    pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+    //frames are handed out as views into the read buffer, which is grown by this much at a time
+    const READ_BUF_SIZE: usize = 8 * 1024;
 
     pub struct IBReader {
-        tcp: OwnedReadHalf,
-        headbuf: [u8;4]
+        tcp: Box<dyn AsyncRead + Send + Unpin>,
+        buf: BytesMut,
+        recorder: Option<Recorder>
     }
 
     pub struct IBWriter {
-        tcp: OwnedWriteHalf,
+        tcp: Box<dyn AsyncWrite + Send + Unpin>,
+        recorder: Option<Recorder>
     }
 
     impl IBReader {
-        pub fn new(tcp: OwnedReadHalf) -> IBReader {
+        pub fn new<R: AsyncRead + Send + Unpin + 'static>(tcp: R) -> IBReader {
             IBReader {
-                headbuf: [0; 4],
-                tcp,
+                buf: BytesMut::with_capacity(READ_BUF_SIZE),
+                tcp: Box::new(tcp),
+                recorder: None
             }
         }
-        pub async fn read(&mut self) -> AsyncResult<Vec<u8>> {
-            let bytes = self.tcp.read(&mut self.headbuf).await?;
-            let msg_size = u32::from_be_bytes(self.headbuf);
-            let mut msg = vec![0; msg_size.try_into().unwrap()];
-            let bytes = self.tcp.read(&mut msg).await?;
-            Ok(msg)
+
+        pub fn set_recorder(&mut self, recorder: Option<Recorder>) {
+            self.recorder = recorder;
+        }
+
+        /// Reads the next length-prefixed frame, buffering partial and coalesced frames.
+        /// The frame shares the read buffer, which is only reallocated once every few kilobytes.
+        pub async fn read(&mut self) -> AsyncResult<Bytes> {
+            loop {
+                if self.buf.len() >= 4 {
+                    let msg_size = u32::from_be_bytes(self.buf[..4].try_into().unwrap()) as usize;
+                    if msg_size > MAX_FRAME_SIZE {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Frame size {} exceeds the maximum", msg_size)).into());
+                    }
+                    if self.buf.len() >= 4 + msg_size {
+                        self.buf.advance(4);
+                        let msg = self.buf.split_to(msg_size).freeze();
+                        debug!(frame = ?String::from_utf8_lossy(&msg), "Received frame");
+                        if let Some(recorder) = &self.recorder {
+                            recorder.record(Direction::In, &msg);
+                        }
+                        return Ok(msg);
+                    }
+                    self.buf.reserve(4 + msg_size - self.buf.len());
+                }
+                if self.buf.capacity() - self.buf.len() < READ_BUF_SIZE / 8 {
+                    self.buf.reserve(READ_BUF_SIZE);
+                }
+                if self.tcp.read_buf(&mut self.buf).await? == 0 {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed by TWS").into());
+                }
+            }
         }
     }
     impl IBWriter {
-        pub fn new(tcp: OwnedWriteHalf) -> IBWriter {
+        pub fn new<W: AsyncWrite + Send + Unpin + 'static>(tcp: W) -> IBWriter {
             IBWriter {
-                tcp
+                tcp: Box::new(tcp),
+                recorder: None
             }
         }
 
+        pub fn set_recorder(&mut self, recorder: Option<Recorder>) {
+            self.recorder = recorder;
+        }
+
         pub async fn write_raw(&mut self, msg: &[u8]) -> AsyncResult<()> {
+            debug!(frame = ?String::from_utf8_lossy(msg), "Sending raw bytes");
+            if let Some(recorder) = &self.recorder {
+                recorder.record(Direction::Out, msg);
+            }
             self.tcp.write_all(msg).await?;
             Ok(())
         }
 
-        pub async fn write(&mut self, msg: &str) -> AsyncResult<()> {
-            self.tcp.write_all(&msg.to_ib_message().unwrap()).await?;
+        /// Writes a message built with `MessageBuilder`, length prefix included.
+        pub async fn write(&mut self, msg: &[u8]) -> AsyncResult<()> {
+            let payload = &msg[4..];
+            debug!(frame = ?String::from_utf8_lossy(payload), "Sending frame");
+            if let Some(recorder) = &self.recorder {
+                recorder.record(Direction::Out, payload);
+            }
+            self.tcp.write_all(msg).await?;
+            Ok(())
+        }
+
+        /// Writes a message that is already encoded, e.g. a protobuf message behind its binary id.
+        pub async fn write_payload(&mut self, payload: &[u8]) -> AsyncResult<()> {
+            debug!(frame = ?String::from_utf8_lossy(payload), "Sending frame");
+            if let Some(recorder) = &self.recorder {
+                recorder.record(Direction::Out, payload);
+            }
+            self.tcp.write_all(&payload.to_ib_message().unwrap()).await?;
+            Ok(())
+        }
+
+        pub async fn shutdown(&mut self) -> AsyncResult<()> {
+            self.tcp.shutdown().await?;
             Ok(())
         }
     }
@@ -0,0 +1,135 @@
+use rs_ib_api::ib_client::{IBClient, ConnectOptions};
+use rs_ib_api::ib_contract::Contract;
+use rs_ib_api::ib_enums::*;
+use rs_ib_api::order::Order;
+use bytes::BytesMut;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::sync::Mutex;
+
+//counts allocations while enabled, tests hold `SERIAL` so nothing else runs meanwhile
+struct CountingAlloc;
+
+static COUNTING: AtomicBool = AtomicBool::new(false);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static SERIAL: Mutex<()> = Mutex::const_new(());
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if COUNTING.load(Ordering::Relaxed) {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn frame(fields: &[&str]) -> Vec<u8> {
+    let mut msg = fields.join("\0");
+    msg.push('\0');
+    let mut frame = (msg.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(msg.as_bytes());
+    frame
+}
+
+async fn read_msg(stream: &mut DuplexStream) -> Vec<String> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).await.unwrap();
+    let mut msg = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut msg).await.unwrap();
+    String::from_utf8(msg).unwrap().split('\0').map(str::to_string).collect()
+}
+
+//price, size and generic ticks for request id 1, ending with a bid of `last_bid`
+fn tick_batch(n_frames: usize, last_bid: f64) -> Vec<u8> {
+    let mut batch = Vec::new();
+    for n in 0..n_frames - 1 {
+        let price = format!("{}.{:02}", 100 + n % 7, n % 100);
+        batch.extend(match n % 4 {
+            0 => frame(&["1", "6", "1", "2", &price, "300", "1"]),
+            1 => frame(&["1", "6", "1", "4", &price, "100", "0"]),
+            2 => frame(&["2", "6", "1", "8", "1250300"]),
+            _ => frame(&["45", "6", "1", "46", "3.0"])
+        });
+    }
+    batch.extend(frame(&["1", "6", "1", "1", &last_bid.to_string(), "200", "1"]));
+    batch
+}
+
+#[tokio::test]
+async fn tick_frames_do_not_allocate_per_frame() {
+    let _serial = SERIAL.lock().await;
+    let (client_end, mut server) = tokio::io::duplex(1 << 20);
+    let gateway = tokio::spawn(async move {
+        let mut prefix = [0u8; 4];
+        server.read_exact(&mut prefix).await.unwrap();
+        read_msg(&mut server).await;
+        server.write_all(&frame(&[&constants::MIN_SERVER_VER_SIZE_RULES.to_string(), "20210101 00:00:00 UTC"])).await.unwrap();
+        read_msg(&mut server).await;
+        server.write_all(&frame(&["9", "1", "42"])).await.unwrap();
+        server
+    });
+    let options = ConnectOptions::new(4002, 1)
+        .subscribe_account_updates(false)
+        .request_next_order_id(false);
+    let client = IBClient::connect_with_stream(client_end, options).await.unwrap();
+    let mut server = gateway.await.unwrap();
+    let contract = Contract {
+        symbol: Some("AAPL".to_string()),
+        sec_type: Some(SecType::Stock),
+        ..Default::default()
+    };
+    let (ticker, _) = tokio::join!(client.req_market_data(&contract, false, false, None), async {
+        loop {
+            if read_msg(&mut server).await[0] == "1" {
+                server.write_all(&frame(&["1", "6", "1", "1", "99.0", "100", "0"])).await.unwrap();
+                break;
+            }
+        }
+    });
+    let ticker = ticker.unwrap();
+
+    //the first batch warms up the buffers and channels on the way
+    const N_FRAMES: usize = 1000;
+    for (round, last_bid) in [101.5, 102.5].iter().enumerate() {
+        let batch = tick_batch(N_FRAMES, *last_bid);
+        ALLOCATIONS.store(0, Ordering::SeqCst);
+        COUNTING.store(round == 1, Ordering::SeqCst);
+        server.write_all(&batch).await.unwrap();
+        while ticker.bid() != Some(*last_bid) {
+            tokio::task::yield_now().await;
+        }
+        COUNTING.store(false, Ordering::SeqCst);
+    }
+    //the read buffer is reallocated every few kilobytes, individual frames do not allocate
+    let allocations = ALLOCATIONS.load(Ordering::SeqCst);
+    assert!(allocations < N_FRAMES / 20, "{} allocations for {} frames", allocations, N_FRAMES);
+}
+
+#[test]
+fn encoding_an_order_does_not_allocate_per_field() {
+    let _serial = futures::executor::block_on(SERIAL.lock());
+    let contract = Contract {
+        symbol: Some("AAPL".to_string()),
+        exchange: Some("SMART".to_string()),
+        sec_type: Some(SecType::Stock),
+        currency: Some("USD".to_string()),
+        ..Default::default()
+    };
+    let order = Order::limit(contract, Action::Buy, rust_decimal::Decimal::new(10, 0), rust_decimal::Decimal::new(15025, 2), TimeInForce::Day);
+    let mut buf = BytesMut::with_capacity(4096);
+    ALLOCATIONS.store(0, Ordering::SeqCst);
+    COUNTING.store(true, Ordering::SeqCst);
+    order.encode(&mut buf, constants::MAX_CLIENT_VER);
+    COUNTING.store(false, Ordering::SeqCst);
+    let n_fields = buf.iter().filter(|byte| **byte == 0).count();
+    assert!(n_fields > 100);
+    assert_eq!(ALLOCATIONS.load(Ordering::SeqCst), 0);
+}
@@ -1,4 +1,4 @@
-use rs_ib_api::ib_client::IBClient;
+use rs_ib_api::ib_client::{IBClient, ConnectOptions};
 use rs_ib_api::ib_contract::*;
 use rs_ib_api::order::Order;
 use tokio::time;
@@ -11,7 +11,7 @@ use rust_decimal::prelude::*;
 
 #[tokio::test]
 async fn contract_details() {
-    let mut client = match IBClient::connect(4002, 1, "").await {
+    let client = match IBClient::connect(ConnectOptions::new(4002, 1)).await {
         Ok(client) => client,
         Err(_error) => panic!("Connection not successful!")
     };
@@ -35,7 +35,7 @@ async fn contract_details() {
 
 #[tokio::test]
 async fn liquid_hours() {
-    let mut client = match IBClient::connect(4002, 1, "").await {
+    let client = match IBClient::connect(ConnectOptions::new(4002, 1)).await {
         Ok(client) => client,
         Err(_error) => panic!("Connection not successful!")
     };
@@ -56,7 +56,7 @@ async fn liquid_hours() {
 
 #[tokio::test]
 async fn place_market_order() {
-    let mut client = match IBClient::connect(4002, 2, "").await {
+    let client = match IBClient::connect(ConnectOptions::new(4002, 2)).await {
         Ok(client) => client,
         Err(_error) => panic!("Connection not successful!")
     };
@@ -79,7 +79,7 @@ async fn place_market_order() {
 
 #[tokio::test]
 async fn place_spread_market_order() {
-    let mut client = match IBClient::connect(4002, 1, "").await {
+    let client = match IBClient::connect(ConnectOptions::new(4002, 1)).await {
         Ok(client) => client,
         Err(_error) => panic!("Connection not successful!")
     };
@@ -108,7 +108,7 @@ async fn place_spread_market_order() {
 
 #[tokio::test]
 async fn market_data() {
-    let mut client = match IBClient::connect(4002, 3, "").await {
+    let client = match IBClient::connect(ConnectOptions::new(4002, 3)).await {
         Ok(client) => client,
         Err(_error) => panic!("Connection not successful!")
     };
@@ -131,7 +131,7 @@ async fn market_data() {
 
 #[tokio::test]
 async fn delayed_market_data() {
-    let mut client = match IBClient::connect(4002, 4, "").await {
+    let client = match IBClient::connect(ConnectOptions::new(4002, 4)).await {
         Ok(client) => client,
         Err(_error) => panic!("Connection not successful!")
     };
@@ -156,7 +156,7 @@ async fn delayed_market_data() {
 
 #[tokio::test]
 async fn snapshot_data() {
-    let mut client = match IBClient::connect(4002, 3, "").await {
+    let client = match IBClient::connect(ConnectOptions::new(4002, 3)).await {
         Ok(client) => client,
         Err(_error) => panic!("Connection not successful!")
     };
@@ -180,7 +180,7 @@ async fn snapshot_data() {
 
 #[tokio::test]
 async fn historical_data() {
-    let mut client = match IBClient::connect(4002, 4, "").await {
+    let client = match IBClient::connect(ConnectOptions::new(4002, 4)).await {
         Ok(client) => client,
         Err(_error) => panic!("Connection not successful!")
     };
@@ -0,0 +1,515 @@
+use rs_ib_api::ib_client::{IBClient, ConnectOptions, ConnectionState};
+use rs_ib_api::ib_contract::*;
+use rs_ib_api::ib_enums::*;
+use rs_ib_api::error::{IBError, TwsErrorKind};
+use rs_ib_api::frame::IBFrame;
+use rs_ib_api::mock_gateway::{MockGateway, frames};
+use rs_ib_api::order::Order;
+use rs_ib_api::pacing::RateLimit;
+use rs_ib_api::request::IBRequest;
+use chrono::{TimeZone, Utc};
+use std::time::Duration;
+
+fn aapl() -> Contract {
+    Contract {
+        symbol: Some("AAPL".to_string()),
+        exchange: Some("SMART".to_string()),
+        sec_type: Some(SecType::Stock),
+        currency: Some("USD".to_string()),
+        ..Default::default()
+    }
+}
+
+fn fields(values: &[&str]) -> Vec<String> {
+    values.iter().map(|val| val.to_string()).collect()
+}
+
+async fn connect(gateway: &MockGateway, client_id: i32) -> IBClient {
+    let options = ConnectOptions::new(gateway.port(), client_id).request_timeout(Duration::from_secs(5));
+    IBClient::connect(options).await.expect("Connection not successful!")
+}
+
+//waits until `count` messages of type `kind` have been received, across all connections
+async fn wait_for_count(gateway: &MockGateway, kind: Outgoing, count: usize) -> Vec<Vec<String>> {
+    let id = (kind.ordinal() as i32).to_string();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let received: Vec<_> = gateway.received().into_iter().filter(|msg| msg[0] == id).collect();
+            if received.len() >= count {
+                return received;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await.expect("Messages not received")
+}
+
+#[tokio::test]
+async fn next_order_id_from_gateway() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.set_next_order_id(100);
+    let client = connect(&gateway, 1).await;
+    gateway.wait_for(Outgoing::ReqIds).await;
+    gateway.wait_for(Outgoing::ReqAcctData).await;
+    assert_eq!(client.client_id(), 1);
+}
+
+#[tokio::test]
+async fn client_id_in_use_is_retried() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.reject_client_id(1);
+    let client = connect(&gateway, 1).await;
+    assert_eq!(client.client_id(), 2);
+}
+
+#[tokio::test]
+async fn contract_details_error_resolves_request() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.on(Outgoing::ReqContractData, |msg| {
+        vec![frames::error_msg(msg[2].parse().unwrap(), error_codes::NO_SECURITY_DEFINITION, "No security definition has been found for the request")]
+    });
+    let client = connect(&gateway, 1).await;
+    match client.req_contract_details(&aapl()).await {
+        Err(IBError::Tws(err)) => assert_eq!(err.kind(), TwsErrorKind::NoSecurityDefinition),
+        other => panic!("Unexpected result {:?}", other.map(|details| details.len()))
+    }
+}
+
+#[tokio::test]
+async fn market_data_ticks() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.on(Outgoing::ReqMktData, |msg| {
+        let id = msg[2].parse().unwrap();
+        vec![frames::tick_price(id, 1, 99.5, 100), frames::tick_price(id, 2, 100.5, 200)]
+    });
+    let client = connect(&gateway, 1).await;
+    let ticker = client.req_market_data(&aapl(), false, false, None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(ticker.bid(), Some(99.5));
+    assert_eq!(ticker.ask_size(), Some(rust_decimal::Decimal::new(200, 0)));
+    assert_eq!(ticker.midpoint(), Some(100.0));
+}
+
+#[tokio::test]
+async fn market_data_timeout_cancels_subscription() {
+    let gateway = MockGateway::start().await.unwrap();
+    let options = ConnectOptions::new(gateway.port(), 1).request_timeout(Duration::from_millis(100));
+    let client = IBClient::connect(options).await.unwrap();
+    match client.req_market_data(&aapl(), false, false, None).await {
+        Err(IBError::Timeout) => (),
+        _ => panic!("Expected a timeout")
+    }
+    let cancel = gateway.wait_for(Outgoing::CancelMktData).await;
+    let request = gateway.wait_for(Outgoing::ReqMktData).await;
+    assert_eq!(cancel[2], request[2]);
+}
+
+#[tokio::test]
+async fn historical_data() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.on(Outgoing::ReqHistoricalData, |msg| {
+        vec![fields(&["17", &msg[1], "20200201 00:00:00", "20200301 00:00:00", "2",
+            "20200227", "10.0", "12.0", "9.0", "11.0", "1000", "10.5", "50",
+            "20200228", "11.0", "13.0", "10.0", "12.0", "2000", "11.5", "60"])]
+    });
+    let client = connect(&gateway, 1).await;
+    let end_dt = Utc.ymd(2020, 3, 1).and_hms(0, 0, 0);
+    let bars = client.req_historical_data(&aapl(), &end_dt, HistoricalDataDuration::Months(1), HistoricalDataBarSize::OneDay,
+        HistoricalDataType::Midpoint, true).await.unwrap();
+    assert_eq!(bars.n_bars, 2);
+    assert_eq!(bars.data.unwrap()[1].close, 12.0);
+}
+
+#[tokio::test]
+async fn historical_data_from_old_server() {
+    //before server version 124 the message has a version field and every bar a has gaps flag
+    let gateway = MockGateway::with_server_version(120).await.unwrap();
+    gateway.on(Outgoing::ReqHistoricalData, |msg| {
+        vec![fields(&["17", "3", &msg[2], "20200201 00:00:00", "20200301 00:00:00", "1",
+            "20200227", "10.0", "12.0", "9.0", "11.0", "1000", "10.5", "false", "50"])]
+    });
+    let client = connect(&gateway, 1).await;
+    let end_dt = Utc.ymd(2020, 3, 1).and_hms(0, 0, 0);
+    let bars = client.req_historical_data(&aapl(), &end_dt, HistoricalDataDuration::Months(1), HistoricalDataBarSize::OneDay,
+        HistoricalDataType::Midpoint, true).await.unwrap();
+    let request = gateway.wait_for(Outgoing::ReqHistoricalData).await;
+    assert_eq!(request[1], "6");
+    assert_eq!(bars.data.unwrap()[0].count, 50);
+}
+
+#[tokio::test]
+async fn contract_details_with_size_rules() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.on(Outgoing::ReqContractData, |msg| {
+        let id = &msg[2];
+        vec![fields(&["10", id, "AAPL", "STK", "", "0", "", "SMART", "USD", "AAPL", "NMS", "NMS", "265598", "0.01",
+            "", "LMT,MKT", "SMART,NASDAQ", "1", "0", "APPLE INC", "", "", "", "", "", "US/Eastern", "", "", "", "", "0",
+            "1", "", "", "", "", "COMMON", "0.0001", "0.0001", "100"]),
+            frames::contract_data_end(id.parse().unwrap())]
+    });
+    let client = connect(&gateway, 1).await;
+    let details = client.req_contract_details(&aapl()).await.unwrap();
+    assert_eq!(details[0].long_name.as_deref(), Some("APPLE INC"));
+    assert_eq!(details[0].stock_type.as_deref(), Some("COMMON"));
+    assert_eq!(details[0].min_size, Some(rust_decimal::Decimal::new(1, 4)));
+    assert_eq!(details[0].suggested_size_increment, Some(rust_decimal::Decimal::new(100, 0)));
+}
+
+#[tokio::test]
+async fn order_fields_follow_server_version() {
+    let old_gateway = MockGateway::with_server_version(144).await.unwrap();
+    let new_gateway = MockGateway::start().await.unwrap();
+    let order = rs_ib_api::order::Order::market(aapl(), Action::Buy, rust_decimal::Decimal::new(10, 0));
+    let mut placed = Vec::new();
+    for gateway in [&old_gateway, &new_gateway].iter() {
+        gateway.on(Outgoing::PlaceOrder, |msg| {
+            let id = msg.iter().position(|field| field == "AAPL").unwrap() - 2;
+            vec![frames::error_msg(msg[id].parse().unwrap(), error_codes::ORDER_REJECTED, "Order rejected")]
+        });
+        let client = connect(gateway, 1).await;
+        let _ = client.place_order(&order).await;
+        placed.push(gateway.wait_for(Outgoing::PlaceOrder).await);
+    }
+    //the old server gets a version field, but none of the six fields added up to version 164
+    //(oms container, discretionary up to limit price, price management algo, duration,
+    //post to ats, auto cancel parent), so the new message is five fields longer
+    assert_eq!(placed[0][1], "45");
+    assert_eq!(placed[1].len() - placed[0].len(), 5);
+}
+
+#[tokio::test]
+async fn rejected_order() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.on(Outgoing::PlaceOrder, |msg| {
+        vec![frames::error_msg(msg[1].parse().unwrap(), error_codes::ORDER_REJECTED, "Order rejected - reason: insufficient funds")]
+    });
+    let client = connect(&gateway, 1).await;
+    let order = rs_ib_api::order::Order::market(aapl(), Action::Buy, rust_decimal::Decimal::new(10, 0));
+    match client.place_order(&order).await {
+        Err(IBError::Tws(err)) => assert_eq!(err.kind(), TwsErrorKind::OrderRejected),
+        _ => panic!("Expected a rejection")
+    }
+}
+
+#[tokio::test]
+async fn order_error_does_not_fail_request_with_same_id() {
+    let gateway = MockGateway::start().await.unwrap();
+    //the first order id and the first request id are both 1
+    gateway.set_next_order_id(0);
+    gateway.on(Outgoing::ReqContractData, |_| Vec::new());
+    gateway.on(Outgoing::PlaceOrder, |msg| {
+        vec![frames::error_msg(msg[1].parse().unwrap(), error_codes::ORDER_REJECTED, "Order rejected")]
+    });
+    let client = connect(&gateway, 1).await;
+    let details_client = client.clone();
+    let details = tokio::spawn(async move { details_client.req_contract_details(&aapl()).await });
+    let request = gateway.wait_for(Outgoing::ReqContractData).await;
+    let order = Order::market(aapl(), Action::Buy, rust_decimal::Decimal::new(10, 0));
+    match client.place_order(&order).await {
+        Err(IBError::Tws(err)) => assert_eq!(err.kind(), TwsErrorKind::OrderRejected),
+        _ => panic!("Expected a rejection")
+    }
+    assert_eq!(gateway.wait_for(Outgoing::PlaceOrder).await[1], request[2]);
+    let req_id = request[2].parse().unwrap();
+    let contract_details = ContractDetails{contract: Some(aapl()), ..Default::default()};
+    for frame in [IBFrame::ContractDetails{req_id, contract_details: Box::new(contract_details)}, IBFrame::ContractDetailsEnd(req_id)] {
+        gateway.push_raw(frame.encode(client.server_version()).unwrap()[4..].to_vec());
+    }
+    assert_eq!(details.await.unwrap().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn order_answered_after_timeout_is_dropped() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.on(Outgoing::PlaceOrder, |_| Vec::new());
+    let options = ConnectOptions::new(gateway.port(), 1).request_timeout(Duration::from_millis(100));
+    let client = IBClient::connect(options).await.unwrap();
+    let mut order = Order::market(aapl(), Action::Buy, rust_decimal::Decimal::new(10, 0));
+    match client.place_order(&order).await {
+        Err(IBError::Timeout) => (),
+        _ => panic!("Expected a timeout")
+    }
+    order.order_id = gateway.wait_for(Outgoing::PlaceOrder).await[1].parse().unwrap();
+    let open_order = IBFrame::OpenOrder{order: Box::new(order.clone()), order_state: Box::new(Default::default())};
+    gateway.push_raw(open_order.encode(client.server_version()).unwrap()[4..].to_vec());
+    gateway.push(frames::order_status(order.order_id, "Filled", "10", "0", 150.0));
+    //the dispatcher keeps serving requests after the late updates
+    client.req_current_time().await.unwrap();
+}
+
+#[tokio::test]
+async fn fills_for_dropped_tracker_are_skipped() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.on_request(Outgoing::PlaceOrder, |request| match request {
+        IBRequest::PlaceOrder{order_id, order} => {
+            let mut order = order.as_ref().clone();
+            order.order_id = *order_id;
+            vec![IBFrame::OpenOrder{order: Box::new(order), order_state: Box::default()}]
+        },
+        _ => Vec::new()
+    });
+    let client = connect(&gateway, 1).await;
+    let tracker = client.place_order(&Order::market(aapl(), Action::Buy, rust_decimal::Decimal::new(10, 0))).await.unwrap();
+    let order_id = gateway.wait_for(Outgoing::PlaceOrder).await[1].parse().unwrap();
+    drop(tracker);
+    let execution = rs_ib_api::order::Execution {
+        exec_id: "0001f4e8.01".to_string(),
+        time: "20240102 10:00:00".to_string(),
+        acct_number: "DU123456".to_string(),
+        exchange: "ISLAND".to_string(),
+        side: Side::Long,
+        shares: rust_decimal::Decimal::new(10, 0),
+        price: rust_decimal::Decimal::new(15025, 2),
+        perm_id: 123456,
+        client_id: 1,
+        order_id,
+        contract: aapl(),
+        liquidation: 0,
+        cum_qty: rust_decimal::Decimal::new(10, 0),
+        avg_price: rust_decimal::Decimal::new(15025, 2),
+        order_ref: None,
+        ev_rule: None,
+        ev_multiplier: None,
+        model_code: None,
+        last_liquidity: None,
+        pending_price_revision: None,
+        submitter: None
+    };
+    let frame = IBFrame::Execution(Box::new(execution));
+    gateway.push_raw(frame.encode(client.server_version()).unwrap()[4..].to_vec());
+    gateway.push(frames::order_status(order_id, "Filled", "10", "0", 150.25));
+    //the dispatcher keeps serving requests after the fills
+    client.req_current_time().await.unwrap();
+}
+
+#[tokio::test]
+async fn account_updates_are_pushed() {
+    let gateway = MockGateway::start().await.unwrap();
+    let client = connect(&gateway, 1).await;
+    gateway.wait_for(Outgoing::ReqAcctData).await;
+    gateway.push(frames::account_value("NetLiquidation", "25000.50", "USD", "DU123456"));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(client.net_liquidation_value(), Some(rust_decimal::Decimal::new(2500050, 2)));
+}
+
+#[tokio::test]
+async fn disconnect_unsubscribes_account_updates() {
+    let gateway = MockGateway::start().await.unwrap();
+    let client = connect(&gateway, 1).await;
+    client.disconnect().await.unwrap();
+    let unsubscribe = gateway.received().into_iter()
+        .filter(|msg| msg[0] == "6")
+        .next_back()
+        .unwrap();
+    assert_eq!(unsubscribe[2], "0");
+}
+
+#[tokio::test]
+async fn disconnect_cancels_pending_requests() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.on(Outgoing::ReqContractData, |_| vec![]);
+    let client = connect(&gateway, 1).await;
+    let pending = {
+        let client = client.clone();
+        tokio::spawn(async move { client.req_contract_details(&aapl()).await })
+    };
+    gateway.wait_for(Outgoing::ReqContractData).await;
+    client.disconnect().await.unwrap();
+    match pending.await.unwrap() {
+        Err(IBError::Cancelled) => {},
+        other => panic!("Unexpected result {:?}", other.map(|details| details.len()))
+    }
+}
+
+#[tokio::test]
+async fn disconnect_flushes_queued_messages() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.on(Outgoing::PlaceOrder, |_| Vec::new());
+    //the heartbeat takes the only token, the order waits a second for the next one
+    let options = ConnectOptions::new(gateway.port(), 1)
+        .subscribe_account_updates(false)
+        .rate_limit(Some(RateLimit::new(1, 1)));
+    let client = IBClient::connect(options).await.unwrap();
+    gateway.wait_for(Outgoing::ReqCurrentTime).await;
+    let order_client = client.clone();
+    let order = tokio::spawn(async move {
+        let order = Order::market(aapl(), Action::Buy, rust_decimal::Decimal::new(10, 0));
+        order_client.place_order(&order).await.map(|_| ())
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(gateway.received().iter().all(|msg| msg[0] != "3"));
+    client.disconnect().await.unwrap();
+    gateway.wait_for(Outgoing::PlaceOrder).await;
+    assert!(matches!(order.await.unwrap(), Err(IBError::Cancelled)));
+}
+
+#[derive(Clone, Default)]
+struct Captured(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn farm_status_is_logged_as_event() {
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || writer.clone())
+        .with_ansi(false)
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+    let gateway = MockGateway::start().await.unwrap();
+    let _client = connect(&gateway, 1).await;
+    gateway.push(frames::error_msg(-1, 2104, "Market data farm connection is OK:usfarm"));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let logs = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+    let event = logs.lines().find(|line| line.contains("Market data farm")).expect("No farm status event");
+    assert!(event.contains("connection{client_id=1"));
+    assert!(event.contains("code=2104 farm=\"usfarm\" connected=true"));
+}
+
+#[tokio::test]
+async fn current_time_updates_heartbeat() {
+    let gateway = MockGateway::start().await.unwrap();
+    let client = connect(&gateway, 1).await;
+    let server_time = client.req_current_time().await.unwrap();
+    assert!((Utc::now() - server_time).num_seconds().abs() <= 1);
+    let heartbeat = client.last_heartbeat().unwrap();
+    assert!(heartbeat.round_trip < Duration::from_secs(1));
+    assert!(client.clock_offset().unwrap().num_seconds().abs() <= 1);
+}
+
+#[tokio::test]
+async fn unanswered_heartbeats_close_connection() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.on(Outgoing::ReqCurrentTime, |_| Vec::new());
+    let options = ConnectOptions::new(gateway.port(), 1).heartbeat(Duration::from_millis(50), 2);
+    let client = IBClient::connect(options).await.unwrap();
+    let mut state = client.connection_state();
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while *state.borrow() != ConnectionState::Disconnected {
+            state.changed().await.unwrap();
+        }
+    }).await.expect("Connection not declared dead");
+    assert!(client.last_heartbeat().is_none());
+}
+
+#[tokio::test]
+async fn frame_subscription_sees_unhandled_frames() {
+    let gateway = MockGateway::start().await.unwrap();
+    let client = connect(&gateway, 1).await;
+    let mut frames = client.subscribe_frames();
+    let before = Utc::now();
+    gateway.push(frames::tick_size(7, 0, 300));
+    //market data type notifications are not handled by the client
+    gateway.push(fields(&["58", "1", "7", "3"]));
+    let received = tokio::time::timeout(Duration::from_secs(2), async {
+        let mut received = Vec::new();
+        while received.len() < 2 {
+            let frame = frames.recv().await.unwrap();
+            match frame.frame {
+                Ok(IBFrame::SizeTick{..}) | Ok(IBFrame::NotImplemented) => received.push(frame),
+                _ => ()
+            }
+        }
+        received
+    }).await.expect("Frames not received");
+    match &received[0].frame {
+        Ok(IBFrame::SizeTick{id, size, ..}) => assert_eq!((*id, size.to_string()), (7, "300".to_string())),
+        _ => panic!("Unexpected frame")
+    }
+    assert!(received[0].received_at >= before);
+    assert_eq!(&received[1].raw[..], b"58\x001\x007\x003\0");
+}
+
+#[tokio::test]
+async fn reconnect_resubscribes_and_fails_pending_requests() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.on(Outgoing::ReqMktData, |msg| vec![frames::tick_size(msg[2].parse().unwrap(), 0, 100)]);
+    gateway.on(Outgoing::ReqContractData, |_| Vec::new());
+    gateway.on_request(Outgoing::PlaceOrder, |request| match request {
+        IBRequest::PlaceOrder{order_id, order} => {
+            let mut order = order.as_ref().clone();
+            order.order_id = *order_id;
+            vec![IBFrame::OpenOrder{order: Box::new(order), order_state: Box::default()}]
+        },
+        _ => Vec::new()
+    });
+    let options = ConnectOptions::new(gateway.port(), 1)
+        .request_timeout(Duration::from_secs(5))
+        .reconnect(true)
+        .reconnect_backoff(Duration::from_millis(300), Duration::from_millis(300));
+    let client = IBClient::connect(options).await.unwrap();
+    let _ticker = client.req_market_data(&aapl(), false, false, None).await.unwrap();
+    let _tracker = client.place_order(&Order::market(aapl(), Action::Buy, rust_decimal::Decimal::new(10, 0))).await.unwrap();
+    let pending_client = client.clone();
+    let pending = tokio::spawn(async move { pending_client.req_contract_details(&aapl()).await });
+    gateway.wait_for(Outgoing::ReqContractData).await;
+
+    gateway.disconnect_all();
+    match pending.await.unwrap() {
+        Err(IBError::ConnectionClosed) => (),
+        other => panic!("Unexpected result {:?}", other.map(|details| details.len()))
+    }
+    //nothing is written until the session is back
+    match client.req_current_time().await {
+        Err(IBError::ConnectionClosed) => (),
+        other => panic!("Unexpected result {:?}", other)
+    }
+
+    let mkt_data = wait_for_count(&gateway, Outgoing::ReqMktData, 2).await;
+    assert_ne!(mkt_data[0][2], mkt_data[1][2]);
+    wait_for_count(&gateway, Outgoing::ReqAcctData, 2).await;
+    gateway.wait_for(Outgoing::ReqOpenOrders).await;
+    assert_eq!(*client.connection_state().borrow(), ConnectionState::Connected);
+}
+
+#[tokio::test]
+async fn market_data_type_is_kept_across_reconnects() {
+    let gateway = MockGateway::start().await.unwrap();
+    let options = ConnectOptions::new(gateway.port(), 1)
+        .reconnect(true)
+        .reconnect_backoff(Duration::from_millis(50), Duration::from_millis(50));
+    let client = IBClient::connect(options).await.unwrap();
+    client.set_mkt_data_delayed().await.unwrap();
+    gateway.wait_for(Outgoing::ReqMarketDataType).await;
+    gateway.disconnect_all();
+    let requests = wait_for_count(&gateway, Outgoing::ReqMarketDataType, 2).await;
+    assert_eq!(requests[1], requests[0]);
+}
+
+#[tokio::test]
+async fn frame_subscription_sees_unknown_messages() {
+    let gateway = MockGateway::start().await.unwrap();
+    let client = connect(&gateway, 1).await;
+    let mut frames = client.subscribe_frames();
+    gateway.push(fields(&["199", "1", "unknown"]));
+    gateway.push(frames::tick_size(7, 0, 300));
+    let received = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            let frame = frames.recv().await.unwrap();
+            if frame.frame.is_err() {
+                return frame;
+            }
+        }
+    }).await.expect("Unknown message not received");
+    match &received.frame {
+        Err(err) => assert_eq!(err.msg_id, Some(199)),
+        _ => panic!("Unexpected frame")
+    }
+    assert!(received.raw.ends_with(b"1\0unknown\0"));
+    //the client goes on after a message it could not decode
+    loop {
+        if let Ok(IBFrame::SizeTick{id, ..}) = frames.recv().await.unwrap().frame {
+            assert_eq!(id, 7);
+            break;
+        }
+    }
+}
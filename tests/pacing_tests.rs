@@ -0,0 +1,177 @@
+use rs_ib_api::ib_client::{IBClient, ConnectOptions};
+use rs_ib_api::ib_contract::*;
+use rs_ib_api::ib_enums::*;
+use rs_ib_api::mock_gateway::{MockGateway, frames};
+use rs_ib_api::error::IBError;
+use rs_ib_api::pacing::{HistoricalPacing, Lane, RateLimit};
+use chrono::{TimeZone, Utc};
+use std::time::{Duration, Instant};
+
+fn aapl() -> Contract {
+    Contract {
+        symbol: Some("AAPL".to_string()),
+        exchange: Some("SMART".to_string()),
+        sec_type: Some(SecType::Stock),
+        currency: Some("USD".to_string()),
+        ..Default::default()
+    }
+}
+
+fn options(gateway: &MockGateway) -> ConnectOptions {
+    ConnectOptions::new(gateway.port(), 1)
+        .subscribe_account_updates(false)
+        .request_next_order_id(false)
+        .request_timeout(Duration::from_secs(5))
+}
+
+async fn connect(gateway: &MockGateway, limit: RateLimit) -> IBClient {
+    IBClient::connect(options(gateway).rate_limit(Some(limit))).await.expect("Connection not successful!")
+}
+
+fn answer_historical_data(gateway: &MockGateway) {
+    gateway.on(Outgoing::ReqHistoricalData, |msg| {
+        vec![["17", &msg[1], "20200201 00:00:00", "20200301 00:00:00", "1",
+            "20200227", "10.0", "12.0", "9.0", "11.0", "1000", "10.5", "50"].iter().map(|val| val.to_string()).collect()]
+    });
+}
+
+async fn daily_bars(client: &IBClient) -> Result<rs_ib_api::bars::BarSeries, IBError> {
+    let end_dt = Utc.ymd(2020, 3, 1).and_hms(0, 0, 0);
+    client.req_historical_data(&aapl(), &end_dt, HistoricalDataDuration::Months(1), HistoricalDataBarSize::OneDay,
+        HistoricalDataType::Midpoint, true).await
+}
+
+#[test]
+fn lanes_by_message_id() {
+    assert_eq!(Lane::of(b"4\x001\x002\0"), Lane::Urgent);
+    assert_eq!(Lane::of(b"9\x008\x001\0"), Lane::Bulk);
+    assert_eq!(Lane::of(b"49\x001\0"), Lane::Normal);
+    //the market data type stays in order with the market data requests
+    assert_eq!(Lane::of(b"59\x001\x003\0"), Lane::Bulk);
+    //binary message ids of newer server versions
+    assert_eq!(Lane::of(b"\0\0\0\x03AAPL\0"), Lane::Urgent);
+    assert_eq!(Lane::of(b"\0\0\0\x141\0"), Lane::Bulk);
+}
+
+#[tokio::test]
+async fn requests_are_spaced() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.on(Outgoing::ReqContractData, |msg| vec![frames::contract_data_end(msg[2].parse().unwrap())]);
+    let client = connect(&gateway, RateLimit::new(20, 1)).await;
+    let contract = aapl();
+    let start = Instant::now();
+    let requests = (0..5).map(|_| client.req_contract_details(&contract));
+    futures::future::join_all(requests).await;
+    //the heartbeat took the only token, every request waits 50ms for the next one
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    let stats = client.pacing_stats();
+    assert_eq!(stats.sent, 6);
+    assert!(stats.delayed >= 4);
+    assert_eq!(stats.queued, [0, 0, 0]);
+}
+
+#[tokio::test]
+async fn orders_overtake_bulk_requests() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.on(Outgoing::ReqContractData, |msg| vec![frames::contract_data_end(msg[2].parse().unwrap())]);
+    gateway.on(Outgoing::PlaceOrder, |msg| {
+        vec![frames::error_msg(msg[1].parse().unwrap(), error_codes::ORDER_REJECTED, "Order rejected")]
+    });
+    let client = connect(&gateway, RateLimit::new(10, 1)).await;
+    let contract = aapl();
+    let order = rs_ib_api::order::Order::market(aapl(), Action::Buy, rust_decimal::Decimal::new(10, 0));
+    let requests = futures::future::join_all((0..5).map(|_| client.req_contract_details(&contract)));
+    let (_, placed) = tokio::join!(requests, async {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        client.place_order(&order).await
+    });
+    assert!(placed.is_err());
+    let kinds: Vec<String> = gateway.received().into_iter().map(|msg| msg[0].clone()).collect();
+    let order_pos = kinds.iter().position(|kind| kind == "3").unwrap();
+    let last_request = kinds.iter().rposition(|kind| kind == "9").unwrap();
+    assert!(order_pos < last_request);
+}
+
+#[tokio::test]
+async fn cancel_withdraws_queued_subscription() {
+    let gateway = MockGateway::start().await.unwrap();
+    //the heartbeat takes the only token, the market data request waits a second for the next one
+    let options = options(&gateway)
+        .rate_limit(Some(RateLimit::new(1, 1)))
+        .request_timeout(Duration::from_millis(100));
+    let client = IBClient::connect(options).await.unwrap();
+    gateway.wait_for(Outgoing::ReqCurrentTime).await;
+    match client.req_market_data(&aapl(), false, false, None).await {
+        Err(IBError::Timeout) => (),
+        other => panic!("Unexpected result {:?}", other.map(|_| ()))
+    }
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(gateway.received().iter().all(|msg| msg[0] != "1" && msg[0] != "2"));
+    assert_eq!(client.pacing_stats().queued, [0, 0, 0]);
+}
+
+#[tokio::test]
+async fn market_data_type_keeps_its_place() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.on(Outgoing::ReqMktData, |msg| vec![frames::tick_size(msg[2].parse().unwrap(), 0, 100)]);
+    let client = connect(&gateway, RateLimit::new(20, 1)).await;
+    let contract = aapl();
+    let (ticker, delayed) = tokio::join!(client.req_market_data(&contract, false, false, None), client.set_mkt_data_delayed());
+    ticker.unwrap();
+    delayed.unwrap();
+    gateway.wait_for(Outgoing::ReqMarketDataType).await;
+    let kinds: Vec<String> = gateway.received().into_iter().map(|msg| msg[0].clone()).collect();
+    let mkt_data_pos = kinds.iter().position(|kind| kind == "1").unwrap();
+    let mkt_data_type_pos = kinds.iter().position(|kind| kind == "59").unwrap();
+    assert!(mkt_data_pos < mkt_data_type_pos);
+}
+
+#[tokio::test]
+async fn identical_historical_requests_are_merged() {
+    let gateway = MockGateway::start().await.unwrap();
+    answer_historical_data(&gateway);
+    let client = IBClient::connect(options(&gateway)).await.unwrap();
+    let (first, second) = tokio::join!(daily_bars(&client), daily_bars(&client));
+    assert_eq!(first.unwrap().n_bars, 1);
+    assert_eq!(second.unwrap().n_bars, 1);
+    let requests = gateway.received().into_iter().filter(|msg| msg[0] == "20").count();
+    assert_eq!(requests, 1);
+}
+
+#[tokio::test]
+async fn identical_historical_requests_are_spaced() {
+    let gateway = MockGateway::start().await.unwrap();
+    answer_historical_data(&gateway);
+    let pacing = HistoricalPacing{identical_interval: Duration::from_millis(300), ..Default::default()};
+    let client = IBClient::connect(options(&gateway).historical_pacing(Some(pacing))).await.unwrap();
+    let start = Instant::now();
+    daily_bars(&client).await.unwrap();
+    daily_bars(&client).await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(300));
+}
+
+#[tokio::test]
+async fn zero_historical_limits_are_no_limits() {
+    let gateway = MockGateway::start().await.unwrap();
+    answer_historical_data(&gateway);
+    let pacing = HistoricalPacing{identical_interval: Duration::ZERO, same_contract_limit: 0, total_limit: 0, ..Default::default()};
+    let client = IBClient::connect(options(&gateway).historical_pacing(Some(pacing))).await.unwrap();
+    let start = Instant::now();
+    daily_bars(&client).await.unwrap();
+    daily_bars(&client).await.unwrap();
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn historical_pacing_violation() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.on(Outgoing::ReqHistoricalData, |msg| {
+        vec![frames::error_msg(msg[1].parse().unwrap(), error_codes::HISTORICAL_DATA_ERROR,
+            "Historical Market Data Service error message:Historical data request pacing violation")]
+    });
+    let client = IBClient::connect(options(&gateway)).await.unwrap();
+    match daily_bars(&client).await {
+        Err(IBError::Pacing(err)) => assert_eq!(err.code, error_codes::HISTORICAL_DATA_ERROR),
+        other => panic!("Unexpected result {:?}", other.map(|bars| bars.n_bars))
+    }
+}
@@ -0,0 +1,189 @@
+use rs_ib_api::ib_client::{IBClient, ConnectOptions};
+use rs_ib_api::ib_contract::*;
+use rs_ib_api::ib_enums::*;
+use rs_ib_api::error::{IBError, TwsErrorKind};
+use rs_ib_api::frame::IBFrame;
+use rs_ib_api::mock_gateway::{MockGateway, frames};
+use rust_decimal::Decimal;
+use std::time::Duration;
+
+//ErrorMessage{id: 1, errorTime: 1, errorCode: 201, errorMsg: "Order rejected"} as sent by TWS
+const ORDER_REJECTED: &[u8] = b"\x08\x01\x10\x01\x18\xc9\x01\x22\x0eOrder rejected";
+
+//OrderStatus{orderId: 7, status: "Filled", filled: "100", remaining: "0", avgFillPrice: 150.25, permId: 123456,
+//parentId: 3, lastFillPrice: 150.5, clientId: 1, whyHeld: "locate", mktCapPrice: 149.75}
+const ORDER_FILLED: &[u8] = b"\x08\x07\x12\x06Filled\x1a\x03100\x22\x010)\x00\x00\x00\x00\x00\xc8b@0\xc0\xc4\x078\x03\
+    A\x00\x00\x00\x00\x00\xd0b@H\x01R\x06locateY\x00\x00\x00\x00\x00\xb8b@";
+
+//OrderStatus{orderId: 7, status: "Submitted", filled: "0", remaining: "100"}
+const ORDER_SUBMITTED: &[u8] = b"\x08\x07\x12\x09Submitted\x1a\x010\x22\x03100";
+
+//ExecutionDetails{reqId: -1, contract: {conId: 265598, symbol: "AAPL", secType: "STK", exchange: "SMART", currency: "USD"},
+//execution: {orderId: 7, execId: "0001f4e8.01", time: "20240102 10:00:00", acctNumber: "DU123456", exchange: "ISLAND",
+//side: "BOT", shares: "100", price: 150.25, permId: 123456, clientId: 1, isLiquidation: false, cumQty: "100",
+//avgPrice: 150.25, orderRef: "ref", evRule: "aussie", evMultiplier: 0.5, modelCode: "model", lastLiquidity: 2,
+//isPriceRevisionPending: true, submitter: "trader"}}
+const EXECUTION: &[u8] = b"\x08\xff\xff\xff\xff\xff\xff\xff\xff\xff\x01\x12\x1b\x08\xfe\x9a\x10\x12\x04AAPL\x1a\x03STK\
+    B\x05SMARTR\x03USD\x1a\x8b\x01\x08\x07\x12\x0b0001f4e8.01\x1a\x1120240102 10:00:00\x22\x08DU123456*\x06ISLAND\
+    2\x03BOT:\x03100A\x00\x00\x00\x00\x00\xc8b@H\xc0\xc4\x07P\x01X\x00b\x03100i\x00\x00\x00\x00\x00\xc8b@r\x03ref\
+    z\x06aussie\x81\x01\x00\x00\x00\x00\x00\x00\xe0?\x8a\x01\x05model\x90\x01\x02\x98\x01\x01\xa2\x01\x06trader";
+
+//ExecutionDetails{contract: {conId: 265598}, execution: {orderId: 7, execId: "0001f4e8.02", time: "20240102 10:00:01",
+//acctNumber: "DU123456", exchange: "ISLAND", side: "SLD", shares: "50", price: 151.0}}
+const EXECUTION_WITHOUT_OPTIONALS: &[u8] = b"\x12\x04\x08\xfe\x9a\x10\x1aF\x08\x07\x12\x0b0001f4e8.02\
+    \x1a\x1120240102 10:00:01\x22\x08DU123456*\x06ISLAND2\x03SLD:\x0250A\x00\x00\x00\x00\x00\xe0b@";
+
+fn parse(msg_type: Incoming, body: &[u8]) -> IBFrame {
+    IBFrame::parse(&frames::protobuf(msg_type as i32, body), constants::MIN_SERVER_VER_PROTOBUF).expect("Frame not decodable")
+}
+
+async fn connect(gateway: &MockGateway) -> IBClient {
+    let options = ConnectOptions::new(gateway.port(), 1).request_timeout(Duration::from_secs(5));
+    IBClient::connect(options).await.expect("Connection not successful!")
+}
+
+#[tokio::test]
+async fn binary_msg_ids() {
+    let gateway = MockGateway::with_server_version(constants::MIN_SERVER_VER_PROTOBUF).await.unwrap();
+    let client = connect(&gateway).await;
+    assert_eq!(client.server_version(), constants::MIN_SERVER_VER_PROTOBUF);
+    let start_api = &gateway.received()[1];
+    assert_eq!(start_api[0], "71");
+    gateway.wait_for(Outgoing::ReqAcctData).await;
+    gateway.push(frames::account_value("NetLiquidation", "25000.50", "USD", "DU123456"));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(client.net_liquidation_value(), Some(rust_decimal::Decimal::new(2500050, 2)));
+}
+
+#[tokio::test]
+async fn protobuf_error_rejects_order() {
+    let gateway = MockGateway::with_server_version(constants::MIN_SERVER_VER_PROTOBUF).await.unwrap();
+    //the client hands out ids after the next valid id, so the order gets id 1
+    gateway.set_next_order_id(0);
+    gateway.on_raw(Outgoing::PlaceOrder, |_| vec![frames::protobuf(Incoming::ErrMsg as i32, ORDER_REJECTED)]);
+    let client = connect(&gateway).await;
+    let contract = Contract {
+        symbol: Some("AAPL".to_string()),
+        exchange: Some("SMART".to_string()),
+        sec_type: Some(SecType::Stock),
+        currency: Some("USD".to_string()),
+        ..Default::default()
+    };
+    let order = rs_ib_api::order::Order::market(contract, Action::Buy, rust_decimal::Decimal::new(10, 0));
+    match client.place_order(&order).await {
+        Err(IBError::Tws(err)) => assert_eq!(err.kind(), TwsErrorKind::OrderRejected),
+        _ => panic!("Expected a rejection")
+    }
+}
+
+#[test]
+fn order_status() {
+    match parse(Incoming::OrderStatus, ORDER_FILLED) {
+        IBFrame::OrderStatus(status) => {
+            assert_eq!(status.order_id, 7);
+            assert_eq!(status.status, "Filled");
+            assert_eq!(status.filled, Decimal::new(100, 0));
+            assert_eq!(status.remaining, Decimal::default());
+            assert_eq!(status.avg_fill_price, Decimal::new(15025, 2));
+            assert_eq!(status.perm_id, 123456);
+            assert_eq!(status.parent_id, 3);
+            assert_eq!(status.last_fill_price, Decimal::new(1505, 1));
+            assert_eq!(status.client_id, 1);
+            assert_eq!(status.why_held.as_deref(), Some("locate"));
+            assert_eq!(status.mkt_cap_price, Some(Decimal::new(14975, 2)));
+        },
+        _ => panic!("Unexpected frame")
+    }
+}
+
+#[test]
+fn order_status_without_optionals() {
+    match parse(Incoming::OrderStatus, ORDER_SUBMITTED) {
+        IBFrame::OrderStatus(status) => {
+            assert_eq!(status.order_id, 7);
+            assert_eq!(status.status, "Submitted");
+            assert_eq!(status.filled, Decimal::default());
+            assert_eq!(status.remaining, Decimal::new(100, 0));
+            assert_eq!(status.avg_fill_price, Decimal::default());
+            assert_eq!(status.perm_id, 0);
+            assert_eq!(status.parent_id, 0);
+            assert_eq!(status.last_fill_price, Decimal::default());
+            assert_eq!(status.client_id, 0);
+            assert_eq!(status.why_held, None);
+            assert_eq!(status.mkt_cap_price, None);
+        },
+        _ => panic!("Unexpected frame")
+    }
+}
+
+#[test]
+fn execution_details() {
+    match parse(Incoming::ExecutionData, EXECUTION) {
+        IBFrame::Execution(execution) => {
+            assert_eq!(execution.order_id, 7);
+            assert_eq!(execution.contract.con_id, Some(265598));
+            assert_eq!(execution.contract.symbol.as_deref(), Some("AAPL"));
+            assert!(matches!(execution.contract.sec_type, Some(SecType::Stock)));
+            assert_eq!(execution.contract.exchange.as_deref(), Some("SMART"));
+            assert_eq!(execution.contract.currency.as_deref(), Some("USD"));
+            assert_eq!(execution.exec_id, "0001f4e8.01");
+            assert_eq!(execution.time, "20240102 10:00:00");
+            assert_eq!(execution.acct_number, "DU123456");
+            assert_eq!(execution.exchange, "ISLAND");
+            assert!(matches!(execution.side, Side::Long));
+            assert_eq!(execution.shares, Decimal::new(100, 0));
+            assert_eq!(execution.price, Decimal::new(15025, 2));
+            assert_eq!(execution.perm_id, 123456);
+            assert_eq!(execution.client_id, 1);
+            assert_eq!(execution.liquidation, 0);
+            assert_eq!(execution.cum_qty, Decimal::new(100, 0));
+            assert_eq!(execution.avg_price, Decimal::new(15025, 2));
+            assert_eq!(execution.order_ref.as_deref(), Some("ref"));
+            assert_eq!(execution.ev_rule.as_deref(), Some("aussie"));
+            assert_eq!(execution.ev_multiplier, Some(Decimal::new(5, 1)));
+            assert_eq!(execution.model_code.as_deref(), Some("model"));
+            assert_eq!(execution.last_liquidity, Some(2));
+            assert_eq!(execution.pending_price_revision, Some(true));
+            assert_eq!(execution.submitter.as_deref(), Some("trader"));
+        },
+        _ => panic!("Unexpected frame")
+    }
+}
+
+#[test]
+fn execution_details_without_optionals() {
+    match parse(Incoming::ExecutionData, EXECUTION_WITHOUT_OPTIONALS) {
+        IBFrame::Execution(execution) => {
+            assert_eq!(execution.order_id, 7);
+            assert_eq!(execution.contract.con_id, Some(265598));
+            assert_eq!(execution.contract.symbol, None);
+            assert_eq!(execution.exec_id, "0001f4e8.02");
+            assert_eq!(execution.time, "20240102 10:00:01");
+            assert_eq!(execution.acct_number, "DU123456");
+            assert_eq!(execution.exchange, "ISLAND");
+            assert!(matches!(execution.side, Side::Short));
+            assert_eq!(execution.shares, Decimal::new(50, 0));
+            assert_eq!(execution.price, Decimal::new(151, 0));
+            assert_eq!(execution.perm_id, 0);
+            assert_eq!(execution.client_id, 0);
+            assert_eq!(execution.liquidation, 0);
+            assert_eq!(execution.cum_qty, Decimal::default());
+            assert_eq!(execution.avg_price, Decimal::default());
+            assert_eq!(execution.order_ref, None);
+            assert_eq!(execution.ev_rule, None);
+            assert_eq!(execution.ev_multiplier, None);
+            assert_eq!(execution.model_code, None);
+            assert_eq!(execution.last_liquidity, None);
+            assert_eq!(execution.pending_price_revision, None);
+            assert_eq!(execution.submitter, None);
+        },
+        _ => panic!("Unexpected frame")
+    }
+}
+
+#[test]
+fn execution_details_without_execution_is_an_error() {
+    let msg = frames::protobuf(Incoming::ExecutionData as i32, b"\x08\x01");
+    let err = IBFrame::parse(&msg, constants::MIN_SERVER_VER_PROTOBUF).err().expect("Frame decoded");
+    assert_eq!(err.position, Some(3));
+}
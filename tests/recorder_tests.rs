@@ -0,0 +1,52 @@
+use rs_ib_api::ib_client::{IBClient, ConnectOptions};
+use rs_ib_api::ib_contract::*;
+use rs_ib_api::ib_enums::*;
+use rs_ib_api::mock_gateway::{MockGateway, frames};
+use rs_ib_api::recorder::{self, Direction, Recorder, ReplaySpeed};
+use std::time::Duration;
+
+fn aapl() -> Contract {
+    Contract {
+        symbol: Some("AAPL".to_string()),
+        exchange: Some("SMART".to_string()),
+        sec_type: Some(SecType::Stock),
+        currency: Some("USD".to_string()),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn record_and_replay_market_data() {
+    let path = std::env::temp_dir().join(format!("rs_ib_api_recording_{}.log", std::process::id()));
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.on(Outgoing::ReqMktData, |msg| {
+        let id = msg[2].parse().unwrap();
+        vec![frames::tick_price(id, 1, 99.5, 100), frames::tick_price(id, 2, 100.5, 200)]
+    });
+    let recorder = Recorder::create(&path).unwrap();
+    //heartbeats recorded in between must not hold the replay back
+    let options = ConnectOptions::new(gateway.port(), 1)
+        .heartbeat(Duration::from_millis(20), 0)
+        .record_to(recorder.clone());
+    let client = IBClient::connect(options).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let ticker = client.req_market_data(&aapl(), false, false, None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(ticker.midpoint(), Some(100.0));
+    client.disconnect().await.unwrap();
+    drop(gateway);
+    recorder.flush().await.unwrap();
+
+    let recording = recorder::read_recording(&path).unwrap();
+    assert_eq!(recording[0].direction, Direction::Out);
+    assert_eq!(recording[0].payload, b"API\0");
+    assert!(recording.iter().any(|frame| frame.direction == Direction::In && frame.payload.starts_with(b"1\0")));
+
+    let options = ConnectOptions::new(0, 1).request_timeout(Duration::from_secs(5));
+    let client = recorder::replay(&path, options, ReplaySpeed::AsFastAsPossible).await.unwrap();
+    let ticker = client.req_market_data(&aapl(), false, false, None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(ticker.bid(), Some(99.5));
+    assert_eq!(ticker.ask(), Some(100.5));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,293 @@
+use rs_ib_api::bars::{Bar, BarSeries};
+use rs_ib_api::frame::{IBFrame, TickAttribute};
+use rs_ib_api::ib_client::{IBClient, ConnectOptions};
+use rs_ib_api::ib_contract::*;
+use rs_ib_api::ib_enums::*;
+use rs_ib_api::mock_gateway::MockGateway;
+use rs_ib_api::order::{Order, OrderState};
+use rs_ib_api::request::IBRequest;
+use enumset::EnumSet;
+use rust_decimal::Decimal;
+use std::time::Duration;
+
+const OLD_SERVER_VERSION: i32 = 144;
+const SERVER_VERSIONS: [i32; 3] = [OLD_SERVER_VERSION, constants::MIN_SERVER_VER_SIZE_RULES, constants::MIN_SERVER_VER_PROTOBUF];
+
+fn aapl() -> Contract {
+    Contract {
+        con_id: Some(265598),
+        symbol: Some("AAPL".to_string()),
+        exchange: Some("SMART".to_string()),
+        primary_exchange: Some("NASDAQ".to_string()),
+        sec_type: Some(SecType::Stock),
+        currency: Some("USD".to_string()),
+        ..Default::default()
+    }
+}
+
+//decoding the encoded request has to give a request that encodes to the same bytes
+fn round_trip_request(request: &IBRequest, server_version: i32) -> IBRequest {
+    let msg = request.encode(server_version);
+    let decoded = IBRequest::decode(&msg[4..], server_version).expect("Request not decodable");
+    assert_eq!(decoded.encode(server_version), msg, "Request changed at server version {}", server_version);
+    decoded
+}
+
+fn round_trip_frame(frame: &IBFrame, server_version: i32) -> IBFrame {
+    let msg = frame.encode(server_version).expect("Frame not encodable");
+    let decoded = IBFrame::parse(&msg[4..], server_version).expect("Frame not decodable");
+    assert_eq!(decoded.encode(server_version).unwrap(), msg, "Frame changed at server version {}", server_version);
+    decoded
+}
+
+fn limit_order() -> Order {
+    Order {
+        contract: aapl(),
+        action: Action::Buy,
+        total_qty: Decimal::new(100, 0),
+        order_type: OrderType::Limit,
+        lmt_price: Some(Decimal::new(15025, 2)),
+        tif: Some(TimeInForce::GoodTillCancel),
+        order_ref: Some("ref".to_string()),
+        transmit: true,
+        outside_rth: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn place_order() {
+    for server_version in SERVER_VERSIONS {
+        let request = IBRequest::PlaceOrder{order_id: 7, order: Box::new(limit_order())};
+        match round_trip_request(&request, server_version) {
+            IBRequest::PlaceOrder{order_id, order} => {
+                assert_eq!(order_id, 7);
+                assert_eq!(order.contract.symbol.as_deref(), Some("AAPL"));
+                assert_eq!(order.lmt_price, Some(Decimal::new(15025, 2)));
+                assert!(matches!(order.tif, Some(TimeInForce::GoodTillCancel)));
+                assert!(order.outside_rth);
+            },
+            other => panic!("Unexpected request {:?}", other)
+        }
+    }
+}
+
+#[test]
+fn market_data_with_generic_ticks() {
+    for server_version in SERVER_VERSIONS {
+        let request = IBRequest::ReqMarketData {
+            req_id: 3,
+            contract: Box::new(aapl()),
+            generic_ticks: vec![GenericTickType::ShortableData, GenericTickType::OptionImpliedVol],
+            snapshot: false,
+            regulatory_snapshot: false
+        };
+        match round_trip_request(&request, server_version) {
+            IBRequest::ReqMarketData{req_id, contract, generic_ticks, ..} => {
+                assert_eq!(req_id, 3);
+                assert_eq!(contract.con_id, Some(265598));
+                assert_eq!(generic_ticks.len(), 2);
+            },
+            other => panic!("Unexpected request {:?}", other)
+        }
+    }
+}
+
+#[test]
+fn historical_data() {
+    for server_version in SERVER_VERSIONS {
+        for what_to_show in [Some(HistoricalDataType::Midpoint), None] {
+            let request = IBRequest::ReqHistoricalData {
+                req_id: 4,
+                contract: Box::new(aapl()),
+                end_date_time: what_to_show.as_ref().map(|_| "20240102 16:00:00 US/Eastern".to_string()),
+                bar_size: HistoricalDataBarSize::OneMin,
+                duration: HistoricalDataDuration::Days(2),
+                use_rth: true,
+                what_to_show: what_to_show.clone()
+            };
+            match round_trip_request(&request, server_version) {
+                IBRequest::ReqHistoricalData{bar_size, duration, what_to_show: decoded, ..} => {
+                    assert_eq!(bar_size, HistoricalDataBarSize::OneMin);
+                    assert_eq!(duration, HistoricalDataDuration::Days(2));
+                    assert_eq!(decoded, what_to_show);
+                },
+                other => panic!("Unexpected request {:?}", other)
+            }
+        }
+    }
+}
+
+#[test]
+fn contract_details_request() {
+    for server_version in SERVER_VERSIONS {
+        let request = IBRequest::ReqContractDetails{req_id: 5, contract: Box::new(aapl())};
+        match round_trip_request(&request, server_version) {
+            IBRequest::ReqContractDetails{req_id, contract} => {
+                assert_eq!(req_id, 5);
+                assert_eq!(contract.primary_exchange.as_deref(), Some("NASDAQ"));
+            },
+            other => panic!("Unexpected request {:?}", other)
+        }
+    }
+}
+
+#[test]
+fn unknown_requests_are_an_error() {
+    assert!(IBRequest::decode(b"99\x001\0", OLD_SERVER_VERSION).is_err());
+}
+
+#[test]
+fn open_order() {
+    for server_version in SERVER_VERSIONS {
+        let mut order = limit_order();
+        order.order_id = 7;
+        order.perm_id = 123456;
+        let order_state = OrderState {
+            status: Some("Submitted".to_string()),
+            ..Default::default()
+        };
+        let frame = IBFrame::OpenOrder{order: Box::new(order), order_state: Box::new(order_state)};
+        match round_trip_frame(&frame, server_version) {
+            IBFrame::OpenOrder{order, order_state} => {
+                assert_eq!(order.order_id, 7);
+                assert_eq!(order.perm_id, 123456);
+                assert_eq!(order.lmt_price, Some(Decimal::new(15025, 2)));
+                assert_eq!(order_state.status.as_deref(), Some("Submitted"));
+            },
+            _ => panic!("Unexpected frame")
+        }
+    }
+}
+
+#[test]
+fn open_order_with_overflowing_allocations_is_an_error() {
+    let server_version = constants::MIN_SERVER_VER_PROTOBUF;
+    let order_state = OrderState {
+        reject_reason: Some("rejected".to_string()),
+        ..Default::default()
+    };
+    let frame = IBFrame::OpenOrder{order: Box::new(limit_order()), order_state: Box::new(order_state)};
+    let msg = frame.encode(server_version).unwrap()[4..].to_vec();
+    let allocations = b"rejected\x000\0";
+    let pos = msg.windows(allocations.len()).position(|window| window == allocations).unwrap();
+    let mut malformed = msg[..pos + 9].to_vec();
+    malformed.extend_from_slice(format!("{}\0", usize::MAX).as_bytes());
+    malformed.extend_from_slice(&msg[pos + allocations.len()..]);
+    assert!(IBFrame::parse(&malformed, server_version).is_err());
+}
+
+#[test]
+fn contract_details() {
+    for server_version in SERVER_VERSIONS {
+        let details = ContractDetails {
+            contract: Some(aapl()),
+            market_name: Some("NMS".to_string()),
+            min_tick: Some(Decimal::new(1, 2)),
+            long_name: Some("APPLE INC".to_string()),
+            sec_id_list: Some(vec![("ISIN".to_string(), "US0378331005".to_string())]),
+            ..Default::default()
+        };
+        let frame = IBFrame::ContractDetails{req_id: 5, contract_details: Box::new(details)};
+        match round_trip_frame(&frame, server_version) {
+            IBFrame::ContractDetails{req_id, contract_details} => {
+                assert_eq!(req_id, 5);
+                assert_eq!(contract_details.long_name.as_deref(), Some("APPLE INC"));
+                assert_eq!(contract_details.sec_id_list.map(|ids| ids.len()), Some(1));
+                assert_eq!(contract_details.contract.and_then(|contract| contract.con_id), Some(265598));
+            },
+            _ => panic!("Unexpected frame")
+        }
+    }
+}
+
+#[test]
+fn ticks() {
+    for server_version in SERVER_VERSIONS {
+        let price = IBFrame::PriceTick {
+            id: 3, kind: TickType::Bid, price: 150.25, size: Some(Decimal::new(200, 0)), attributes: EnumSet::only(TickAttribute::CanAutoExecute)
+        };
+        match round_trip_frame(&price, server_version) {
+            IBFrame::PriceTick{price, attributes, ..} => {
+                assert_eq!(price, 150.25);
+                assert!(attributes.contains(TickAttribute::CanAutoExecute));
+            },
+            _ => panic!("Unexpected frame")
+        }
+        round_trip_frame(&IBFrame::SizeTick{id: 3, kind: TickType::BidSize, size: Decimal::new(300, 0)}, server_version);
+        round_trip_frame(&IBFrame::StringTick{id: 3, kind: TickType::LastTimestamp, val: Some("1704207600".to_string())}, server_version);
+    }
+}
+
+#[test]
+fn bars() {
+    for server_version in SERVER_VERSIONS {
+        let bar = Bar {
+            t_stamp: "20240102 09:30:00".to_string(),
+            open: 150.0,
+            high: 151.5,
+            low: 149.75,
+            close: 151.0,
+            wap: 150.5,
+            volume: Decimal::new(12000, 0),
+            count: 250
+        };
+        let data = BarSeries {
+            //only sent with the bars before MIN_SERVER_VER_HISTORICAL_DATA_END
+            start_dt: if server_version < constants::MIN_SERVER_VER_HISTORICAL_DATA_END {"20240102 09:30:00".to_string()} else {String::new()},
+            end_dt: if server_version < constants::MIN_SERVER_VER_HISTORICAL_DATA_END {"20240102 09:31:00".to_string()} else {String::new()},
+            n_bars: 1,
+            data: Some(vec![bar])
+        };
+        match round_trip_frame(&IBFrame::Bars{id: 4, data}, server_version) {
+            IBFrame::Bars{id, data} => {
+                assert_eq!(id, 4);
+                let bars = data.data.unwrap();
+                assert_eq!(bars.len(), 1);
+                assert_eq!(bars[0].count, 250);
+                assert_eq!(bars[0].close, 151.0);
+            },
+            _ => panic!("Unexpected frame")
+        }
+    }
+}
+
+#[test]
+fn error() {
+    for server_version in SERVER_VERSIONS {
+        let frame = IBFrame::Error{id: 5, code: 200, msg: "No security definition has been found for the request".to_string()};
+        match round_trip_frame(&frame, server_version) {
+            IBFrame::Error{id, code, msg} => {
+                assert_eq!((id, code), (5, 200));
+                assert_eq!(msg, "No security definition has been found for the request");
+            },
+            _ => panic!("Unexpected frame")
+        }
+    }
+}
+
+#[tokio::test]
+async fn gateway_answers_decoded_requests() {
+    let gateway = MockGateway::start().await.unwrap();
+    gateway.on_request(Outgoing::ReqContractData, |request| {
+        let (req_id, contract) = match request {
+            IBRequest::ReqContractDetails{req_id, contract} => (*req_id, contract.as_ref().clone()),
+            _ => return Vec::new()
+        };
+        let details = ContractDetails {
+            contract: Some(contract),
+            long_name: Some("APPLE INC".to_string()),
+            ..Default::default()
+        };
+        vec![
+            IBFrame::ContractDetails{req_id, contract_details: Box::new(details)},
+            IBFrame::ContractDetailsEnd(req_id)
+        ]
+    });
+    let options = ConnectOptions::new(gateway.port(), 1).request_timeout(Duration::from_secs(5));
+    let client = IBClient::connect(options).await.expect("Connection not successful!");
+    let details = client.req_contract_details(&aapl()).await.unwrap();
+    assert_eq!(details.len(), 1);
+    assert_eq!(details[0].long_name.as_deref(), Some("APPLE INC"));
+    assert_eq!(details[0].contract.as_ref().and_then(|contract| contract.con_id), Some(265598));
+}
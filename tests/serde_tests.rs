@@ -0,0 +1,89 @@
+use rs_ib_api::bars::{Bar, BarSeries};
+use rs_ib_api::ib_contract::*;
+use rs_ib_api::ib_enums::*;
+use rs_ib_api::order::Order;
+use rust_decimal::Decimal;
+
+fn aapl() -> Contract {
+    Contract {
+        con_id: Some(265598),
+        symbol: Some("AAPL".to_string()),
+        exchange: Some("SMART".to_string()),
+        sec_type: Some(SecType::Stock),
+        currency: Some("USD".to_string()),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn enums_as_wire_codes() {
+    assert_eq!(serde_json::to_string(&SecType::Stock).unwrap(), "\"STK\"");
+    assert_eq!(serde_json::to_string(&OrderType::RelativeLimit).unwrap(), "\"REL + LMT\"");
+    assert_eq!(serde_json::to_string(&HistoricalDataDuration::Days(2)).unwrap(), "\"2 D\"");
+    assert_eq!(serde_json::to_string(&GenericTickType::ShortableData).unwrap(), "\"236\"");
+    assert!(matches!(serde_json::from_str("\"OPT\"").unwrap(), SecType::Option));
+    assert!(matches!(serde_json::from_str("\"REL + LMT\"").unwrap(), OrderType::RelativeLimit));
+    assert_eq!(serde_json::from_str::<HistoricalDataBarSize>("\"1 min\"").unwrap(), HistoricalDataBarSize::OneMin);
+    assert!(serde_json::from_str::<SecType>("\"STOCK\"").is_err());
+}
+
+#[test]
+fn order_round_trip() {
+    let order = Order {
+        contract: aapl(),
+        action: Action::Buy,
+        total_qty: Decimal::new(100, 0),
+        order_type: OrderType::Limit,
+        lmt_price: Some(Decimal::new(15025, 2)),
+        tif: Some(TimeInForce::GoodTillCancel),
+        algo_params: Some(vec![("maxPctVol".to_string(), "0.1".to_string())]),
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&order).unwrap();
+    assert!(json.contains("\"action\":\"BUY\""));
+    assert!(json.contains("\"lmt_price\":\"150.25\""));
+    let reloaded: Order = serde_json::from_str(&json).unwrap();
+    assert_eq!(reloaded.lmt_price, Some(Decimal::new(15025, 2)));
+    assert_eq!(reloaded.contract.con_id, Some(265598));
+    assert_eq!(serde_json::to_string(&reloaded).unwrap(), json);
+}
+
+#[test]
+fn contract_from_toml() {
+    //fields left out take their defaults
+    let contract: Contract = toml::from_str(r#"
+        symbol = "ES"
+        sec_type = "FUT"
+        exchange = "CME"
+        currency = "USD"
+        last_trade_date_or_contract_month = "202412"
+    "#).unwrap();
+    assert!(matches!(contract.sec_type, Some(SecType::Future)));
+    assert_eq!(contract.symbol.as_deref(), Some("ES"));
+    assert_eq!(contract.con_id, None);
+    let reloaded: Contract = toml::from_str(&toml::to_string(&contract).unwrap()).unwrap();
+    assert_eq!(reloaded.last_trade_date_or_contract_month.as_deref(), Some("202412"));
+}
+
+#[test]
+fn bars_round_trip() {
+    let bars = BarSeries {
+        start_dt: "20240102 09:30:00".to_string(),
+        end_dt: "20240102 09:31:00".to_string(),
+        n_bars: 1,
+        data: Some(vec![Bar {
+            t_stamp: "20240102 09:30:00".to_string(),
+            open: 150.0,
+            high: 151.5,
+            low: 149.75,
+            close: 151.0,
+            wap: 150.5,
+            volume: Decimal::new(12000, 0),
+            count: 250
+        }])
+    };
+    let reloaded: BarSeries = serde_json::from_str(&serde_json::to_string(&bars).unwrap()).unwrap();
+    let bar = &reloaded.data.unwrap()[0];
+    assert_eq!(bar.close, 151.0);
+    assert_eq!(bar.volume, Decimal::new(12000, 0));
+}
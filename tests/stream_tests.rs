@@ -0,0 +1,88 @@
+use rs_ib_api::ib_client::{IBClient, ConnectOptions, ConnectionState};
+use rs_ib_api::ib_enums::constants;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+fn frame(fields: &[&str]) -> Vec<u8> {
+    let mut msg = fields.join("\0");
+    msg.push('\0');
+    let mut frame = (msg.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(msg.as_bytes());
+    frame
+}
+
+async fn read_msg(stream: &mut DuplexStream) -> Vec<String> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).await.unwrap();
+    let mut msg = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut msg).await.unwrap();
+    String::from_utf8(msg).unwrap().split('\0').map(str::to_string).collect()
+}
+
+//reads the client side of the handshake up to the StartApi message
+async fn accept_handshake(server: &mut DuplexStream) -> Vec<String> {
+    let mut prefix = [0u8; 4];
+    server.read_exact(&mut prefix).await.unwrap();
+    assert_eq!(&prefix, b"API\0");
+    let versions = read_msg(server).await;
+    assert_eq!(versions[0], format!("100..{}", constants::MAX_CLIENT_VER));
+    server.write_all(&frame(&["151", "20210101 00:00:00 UTC"])).await.unwrap();
+    read_msg(server).await
+}
+
+fn options(client_id: i32) -> ConnectOptions {
+    ConnectOptions::new(4002, client_id)
+        .subscribe_account_updates(false)
+        .request_next_order_id(false)
+}
+
+#[tokio::test]
+async fn connect_over_duplex() {
+    let (client_end, mut server) = tokio::io::duplex(4096);
+    let gateway = tokio::spawn(async move {
+        let start_api = accept_handshake(&mut server).await;
+        assert_eq!(start_api[0], "71");
+        assert_eq!(start_api[2], "7");
+        server.write_all(&frame(&["9", "1", "42"])).await.unwrap();
+        server
+    });
+    let client = IBClient::connect_with_stream(client_end, options(7)).await.unwrap();
+    assert_eq!(client.client_id(), 7);
+    gateway.await.unwrap();
+}
+
+#[tokio::test]
+async fn frames_split_and_coalesced() {
+    let (client_end, mut server) = tokio::io::duplex(4096);
+    let gateway = tokio::spawn(async move {
+        accept_handshake(&mut server).await;
+        //a system message and the next valid id in one write, delivered a few bytes at a time
+        let mut bytes = frame(&["4", "2", "-1", "2104", "Market data farm connection is OK:usfarm"]);
+        bytes.extend(frame(&["9", "1", "42"]));
+        for chunk in bytes.chunks(3) {
+            server.write_all(chunk).await.unwrap();
+            server.flush().await.unwrap();
+            tokio::task::yield_now().await;
+        }
+        server
+    });
+    let client = IBClient::connect_with_stream(client_end, options(1)).await;
+    assert!(client.is_ok());
+    gateway.await.unwrap();
+}
+
+#[tokio::test]
+async fn oversized_frame_closes_connection() {
+    let (client_end, mut server) = tokio::io::duplex(4096);
+    let gateway = tokio::spawn(async move {
+        accept_handshake(&mut server).await;
+        server.write_all(&frame(&["9", "1", "42"])).await.unwrap();
+        server
+    });
+    let client = IBClient::connect_with_stream(client_end, options(1)).await.unwrap();
+    let mut server = gateway.await.unwrap();
+    let mut state = client.connection_state();
+    server.write_all(&u32::MAX.to_be_bytes()).await.unwrap();
+    server.write_all(&[0u8; 64]).await.unwrap();
+    tokio::time::timeout(std::time::Duration::from_secs(5), state.changed()).await.unwrap().unwrap();
+    assert_eq!(*state.borrow(), ConnectionState::Disconnected);
+}